@@ -0,0 +1,167 @@
+//! Latency of the event reads of `PostgresStorage` on a large events table.
+//!
+//! The benchmark fills the database with events, so it only runs against the
+//! one given in `KORMIR_BENCH_DATABASE_URL`:
+//!
+//! ```sh
+//! KORMIR_BENCH_DATABASE_URL=postgres://localhost/kormir_bench cargo bench -p kormir-server
+//! ```
+//!
+//! `KORMIR_BENCH_EVENTS` sets the number of events, half of them attested.
+//! Events from a previous run are reused.
+
+// kormir-server is a binary, so the models are compiled into the benchmark
+#[allow(dead_code)]
+#[path = "../src/models/mod.rs"]
+mod models;
+
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel::PgConnection;
+use diesel_migrations::MigrationHarness;
+use kormir::error::Error;
+use kormir::storage::Storage;
+use kormir::Oracle;
+use models::{EventFilter, EventStatus, PostgresStorage, MIGRATIONS};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: usize = 20;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let Ok(pg_url) = std::env::var("KORMIR_BENCH_DATABASE_URL") else {
+        println!("KORMIR_BENCH_DATABASE_URL is not set, skipping");
+        return Ok(());
+    };
+    let num_events: usize = std::env::var("KORMIR_BENCH_EVENTS")
+        .ok()
+        .map(|n| n.parse())
+        .transpose()?
+        .unwrap_or(20_000);
+
+    let manager = ConnectionManager::<PgConnection>::new(&pg_url);
+    let db_pool = Pool::builder().max_size(4).build(manager)?;
+    db_pool
+        .get()?
+        .run_pending_migrations(MIGRATIONS)
+        .map_err(|e| anyhow::anyhow!("migrations could not run: {e}"))?;
+
+    let signing_key = SecretKey::from_slice(&[1; 32])?;
+    let oracle_public_key = signing_key.x_only_public_key(&Secp256k1::new()).0;
+    let storage = PostgresStorage::new(db_pool, oracle_public_key)?;
+    let oracle = Oracle::from_signing_key(storage.clone(), signing_key)?;
+    seed(&oracle, num_events).await?;
+
+    let filter = EventFilter {
+        status: None,
+        maturity_from: None,
+        maturity_until: None,
+        is_enum: None,
+        event_id_prefix: None,
+        descending: false,
+        after: None,
+        limit: 100,
+    };
+    let middle = storage
+        .get_event(event_id(num_events / 2))
+        .await?
+        .expect("seeded event");
+    let middle_cursor = (
+        middle.announcement.oracle_event.event_maturity_epoch,
+        middle.event_id,
+    );
+
+    println!("{num_events} events");
+    bench("list_events", || storage.list_events()).await;
+    bench("first page of 100", || {
+        storage.list_events_filtered(&filter)
+    })
+    .await;
+    let after_middle = EventFilter {
+        after: Some(middle_cursor.clone()),
+        ..filter.clone()
+    };
+    bench("page of 100 in the middle", || {
+        storage.list_events_filtered(&after_middle)
+    })
+    .await;
+    let attested_desc = EventFilter {
+        status: Some(EventStatus::Attested),
+        descending: true,
+        ..filter.clone()
+    };
+    bench("last page of 100 attested", || {
+        storage.list_events_filtered(&attested_desc)
+    })
+    .await;
+    let prefix = EventFilter {
+        event_id_prefix: Some("bench-0001".to_string()),
+        ..filter.clone()
+    };
+    bench("page of 100 by prefix", || {
+        storage.list_events_filtered(&prefix)
+    })
+    .await;
+    bench("get_event", || storage.get_event(event_id(0))).await;
+
+    let cached = storage.clone().with_cache(num_events);
+    // warm up the cache
+    cached.list_events().await?;
+    bench("list_events, cached", || cached.list_events()).await;
+    bench("get_event, cached", || cached.get_event(event_id(0))).await;
+
+    Ok(())
+}
+
+fn event_id(index: usize) -> String {
+    format!("bench-{index:06}")
+}
+
+/// Creates the missing events, attesting every other one.
+async fn seed(oracle: &Oracle<PostgresStorage>, num_events: usize) -> anyhow::Result<()> {
+    let existing = oracle.storage.list_events().await?.len();
+    if existing < num_events {
+        println!("creating {} events", num_events - existing);
+    }
+
+    let start = Instant::now();
+    for index in existing..num_events {
+        let outcomes = vec!["a".to_string(), "b".to_string()];
+        let maturity = 1_700_000_000 + (index as u32 % 1_000) * 3_600;
+        oracle
+            .create_enum_event(event_id(index), outcomes, maturity)
+            .await?;
+        if index % 2 == 0 {
+            oracle
+                .sign_enum_event(event_id(index), "a".to_string())
+                .await?;
+        }
+    }
+    if existing < num_events {
+        println!("created in {:?}", start.elapsed());
+    }
+    Ok(())
+}
+
+async fn bench<F, Fut, T>(name: &str, f: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut times = Vec::with_capacity(ITERATIONS);
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        f().await.expect("read failed");
+        times.push(start.elapsed());
+    }
+    times.sort();
+
+    let total: Duration = times.iter().sum();
+    println!(
+        "{name:<28} mean {:>10.3?}  median {:>10.3?}  max {:>10.3?}",
+        total / ITERATIONS as u32,
+        times[ITERATIONS / 2],
+        times[ITERATIONS - 1],
+    );
+}
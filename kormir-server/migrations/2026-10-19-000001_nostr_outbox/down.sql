@@ -0,0 +1 @@
+drop table nostr_outbox;
@@ -0,0 +1,13 @@
+-- Nostr events that have not been accepted by any relay yet,
+-- the background publisher retries them until they are published
+CREATE TABLE nostr_outbox
+(
+    nostr_event_id bytea     NOT NULL PRIMARY KEY,
+    event_id       TEXT      NOT NULL REFERENCES events (event_id),
+    nostr_event    TEXT      NOT NULL,
+    attempts       INTEGER   NOT NULL DEFAULT 0,
+    next_attempt   timestamp NOT NULL DEFAULT NOW(),
+    last_error     TEXT,
+    created_at     timestamp NOT NULL DEFAULT NOW(),
+    updated_at     timestamp NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,3 @@
+-- Withdrawn announcements are kept for reference but can never be signed
+ALTER TABLE events
+ADD COLUMN withdrawn BOOLEAN NOT NULL DEFAULT FALSE;
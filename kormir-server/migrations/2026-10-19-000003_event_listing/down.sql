@@ -0,0 +1,9 @@
+DROP INDEX event_nonces_event_id_idx;
+DROP INDEX events_event_id_prefix_idx;
+DROP INDEX events_maturity_idx;
+
+ALTER TABLE events
+DROP COLUMN attested;
+
+ALTER TABLE events
+DROP COLUMN maturity;
@@ -0,0 +1,29 @@
+-- Columns to filter and sort the event list on without decoding oracle_event
+
+-- The maturity is read from the encoded oracle event: a u16 count of 32 byte
+-- nonces followed by the big endian u32 maturity epoch
+ALTER TABLE events
+ADD COLUMN maturity BIGINT;
+
+UPDATE events
+SET maturity = (SELECT (get_byte(oracle_event, o)::BIGINT << 24)
+                           | (get_byte(oracle_event, o + 1)::BIGINT << 16)
+                           | (get_byte(oracle_event, o + 2)::BIGINT << 8)
+                           | get_byte(oracle_event, o + 3)::BIGINT
+                FROM (SELECT 2 + 32 * ((get_byte(oracle_event, 0) << 8) | get_byte(oracle_event, 1)) AS o) AS offsets);
+
+ALTER TABLE events
+ALTER COLUMN maturity SET NOT NULL;
+
+ALTER TABLE events
+ADD COLUMN attested BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE events
+SET attested = EXISTS (SELECT 1
+                       FROM event_nonces
+                       WHERE event_nonces.event_id = events.event_id
+                         AND event_nonces.signature IS NOT NULL);
+
+CREATE INDEX events_maturity_idx ON events (maturity, event_id);
+CREATE INDEX events_event_id_prefix_idx ON events (event_id text_pattern_ops);
+CREATE INDEX event_nonces_event_id_idx ON event_nonces (event_id);
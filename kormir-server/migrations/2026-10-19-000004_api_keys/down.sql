@@ -0,0 +1,6 @@
+ALTER TABLE events
+    DROP COLUMN created_by,
+    DROP COLUMN attested_by,
+    DROP COLUMN withdrawn_by;
+
+DROP TABLE api_keys;
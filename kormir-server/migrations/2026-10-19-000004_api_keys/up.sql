@@ -0,0 +1,20 @@
+-- Named credentials for the authenticated routes, each limited to a set of
+-- scopes (create, sign, admin) and optionally to event ids starting with a namespace
+CREATE TABLE api_keys
+(
+    key_id          TEXT      NOT NULL PRIMARY KEY,
+    secret          TEXT      NOT NULL,
+    scopes          TEXT[]    NOT NULL,
+    event_namespace TEXT,
+    revoked_at      timestamp,
+    created_at      timestamp NOT NULL DEFAULT NOW(),
+    updated_at      timestamp NOT NULL DEFAULT NOW()
+);
+
+SELECT diesel_manage_updated_at('api_keys');
+
+-- The credential that performed each action on an event
+ALTER TABLE events
+    ADD COLUMN created_by   TEXT,
+    ADD COLUMN attested_by  TEXT,
+    ADD COLUMN withdrawn_by TEXT;
@@ -0,0 +1,11 @@
+-- Nonces of the authenticated requests, kept until their timestamp is too old
+-- to be accepted so a request can not be replayed
+CREATE TABLE request_nonces
+(
+    key_id     TEXT      NOT NULL,
+    nonce      TEXT      NOT NULL,
+    expires_at timestamp NOT NULL,
+    PRIMARY KEY (key_id, nonce)
+);
+
+CREATE INDEX request_nonces_expires_at_idx ON request_nonces (expires_at);
@@ -0,0 +1,10 @@
+-- Outcomes approved by each credential while attestations require several
+-- approvals, an event is signed once enough credentials approve the same outcome
+CREATE TABLE attestation_approvals
+(
+    event_id   TEXT      NOT NULL REFERENCES events (event_id),
+    key_id     TEXT      NOT NULL,
+    outcome    TEXT      NOT NULL,
+    created_at timestamp NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (event_id, key_id, outcome)
+);
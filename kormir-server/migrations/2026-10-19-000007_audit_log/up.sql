@@ -0,0 +1,32 @@
+-- Append-only log of the actions performed on the oracle, each entry commits
+-- to the previous one through prev_hash, see kormir::audit for the hash
+CREATE TABLE audit_log
+(
+    sequence    BIGINT PRIMARY KEY,
+    action      TEXT   NOT NULL,
+    event_id    TEXT   NOT NULL,
+    outcome     TEXT,
+    key_id      TEXT,
+    source      TEXT,
+    recorded_at BIGINT NOT NULL,
+    result      TEXT   NOT NULL,
+    prev_hash   BYTEA  NOT NULL,
+    hash        BYTEA  NOT NULL UNIQUE
+);
+
+CREATE INDEX audit_log_event_id_idx ON audit_log (event_id);
+
+CREATE FUNCTION audit_log_append_only() RETURNS trigger AS
+$$
+BEGIN
+    RAISE EXCEPTION 'audit_log is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_no_update
+    BEFORE UPDATE OR DELETE ON audit_log
+    FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+
+CREATE TRIGGER audit_log_no_truncate
+    BEFORE TRUNCATE ON audit_log
+    FOR EACH STATEMENT EXECUTE FUNCTION audit_log_append_only();
@@ -0,0 +1,22 @@
+-- Notifications POSTed to the configured webhooks, the background sender
+-- retries them until the webhook accepts them or they run out of attempts
+CREATE TABLE webhook_deliveries
+(
+    id           BIGSERIAL PRIMARY KEY,
+    webhook_url  TEXT      NOT NULL,
+    event_id     TEXT      NOT NULL REFERENCES events (event_id),
+    kind         TEXT      NOT NULL,
+    payload      TEXT      NOT NULL,
+    attempts     INTEGER   NOT NULL DEFAULT 0,
+    next_attempt timestamp NOT NULL DEFAULT NOW(),
+    last_error   TEXT,
+    delivered_at timestamp,
+    created_at   timestamp NOT NULL DEFAULT NOW(),
+    updated_at   timestamp NOT NULL DEFAULT NOW(),
+    -- each webhook is notified once of each change of an event
+    UNIQUE (webhook_url, event_id, kind)
+);
+
+CREATE INDEX webhook_deliveries_pending_idx ON webhook_deliveries (next_attempt) WHERE delivered_at IS NULL;
+
+SELECT diesel_manage_updated_at('webhook_deliveries');
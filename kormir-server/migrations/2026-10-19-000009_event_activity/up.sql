@@ -0,0 +1,19 @@
+-- Announcements and attestations in the order they happened, streamed to
+-- clients that resume from the id of the last one they received
+CREATE TABLE event_activity
+(
+    id         BIGSERIAL PRIMARY KEY,
+    event_id   TEXT      NOT NULL REFERENCES events (event_id),
+    kind       TEXT      NOT NULL,
+    created_at timestamp NOT NULL DEFAULT NOW()
+);
+
+INSERT INTO event_activity (event_id, kind, created_at)
+SELECT event_id, kind, created_at
+FROM (SELECT event_id, 'announcement' AS kind, created_at
+      FROM events
+      UNION ALL
+      SELECT event_id, 'attestation' AS kind, updated_at AS created_at
+      FROM events
+      WHERE attested) activity
+ORDER BY created_at, kind;
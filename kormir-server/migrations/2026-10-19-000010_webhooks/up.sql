@@ -0,0 +1,7 @@
+-- Webhooks the server was configured with, and since when. Only the events
+-- maturing after a webhook was added are notified to it as matured
+CREATE TABLE webhooks
+(
+    url        TEXT PRIMARY KEY,
+    created_at timestamp NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,208 @@
+//! Multi-operator approval of attestations.
+//!
+//! When `KORMIR_APPROVALS_REQUIRED` is above 1, the sign routes record the
+//! caller's approval of the outcome instead of signing. The event is only
+//! signed once that many distinct credentials approved the same outcome.
+//! An event whose approvers disagree is flagged as conflicting and is never
+//! signed, it can still be withdrawn.
+
+use crate::auth::Caller;
+use crate::error::ApiError;
+use crate::models::approval::Approval;
+use crate::AppState;
+use axum::http::StatusCode;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// The approvals of an event
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ApprovalStatus {
+    pub event_id: String,
+    /// Number of approvals an outcome needs to be signed
+    pub required: usize,
+    pub outcomes: Vec<OutcomeApprovals>,
+    /// Whether different outcomes were approved
+    pub conflict: bool,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OutcomeApprovals {
+    pub outcome: String,
+    /// Credentials that approved the outcome, in order
+    pub approved_by: Vec<String>,
+}
+
+impl ApprovalStatus {
+    pub fn new(event_id: String, required: usize, approvals: Vec<Approval>) -> Self {
+        let mut outcomes: Vec<OutcomeApprovals> = vec![];
+        for approval in approvals {
+            match outcomes.iter_mut().find(|o| o.outcome == approval.outcome) {
+                // approving the same outcome twice counts once
+                Some(outcome) if outcome.approved_by.contains(&approval.key_id) => {}
+                Some(outcome) => outcome.approved_by.push(approval.key_id),
+                None => outcomes.push(OutcomeApprovals {
+                    outcome: approval.outcome,
+                    approved_by: vec![approval.key_id],
+                }),
+            }
+        }
+
+        Self {
+            event_id,
+            required,
+            conflict: outcomes.len() > 1,
+            outcomes,
+        }
+    }
+
+    /// Whether the outcome can be signed with these approvals.
+    pub fn decide(self, outcome: &str) -> Result<Decision, ApiError> {
+        if self.conflict {
+            let outcomes = self
+                .outcomes
+                .iter()
+                .map(|o| o.outcome.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                "conflicting_approvals",
+                format!(
+                    "Event {} has conflicting approvals for {outcomes}",
+                    self.event_id
+                ),
+            )
+            .with_event_id(self.event_id));
+        }
+
+        if self.approvals(outcome) >= self.required {
+            Ok(Decision::Sign)
+        } else {
+            Ok(Decision::Pending(self))
+        }
+    }
+
+    fn approvals(&self, outcome: &str) -> usize {
+        self.outcomes
+            .iter()
+            .find(|o| o.outcome == outcome)
+            .map_or(0, |o| o.approved_by.len())
+    }
+}
+
+/// What to do with a sign request
+#[derive(Debug)]
+pub enum Decision {
+    /// The outcome has enough approvals, the event can be signed
+    Sign,
+    /// The outcome needs more approvals
+    Pending(ApprovalStatus),
+}
+
+/// Records the caller's approval of an already validated outcome.
+pub async fn approve(
+    state: &AppState,
+    caller: &Caller,
+    event_id: &str,
+    outcome: &str,
+) -> Result<Decision, ApiError> {
+    let Some(key_id) = &caller.key_id else {
+        return Err(ApiError::internal("approvals require authentication"));
+    };
+
+    let approvals = state
+        .oracle
+        .storage
+        .add_approval(event_id, key_id, outcome)
+        .await?;
+    let status = ApprovalStatus::new(event_id.to_string(), state.approvals_required, approvals);
+    let decision = status.decide(outcome);
+    match &decision {
+        Err(e) => log::warn!("{}", e.body.message),
+        Ok(Decision::Pending(status)) => log::info!(
+            "{key_id} approved {outcome} for event {event_id}, {} of {} approvals",
+            status.approvals(outcome),
+            status.required
+        ),
+        Ok(Decision::Sign) => {}
+    }
+    decision
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::DateTime;
+
+    const EVENT_ID: &str = "btc-price";
+
+    fn approval(key_id: &str, outcome: &str) -> Approval {
+        Approval {
+            event_id: EVENT_ID.to_string(),
+            key_id: key_id.to_string(),
+            outcome: outcome.to_string(),
+            created_at: DateTime::from_timestamp(1_760_000_000, 0)
+                .unwrap()
+                .naive_utc(),
+        }
+    }
+
+    fn decide(required: usize, approvals: &[(&str, &str)], outcome: &str) -> Decision {
+        let approvals = approvals.iter().map(|(k, o)| approval(k, o)).collect();
+        ApprovalStatus::new(EVENT_ID.to_string(), required, approvals)
+            .decide(outcome)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_pending_below_threshold() {
+        let decision = decide(2, &[("alice", "up")], "up");
+        let Decision::Pending(status) = decision else {
+            panic!("expected pending, got {decision:?}");
+        };
+        assert_eq!(status.required, 2);
+        assert!(!status.conflict);
+        assert_eq!(status.outcomes.len(), 1);
+        assert_eq!(status.outcomes[0].outcome, "up");
+        assert_eq!(status.outcomes[0].approved_by, vec!["alice"]);
+    }
+
+    #[test]
+    fn test_signs_at_threshold() {
+        let decision = decide(2, &[("alice", "up"), ("bob", "up")], "up");
+        assert!(matches!(decision, Decision::Sign));
+
+        let decision = decide(2, &[("alice", "up"), ("bob", "up"), ("carol", "up")], "up");
+        assert!(matches!(decision, Decision::Sign));
+    }
+
+    #[test]
+    fn test_same_key_approving_twice_is_idempotent() {
+        let decision = decide(2, &[("alice", "up"), ("alice", "up")], "up");
+        let Decision::Pending(status) = decision else {
+            panic!("expected pending, got {decision:?}");
+        };
+        assert_eq!(status.outcomes[0].approved_by, vec!["alice"]);
+        assert_eq!(status.approvals("up"), 1);
+    }
+
+    #[test]
+    fn test_conflicting_outcomes() {
+        for approvals in [
+            vec![("alice", "up"), ("bob", "down")],
+            // even from the same credential
+            vec![("alice", "up"), ("alice", "down")],
+            // and once an outcome reached the threshold
+            vec![("alice", "up"), ("bob", "up"), ("carol", "down")],
+        ] {
+            let approvals = approvals.iter().map(|(k, o)| approval(k, o)).collect();
+            let status = ApprovalStatus::new(EVENT_ID.to_string(), 2, approvals);
+            assert!(status.conflict);
+
+            let err = status.decide("up").unwrap_err();
+            assert_eq!(err.status, StatusCode::CONFLICT);
+            assert_eq!(err.body.code, "conflicting_approvals");
+            assert_eq!(err.body.event_id.as_deref(), Some(EVENT_ID));
+        }
+    }
+}
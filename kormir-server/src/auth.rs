@@ -0,0 +1,794 @@
+//! Authentication of the create, sign and admin routes.
+//!
+//! Every request is signed with an HMAC-SHA256 over the request, keyed with
+//! the secret of the API key named in the `X-Key-Id` header, or with the
+//! shared `KORMIR_HMAC_SECRET` when there is no key id. API keys are looked
+//! up on every request, so they can be created, rotated and revoked without
+//! a restart.
+//!
+//! A signed request carries these headers:
+//!
+//! - `X-Key-Id`: the API key, omitted to use the shared secret
+//! - `X-Timestamp`: the current unix time in seconds
+//! - `X-Nonce`: 16 to 128 random characters from `[A-Za-z0-9_-]`, never
+//!   reused with the same key
+//! - `X-Signature`: the hex encoded HMAC of the message below
+//!
+//! The signed message is the timestamp, the nonce, the upper case method and
+//! the path with its query string, each followed by a newline, then the raw
+//! body:
+//!
+//! ```text
+//! 1760000000\n
+//! 4f1c2e5a9b7d4e0f8a6c\n
+//! POST\n
+//! /sign-enum\n
+//! {"event_id":"btc-price","outcome":"up"}
+//! ```
+//!
+//! Requests whose timestamp is more than [`MAX_CLOCK_SKEW`] (5 minutes) away
+//! from the server's clock are rejected, and nonces are remembered until
+//! then, so a captured request can not be replayed.
+//!
+//! Operators listed in `KORMIR_NOSTR_OPERATORS` can instead authenticate
+//! with a NIP-98 `Authorization: Nostr <base64 event>` header. The event must
+//! be signed for the url and method of the request, with the hash of the body
+//! in its `payload` tag, and is only accepted once.
+
+use crate::error::ApiError;
+use crate::metrics::Metrics;
+use crate::models::api_key::ApiKey;
+use crate::models::PostgresStorage;
+use axum::body::Body;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use bitcoin::hashes::{sha256, Hash};
+use chrono::DateTime;
+use hmac::{Hmac, Mac};
+use http_body_util::BodyExt;
+use nostr::nips::nip98::HttpData;
+use nostr::{Event, JsonUtil, Kind, PublicKey, Url};
+use rand::RngCore;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Key id recorded for the actions authenticated with `KORMIR_HMAC_SECRET`
+pub const SHARED_KEY_ID: &str = "shared";
+
+/// Prefix of the key ids recorded for nostr operators, reserved so an API key
+/// cannot act as an operator
+pub const NOSTR_KEY_ID_PREFIX: &str = "nostr:";
+
+/// Key id recorded for the actions of a nostr pubkey
+pub fn nostr_key_id(pubkey: &PublicKey) -> String {
+    format!("{NOSTR_KEY_ID_PREFIX}{}", pubkey.to_hex())
+}
+
+/// How far the timestamp of a request can be from the server's clock
+pub const MAX_CLOCK_SKEW: Duration = Duration::from_secs(300);
+
+/// How far the creation time of a NIP-98 event can be from the server's clock
+const NIP98_MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// What an API key is allowed to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Create and withdraw events
+    Create,
+    /// Attest events
+    Sign,
+    /// Manage API keys and the nostr outbox
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Create => "create",
+            Scope::Sign => "sign",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl Display for Scope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(Scope::Create),
+            "sign" => Ok(Scope::Sign),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(anyhow::anyhow!("invalid scope: {s}")),
+        }
+    }
+}
+
+/// The authenticated caller of a request, added to the request extensions
+#[derive(Debug, Clone)]
+pub struct Caller {
+    /// The credential used, `None` when authentication is disabled
+    pub key_id: Option<String>,
+    scopes: Vec<Scope>,
+    event_namespace: Option<String>,
+    /// Where the request came from, recorded in the audit log
+    pub source: Option<String>,
+}
+
+impl Caller {
+    /// A caller allowed to do everything
+    pub fn unrestricted(key_id: Option<String>) -> Self {
+        Self {
+            key_id,
+            scopes: vec![Scope::Create, Scope::Sign, Scope::Admin],
+            event_namespace: None,
+            source: None,
+        }
+    }
+
+    pub fn new(key_id: String, scopes: Vec<Scope>, event_namespace: Option<String>) -> Self {
+        Self {
+            key_id: Some(key_id),
+            scopes,
+            event_namespace,
+            source: None,
+        }
+    }
+
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    fn from_api_key(key: &ApiKey) -> Self {
+        // scopes are validated when the key is created
+        let scopes = key.scopes.iter().flat_map(|s| s.parse()).collect();
+        Self::new(key.key_id.clone(), scopes, key.event_namespace.clone())
+    }
+
+    /// Checks the caller has the scope, and that the event is in its namespace.
+    pub fn authorize(&self, scope: Scope, event_id: Option<&str>) -> Result<(), ApiError> {
+        if !self.scopes.contains(&scope) {
+            return Err(ApiError::forbidden(format!(
+                "Credential does not have the {scope} scope"
+            )));
+        }
+        match (&self.event_namespace, event_id) {
+            (Some(namespace), Some(event_id)) if !event_id.starts_with(namespace.as_str()) => {
+                Err(ApiError::forbidden(format!(
+                    "Credential is limited to events starting with {namespace}"
+                ))
+                .with_event_id(event_id))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Parses a comma separated list of scopes.
+pub fn parse_scopes(s: &str) -> anyhow::Result<Vec<Scope>> {
+    s.split(',').map(|s| s.trim().parse()).collect()
+}
+
+/// A new random API key secret.
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[derive(Clone)]
+pub struct AuthState {
+    pub storage: PostgresStorage,
+    /// Secret for the requests without a key id
+    pub shared_secret: Option<Vec<u8>>,
+    /// What the shared secret is allowed to do
+    pub shared_scopes: Vec<Scope>,
+    /// Nostr pubkeys allowed to authenticate with NIP-98
+    pub nostr_operators: HashSet<PublicKey>,
+    /// Public URL of the server, that NIP-98 events must be signed for
+    pub base_url: Option<Url>,
+    /// Whether authentication is enabled at all
+    pub enabled: bool,
+    pub metrics: Metrics,
+}
+
+/// Verifies the request signature and adds the [`Caller`] to the request.
+#[allow(clippy::result_large_err)]
+pub async fn authenticate(
+    State(auth): State<AuthState>,
+    mut req: Request,
+    next: Next,
+) -> Result<impl IntoResponse, Response> {
+    let source = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.to_string());
+    let with_source = |caller: Caller| match &source {
+        Some(source) => caller.with_source(source),
+        None => caller,
+    };
+
+    if !auth.enabled {
+        req.extensions_mut()
+            .insert(with_source(Caller::unrestricted(None)));
+        return Ok(next.run(req).await);
+    }
+
+    let (mut parts, body_parts) = req.into_parts();
+    let bytes = body_parts
+        .collect()
+        .await
+        .map_err(|_| internal_server_error("invalid request body"))?
+        .to_bytes();
+
+    let nip98 = header_str(&parts, "Authorization")
+        .and_then(|h| h.strip_prefix("Nostr "))
+        .filter(|_| !auth.nostr_operators.is_empty());
+    let caller = match nip98 {
+        Some(token) => verify_nip98(&auth, &parts, &bytes, token)
+            .await
+            .inspect_err(|_| auth.metrics.auth_failed("nip98"))?,
+        None => verify_hmac(&auth, &parts, &bytes)
+            .await
+            .inspect_err(|_| auth.metrics.auth_failed("hmac"))?,
+    };
+
+    parts.extensions.insert(with_source(caller));
+    let req = Request::from_parts(parts, Body::from(bytes));
+    Ok(next.run(req).await)
+}
+
+#[allow(clippy::result_large_err)]
+async fn verify_hmac(auth: &AuthState, parts: &Parts, body: &[u8]) -> Result<Caller, Response> {
+    if header_str(parts, "X-Signature").is_none() {
+        return Err(unauthorized("missing signature"));
+    }
+
+    let (secret, caller) = match header_str(parts, "X-Key-Id") {
+        Some(key_id) => {
+            let key = auth
+                .storage
+                .get_api_key(key_id)
+                .await
+                .map_err(|e| ApiError::from(e).into_response())?
+                .filter(|key| !key.is_revoked())
+                .ok_or_else(|| unauthorized("unknown api key"))?;
+            (key.secret().to_vec(), Caller::from_api_key(&key))
+        }
+        None => match &auth.shared_secret {
+            Some(secret) => (
+                secret.clone(),
+                Caller::new(SHARED_KEY_ID.to_string(), auth.shared_scopes.clone(), None),
+            ),
+            None => return Err(unauthorized("missing key id")),
+        },
+    };
+
+    let (timestamp, nonce) = check_hmac(&secret, parts, body, now()).map_err(unauthorized)?;
+
+    // only checked once the signature is valid, so nonces can not be burnt
+    let key_id = caller.key_id.as_deref().unwrap_or(SHARED_KEY_ID);
+    use_nonce(auth, key_id, nonce, timestamp + MAX_CLOCK_SKEW.as_secs()).await?;
+
+    Ok(caller)
+}
+
+/// Checks the timestamp, nonce and signature of a request signed with the
+/// secret, returning the timestamp and nonce to remember.
+fn check_hmac<'a>(
+    secret: &[u8],
+    parts: &'a Parts,
+    body: &[u8],
+    now: u64,
+) -> Result<(u64, &'a str), &'static str> {
+    let sig = header_str(parts, "X-Signature").ok_or("missing signature")?;
+    let sig = hex::decode(sig).map_err(|_| "invalid signature")?;
+    let timestamp = header_str(parts, "X-Timestamp")
+        .and_then(|t| t.parse::<u64>().ok())
+        .ok_or("missing or invalid timestamp")?;
+    let nonce = header_str(parts, "X-Nonce")
+        .filter(|n| valid_nonce(n))
+        .ok_or("missing or invalid nonce")?;
+    if now.abs_diff(timestamp) > MAX_CLOCK_SKEW.as_secs() {
+        return Err("stale timestamp");
+    }
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).map_err(|_| "invalid secret")?;
+    mac.update(&signed_message(timestamp, nonce, parts, body));
+    mac.verify_slice(&sig).map_err(|_| "wrong signature")?;
+    Ok((timestamp, nonce))
+}
+
+/// The message signed with the HMAC of a request
+fn signed_message(timestamp: u64, nonce: &str, parts: &Parts, body: &[u8]) -> Vec<u8> {
+    let mut message =
+        format!("{timestamp}\n{nonce}\n{}\n{}\n", parts.method, path(parts)).into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+/// Verifies a NIP-98 `Authorization: Nostr <base64 event>` header, operators
+/// can create and sign events.
+#[allow(clippy::result_large_err)]
+async fn verify_nip98(
+    auth: &AuthState,
+    parts: &Parts,
+    body: &[u8],
+    token: &str,
+) -> Result<Caller, Response> {
+    let event = base64::decode(token)
+        .ok()
+        .and_then(|json| Event::from_json(json).ok())
+        .filter(|event| event.verify().is_ok())
+        .ok_or_else(|| unauthorized("invalid nostr event"))?;
+    if !auth.nostr_operators.contains(&event.pubkey) {
+        return Err(unauthorized("unknown nostr operator"));
+    }
+    check_nip98(&event, parts, body, auth.base_url.as_ref(), now()).map_err(unauthorized)?;
+
+    let key_id = nostr_key_id(&event.pubkey);
+    use_nonce(
+        auth,
+        &key_id,
+        &event.id.to_hex(),
+        event.created_at.as_u64() + NIP98_MAX_CLOCK_SKEW.as_secs(),
+    )
+    .await?;
+
+    Ok(Caller::new(key_id, vec![Scope::Create, Scope::Sign], None))
+}
+
+/// Checks a NIP-98 event was made for this request, recently.
+fn check_nip98(
+    event: &Event,
+    parts: &Parts,
+    body: &[u8],
+    base_url: Option<&Url>,
+    now: u64,
+) -> Result<(), &'static str> {
+    if event.kind != Kind::HttpAuth {
+        return Err("invalid nostr event kind");
+    }
+    if now.abs_diff(event.created_at.as_u64()) > NIP98_MAX_CLOCK_SKEW.as_secs() {
+        return Err("stale nostr event");
+    }
+
+    let data =
+        HttpData::try_from(event.tags.clone().to_vec()).map_err(|_| "invalid nostr event tags")?;
+    if data.method.to_string() != parts.method.as_str() {
+        return Err("wrong method");
+    }
+    if !url_matches(&data.url, parts, base_url) {
+        return Err("wrong url");
+    }
+    // the payload hash is optional for requests without a body
+    match data.payload {
+        Some(payload) if payload != sha256::Hash::hash(body) => Err("wrong payload hash"),
+        None if !body.is_empty() => Err("missing payload hash"),
+        _ => Ok(()),
+    }
+}
+
+/// Compares the url a NIP-98 event was signed for with the request. Without
+/// a configured public url the host is taken from the `Host` header.
+fn url_matches(url: &Url, parts: &Parts, base_url: Option<&Url>) -> bool {
+    match base_url {
+        Some(base) => {
+            let expected = format!("{}{}", base.as_str().trim_end_matches('/'), path(parts));
+            Url::parse(&expected).is_ok_and(|expected| &expected == url)
+        }
+        None => {
+            let host = match url.port() {
+                Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+                None => url.host_str().unwrap_or_default().to_string(),
+            };
+            let path_and_query = match url.query() {
+                Some(query) => format!("{}?{query}", url.path()),
+                None => url.path().to_string(),
+            };
+            header_str(parts, "Host") == Some(host.as_str()) && path_and_query == path(parts)
+        }
+    }
+}
+
+/// Records the nonce of a request until it expires, rejecting replays.
+#[allow(clippy::result_large_err)]
+async fn use_nonce(
+    auth: &AuthState,
+    key_id: &str,
+    nonce: &str,
+    expires_at: u64,
+) -> Result<(), Response> {
+    let expires_at = DateTime::from_timestamp(expires_at as i64, 0)
+        .ok_or_else(|| unauthorized("invalid timestamp"))?
+        .naive_utc();
+    let fresh = auth
+        .storage
+        .use_request_nonce(key_id, nonce, expires_at)
+        .await
+        .map_err(|e| ApiError::from(e).into_response())?;
+    if !fresh {
+        return Err(unauthorized("nonce already used"));
+    }
+    Ok(())
+}
+
+fn path(parts: &Parts) -> &str {
+    parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn header_str<'a>(parts: &'a Parts, name: &str) -> Option<&'a str> {
+    parts
+        .headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+fn valid_nonce(nonce: &str) -> bool {
+    (16..=128).contains(&nonce.len())
+        && nonce
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn internal_server_error(msg: &'static str) -> Response {
+    ApiError::internal(msg).into_response()
+}
+
+fn unauthorized(msg: &'static str) -> Response {
+    ApiError::unauthorized(msg).into_response()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::Method;
+    use nostr::nips::nip98::HttpMethod;
+    use nostr::{EventBuilder, Keys, Timestamp};
+
+    const SECRET: &[u8] = b"secret";
+    const NONCE: &str = "4f1c2e5a9b7d4e0f8a6c";
+    const NOW: u64 = 1_760_000_000;
+    const BODY: &[u8] = br#"{"event_id":"btc-price","outcome":"up"}"#;
+
+    fn request(method: &str, uri: &str, headers: &[(&str, &str)]) -> Parts {
+        let mut builder = Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    /// Signs the documented message, independently of [`signed_message`]
+    fn sign(timestamp: u64, nonce: &str, method: &str, path: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET).unwrap();
+        mac.update(format!("{timestamp}\n{nonce}\n{method}\n{path}\n").as_bytes());
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    fn signed_request(method: &str, uri: &str, timestamp: u64, nonce: &str, sig: &str) -> Parts {
+        request(
+            method,
+            uri,
+            &[
+                ("X-Timestamp", &timestamp.to_string()),
+                ("X-Nonce", nonce),
+                ("X-Signature", sig),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_signed_message_layout() {
+        let parts = request("POST", "/sign-enum?dry_run=true", &[]);
+        let message = String::from_utf8(signed_message(NOW, NONCE, &parts, BODY)).unwrap();
+        let expected = format!(
+            "1760000000\n{NONCE}\nPOST\n/sign-enum?dry_run=true\n{}",
+            std::str::from_utf8(BODY).unwrap()
+        );
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn test_hmac_accepts_signed_request() {
+        let sig = sign(NOW, NONCE, "POST", "/sign-enum", BODY);
+        let parts = signed_request("POST", "/sign-enum", NOW, NONCE, &sig);
+        assert_eq!(check_hmac(SECRET, &parts, BODY, NOW), Ok((NOW, NONCE)));
+
+        // within the allowed clock skew
+        let skew = MAX_CLOCK_SKEW.as_secs();
+        assert!(check_hmac(SECRET, &parts, BODY, NOW + skew).is_ok());
+        assert!(check_hmac(SECRET, &parts, BODY, NOW - skew).is_ok());
+
+        assert_eq!(
+            check_hmac(b"other secret", &parts, BODY, NOW),
+            Err("wrong signature")
+        );
+        assert_eq!(
+            check_hmac(SECRET, &parts, b"{}", NOW),
+            Err("wrong signature")
+        );
+    }
+
+    #[test]
+    fn test_hmac_rejects_stale_and_future_timestamps() {
+        let skew = MAX_CLOCK_SKEW.as_secs();
+        for timestamp in [NOW - skew - 1, NOW + skew + 1] {
+            let sig = sign(timestamp, NONCE, "POST", "/sign-enum", BODY);
+            let parts = signed_request("POST", "/sign-enum", timestamp, NONCE, &sig);
+            assert_eq!(
+                check_hmac(SECRET, &parts, BODY, NOW),
+                Err("stale timestamp")
+            );
+        }
+
+        let sig = sign(NOW, NONCE, "POST", "/sign-enum", BODY);
+        let parts = request(
+            "POST",
+            "/sign-enum",
+            &[
+                ("X-Timestamp", "yesterday"),
+                ("X-Nonce", NONCE),
+                ("X-Signature", &sig),
+            ],
+        );
+        assert_eq!(
+            check_hmac(SECRET, &parts, BODY, NOW),
+            Err("missing or invalid timestamp")
+        );
+    }
+
+    #[test]
+    fn test_hmac_rejects_malformed_nonce() {
+        let too_long = "a".repeat(129);
+        for nonce in [
+            "",
+            "too-short",
+            "contains spaces 0123",
+            "ünïcode-0123456789",
+            &too_long,
+        ] {
+            let sig = sign(NOW, nonce, "POST", "/sign-enum", BODY);
+            let parts = signed_request("POST", "/sign-enum", NOW, nonce, &sig);
+            assert_eq!(
+                check_hmac(SECRET, &parts, BODY, NOW),
+                Err("missing or invalid nonce"),
+                "{nonce}"
+            );
+        }
+
+        assert!(valid_nonce(&"a".repeat(16)));
+        assert!(valid_nonce(&"a".repeat(128)));
+        assert!(valid_nonce("Az09-_Az09-_Az09-_"));
+    }
+
+    #[test]
+    fn test_hmac_rejects_method_and_path_mismatch() {
+        let sig = sign(NOW, NONCE, "POST", "/sign-enum", BODY);
+        for (method, uri) in [
+            ("PUT", "/sign-enum"),
+            ("POST", "/create-enum"),
+            ("POST", "/sign-enum?dry_run=true"),
+        ] {
+            let parts = signed_request(method, uri, NOW, NONCE, &sig);
+            assert_eq!(
+                check_hmac(SECRET, &parts, BODY, NOW),
+                Err("wrong signature"),
+                "{method} {uri}"
+            );
+        }
+    }
+
+    fn nip98_event(kind: Kind, url: &str, method: HttpMethod, body: Option<&[u8]>) -> Event {
+        let mut data = HttpData::new(Url::parse(url).unwrap(), method);
+        if let Some(body) = body {
+            data = data.payload(sha256::Hash::hash(body));
+        }
+        let tags: Vec<nostr::Tag> = data.into();
+        EventBuilder::new(kind, "")
+            .tags(tags)
+            .custom_created_at(Timestamp::from(NOW))
+            .sign_with_keys(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_nip98_accepts_event_for_request() {
+        let base_url = Url::parse("https://oracle.example.com").unwrap();
+        let event = nip98_event(
+            Kind::HttpAuth,
+            "https://oracle.example.com/sign-enum",
+            HttpMethod::POST,
+            Some(BODY),
+        );
+        let parts = request("POST", "/sign-enum", &[]);
+        assert_eq!(
+            check_nip98(&event, &parts, BODY, Some(&base_url), NOW),
+            Ok(())
+        );
+
+        // without a public url the host comes from the request
+        let parts = request("POST", "/sign-enum", &[("Host", "oracle.example.com")]);
+        assert_eq!(check_nip98(&event, &parts, BODY, None, NOW), Ok(()));
+
+        let body = br#"{"event_id":"btc-price"}"#;
+        let event = nip98_event(
+            Kind::HttpAuth,
+            "https://oracle.example.com/withdraw",
+            HttpMethod::POST,
+            Some(body),
+        );
+        let parts = request("POST", "/withdraw", &[("Content-Type", "application/json")]);
+        assert_eq!(
+            check_nip98(&event, &parts, body, Some(&base_url), NOW),
+            Ok(())
+        );
+        // the payload hash is of that request's body
+        assert_eq!(
+            check_nip98(&event, &parts, BODY, Some(&base_url), NOW),
+            Err("wrong payload hash")
+        );
+
+        // requests without a body don't need a payload hash
+        let event = nip98_event(
+            Kind::HttpAuth,
+            "https://oracle.example.com/outbox/flush",
+            HttpMethod::POST,
+            None,
+        );
+        let parts = request("POST", "/outbox/flush", &[]);
+        assert_eq!(
+            check_nip98(&event, &parts, &[], Some(&base_url), NOW),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_nip98_rejects_wrong_kind() {
+        let event = nip98_event(
+            Kind::TextNote,
+            "https://oracle.example.com/sign-enum",
+            HttpMethod::POST,
+            Some(BODY),
+        );
+        let parts = request("POST", "/sign-enum", &[("Host", "oracle.example.com")]);
+        assert_eq!(
+            check_nip98(&event, &parts, BODY, None, NOW),
+            Err("invalid nostr event kind")
+        );
+    }
+
+    #[test]
+    fn test_nip98_rejects_stale_event() {
+        let event = nip98_event(
+            Kind::HttpAuth,
+            "https://oracle.example.com/sign-enum",
+            HttpMethod::POST,
+            Some(BODY),
+        );
+        let parts = request("POST", "/sign-enum", &[("Host", "oracle.example.com")]);
+        let skew = NIP98_MAX_CLOCK_SKEW.as_secs();
+        for now in [NOW + skew + 1, NOW - skew - 1] {
+            assert_eq!(
+                check_nip98(&event, &parts, BODY, None, now),
+                Err("stale nostr event")
+            );
+        }
+    }
+
+    #[test]
+    fn test_nip98_rejects_method_mismatch() {
+        let event = nip98_event(
+            Kind::HttpAuth,
+            "https://oracle.example.com/sign-enum",
+            HttpMethod::PUT,
+            Some(BODY),
+        );
+        let parts = request("POST", "/sign-enum", &[("Host", "oracle.example.com")]);
+        assert_eq!(
+            check_nip98(&event, &parts, BODY, None, NOW),
+            Err("wrong method")
+        );
+    }
+
+    #[test]
+    fn test_nip98_rejects_url_mismatch() {
+        let base_url = Url::parse("https://oracle.example.com").unwrap();
+        let event = nip98_event(
+            Kind::HttpAuth,
+            "https://oracle.example.com/sign-enum",
+            HttpMethod::POST,
+            Some(BODY),
+        );
+
+        let parts = request("POST", "/create-enum", &[("Host", "oracle.example.com")]);
+        assert_eq!(
+            check_nip98(&event, &parts, BODY, None, NOW),
+            Err("wrong url")
+        );
+        let parts = request("POST", "/sign-enum", &[("Host", "evil.example.com")]);
+        assert_eq!(
+            check_nip98(&event, &parts, BODY, None, NOW),
+            Err("wrong url")
+        );
+        let other = Url::parse("https://other.example.com").unwrap();
+        let parts = request("POST", "/sign-enum", &[("Host", "oracle.example.com")]);
+        assert_eq!(
+            check_nip98(&event, &parts, BODY, Some(&other), NOW),
+            Err("wrong url")
+        );
+        let parts = request("POST", "/sign-enum?dry_run=true", &[]);
+        assert_eq!(
+            check_nip98(&event, &parts, BODY, Some(&base_url), NOW),
+            Err("wrong url")
+        );
+    }
+
+    #[test]
+    fn test_nip98_rejects_payload_mismatch() {
+        let event = nip98_event(
+            Kind::HttpAuth,
+            "https://oracle.example.com/sign-enum",
+            HttpMethod::POST,
+            Some(b"{}"),
+        );
+        let parts = request("POST", "/sign-enum", &[("Host", "oracle.example.com")]);
+        assert_eq!(
+            check_nip98(&event, &parts, BODY, None, NOW),
+            Err("wrong payload hash")
+        );
+
+        let event = nip98_event(
+            Kind::HttpAuth,
+            "https://oracle.example.com/sign-enum",
+            HttpMethod::POST,
+            None,
+        );
+        assert_eq!(
+            check_nip98(&event, &parts, BODY, None, NOW),
+            Err("missing payload hash")
+        );
+    }
+
+    #[test]
+    fn test_url_matches_with_port_and_query() {
+        let url = Url::parse("http://localhost:8080/list-events?limit=10").unwrap();
+        let parts = request(
+            "GET",
+            "/list-events?limit=10",
+            &[("Host", "localhost:8080")],
+        );
+        assert!(url_matches(&url, &parts, None));
+        let parts = request("GET", "/list-events?limit=10", &[("Host", "localhost")]);
+        assert!(!url_matches(&url, &parts, None));
+
+        let base_url = Url::parse("http://localhost:8080/").unwrap();
+        let parts = request(Method::GET.as_str(), "/list-events?limit=10", &[]);
+        assert!(url_matches(&url, &parts, Some(&base_url)));
+    }
+}
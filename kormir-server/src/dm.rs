@@ -0,0 +1,152 @@
+//! Event requests over nostr.
+//!
+//! Allow-listed pubkeys can ask the oracle to create events by sending it a
+//! NIP-17 private message (NIP-44 encrypted and NIP-59 gift wrapped). The
+//! message is a JSON request tagged with its `type`:
+//!
+//! ```json
+//! {"type": "create-enum", "event_id": "...", "outcomes": ["a", "b"], "event_maturity_epoch": 1700000000}
+//! {"type": "create-numeric", "event_id": "...", "unit": "usd", "event_maturity_epoch": 1700000000}
+//! ```
+//!
+//! The fields are the same as the `/create-enum` and `/create-numeric` bodies and
+//! go through the same handlers. The oracle replies with a private message
+//! containing either the `announcement` or an `error`, in the same format as
+//! the HTTP error responses.
+
+use crate::auth::{nostr_key_id, Caller, Scope};
+use crate::error::{ApiError, ErrorResponse};
+use crate::json_models::{CreateEnumEventRequest, CreateNumericEventRequest};
+use crate::routes::{create_enum_event, create_numeric_event};
+use crate::AppState;
+use axum::{Extension, Json};
+use dlc_messages::oracle_msgs::OracleAnnouncement;
+use nostr::{Event, Filter, Kind, PublicKey};
+use nostr_sdk::{Client, RelayPoolNotification};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum DmRequest {
+    CreateEnum(CreateEnumEventRequest),
+    CreateNumeric(CreateNumericEventRequest),
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DmResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    announcement: Option<OracleAnnouncement>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorResponse>,
+}
+
+/// Parses the space separated list of pubkeys allowed to send requests.
+pub fn parse_allowlist(value: &str) -> anyhow::Result<HashSet<PublicKey>> {
+    value
+        .split_whitespace()
+        .map(|key| Ok(PublicKey::parse(key)?))
+        .collect()
+}
+
+/// Subscribes to private messages addressed to the oracle and answers the
+/// requests of allow-listed senders.
+pub async fn spawn_dm_intake(
+    state: AppState,
+    client: Client,
+    allowlist: HashSet<PublicKey>,
+) -> anyhow::Result<()> {
+    let pubkey = state.oracle.nostr_keys().public_key;
+
+    // gift wraps are backdated, so only ask for the ones sent from now on
+    let filter = Filter::new().kind(Kind::GiftWrap).pubkey(pubkey).limit(0);
+    client.subscribe(filter, None).await?;
+
+    log::info!(
+        "Listening for event requests from {} nostr pubkeys",
+        allowlist.len()
+    );
+
+    let mut notifications = client.notifications();
+    tokio::spawn(async move {
+        loop {
+            match notifications.recv().await {
+                Ok(RelayPoolNotification::Event { event, .. }) if event.kind == Kind::GiftWrap => {
+                    handle_gift_wrap(&state, &client, &allowlist, &event).await;
+                }
+                Ok(RelayPoolNotification::Shutdown) => break,
+                Ok(_) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    log::warn!("Missed {n} nostr notifications");
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn handle_gift_wrap(
+    state: &AppState,
+    client: &Client,
+    allowlist: &HashSet<PublicKey>,
+    event: &Event,
+) {
+    let gift = match client.unwrap_gift_wrap(event).await {
+        Ok(gift) => gift,
+        Err(e) => {
+            log::debug!("Could not unwrap gift wrap {}: {e}", event.id);
+            return;
+        }
+    };
+
+    // the rumor author must be the one that sealed it
+    if gift.rumor.kind != Kind::PrivateDirectMessage || gift.rumor.pubkey != gift.sender {
+        return;
+    }
+    if !allowlist.contains(&gift.sender) {
+        log::warn!("Ignoring event request from {}", gift.sender);
+        return;
+    }
+
+    let response = match serde_json::from_str::<DmRequest>(&gift.rumor.content) {
+        Ok(request) => {
+            // DM senders may only create events
+            let caller = Caller::new(nostr_key_id(&gift.sender), vec![Scope::Create], None)
+                .with_source("nostr");
+            handle_request(state, caller, request).await
+        }
+        Err(e) => DmResponse {
+            announcement: None,
+            error: Some(ApiError::bad_request(format!("Invalid request: {e}")).body),
+        },
+    };
+
+    let reply = serde_json::to_string(&response).expect("serializable response");
+    if let Err(e) = client.send_private_msg(gift.sender, reply, []).await {
+        log::error!("Failed to reply to {}: {e}", gift.sender);
+    }
+}
+
+async fn handle_request(state: &AppState, caller: Caller, request: DmRequest) -> DmResponse {
+    let result: Result<Json<OracleAnnouncement>, ApiError> = match request {
+        DmRequest::CreateEnum(body) => {
+            create_enum_event(Extension(state.clone()), Extension(caller), Json(body)).await
+        }
+        DmRequest::CreateNumeric(body) => {
+            create_numeric_event(Extension(state.clone()), Extension(caller), Json(body)).await
+        }
+    };
+
+    match result {
+        Ok(Json(announcement)) => DmResponse {
+            announcement: Some(announcement),
+            error: None,
+        },
+        Err(e) => DmResponse {
+            announcement: None,
+            error: Some(e.body),
+        },
+    }
+}
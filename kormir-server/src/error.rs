@@ -0,0 +1,128 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use kormir::error::Error;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// JSON body of every error response
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    /// Stable identifier of the kind of error
+    #[schema(value_type = String)]
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub event_id: Option<String>,
+}
+
+/// Error returned by the routes, rendered as an [`ErrorResponse`]
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub body: ErrorResponse,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            body: ErrorResponse {
+                code,
+                message: message.into(),
+                event_id: None,
+            },
+        }
+    }
+
+    pub fn with_event_id(mut self, event_id: impl Into<String>) -> Self {
+        self.body.event_id = Some(event_id.into());
+        self
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    pub fn not_acceptable(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_ACCEPTABLE, "not_acceptable", message)
+    }
+
+    pub fn invalid_argument(message: impl Into<String>) -> Self {
+        Self::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_argument",
+            message,
+        )
+    }
+
+    pub fn not_found(event_id: impl Into<String>) -> Self {
+        Error::NotFound {
+            event_id: event_id.into(),
+        }
+        .into()
+    }
+
+    /// The event exists but has not been signed yet, 425 Too Early
+    pub fn not_attested(event_id: impl Into<String>) -> Self {
+        let event_id = event_id.into();
+        Self::new(
+            StatusCode::from_u16(425).expect("valid status code"),
+            "not_attested",
+            format!("Event {event_id} has not been attested yet"),
+        )
+        .with_event_id(event_id)
+    }
+
+    pub fn nostr_disabled() -> Self {
+        Self::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "nostr_disabled",
+            "Nostr is disabled",
+        )
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "forbidden", message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal", message)
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        let status = match e {
+            Error::NotFound { .. } => StatusCode::NOT_FOUND,
+            Error::EventAlreadyExists { .. }
+            | Error::EventAlreadySigned { .. }
+            | Error::EventWithdrawn { .. } => StatusCode::CONFLICT,
+            Error::InvalidArgument { .. } | Error::InvalidOutcome { .. } => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            Error::StorageFailure | Error::Nostr => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Unsupported => StatusCode::NOT_IMPLEMENTED,
+            Error::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        Self {
+            status,
+            body: ErrorResponse {
+                code: e.code(),
+                message: e.to_string(),
+                event_id: e.event_id().map(|id| id.to_string()),
+            },
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
@@ -0,0 +1,394 @@
+//! Readiness checks served at `/health/ready`.
+//!
+//! The server is ready when the database can be queried, its migrations are
+//! up to date, it belongs to the oracle's signing key and, when publishing to
+//! nostr, a relay is connected and the outbox is not backed up.
+
+use crate::json_models::{ComponentHealth, HealthStatus, ReadinessResponse};
+use crate::AppState;
+use axum::http::StatusCode;
+use bitcoin::secp256k1::XOnlyPublicKey;
+use kormir::error::Error;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Time allowed to open a database connection before the database is
+/// reported unreachable
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReadinessChecks {
+    /// Whether the relays are connected to, only when publishing to nostr
+    pub relays: bool,
+    /// Most nostr events left in the outbox before the server is not ready,
+    /// only checked when publishing
+    pub max_outbox_backlog: usize,
+}
+
+impl ReadinessChecks {
+    pub async fn run(&self, state: &AppState) -> ReadinessResponse {
+        self.check(state).await
+    }
+
+    async fn check(&self, probe: &impl Probe) -> ReadinessResponse {
+        let mut components = BTreeMap::new();
+
+        let database = match probe.ping().await {
+            Ok(()) => ComponentHealth::ok(),
+            Err(_) => ComponentHealth::fail("database unreachable"),
+        };
+        let database_ok = database.status == HealthStatus::Ok;
+        components.insert("database", database);
+
+        let migrations = if !database_ok {
+            ComponentHealth::skipped("database unreachable")
+        } else {
+            match probe.has_pending_migrations().await {
+                Ok(false) => ComponentHealth::ok(),
+                Ok(true) => ComponentHealth::fail("pending migrations"),
+                Err(e) => ComponentHealth::fail(e.to_string()),
+            }
+        };
+        components.insert("migrations", migrations);
+
+        let oracle_key = if !database_ok {
+            ComponentHealth::skipped("database unreachable")
+        } else {
+            match probe.metadata_public_key().await {
+                Ok(Some(pubkey)) if pubkey == probe.public_key() => ComponentHealth::ok(),
+                Ok(Some(pubkey)) => ComponentHealth::fail(format!(
+                    "oracle metadata pubkey {pubkey} does not match the signing key"
+                )),
+                Ok(None) => ComponentHealth::fail("no oracle metadata"),
+                Err(e) => ComponentHealth::fail(e.to_string()),
+            }
+        };
+        components.insert("oracle_key", oracle_key);
+
+        let nostr = probe.nostr();
+
+        let relays = if nostr && self.relays {
+            let relays = probe.relays().await;
+            let mut health = if relays.values().any(|(_, connected)| *connected) {
+                ComponentHealth::ok()
+            } else {
+                ComponentHealth::fail("no relay connected")
+            };
+            health.relays = Some(
+                relays
+                    .into_iter()
+                    .map(|(url, (status, _))| (url, status))
+                    .collect(),
+            );
+            health
+        } else {
+            ComponentHealth::skipped("nostr publishing disabled")
+        };
+        components.insert("relays", relays);
+
+        let outbox = if !nostr {
+            ComponentHealth::skipped("nostr disabled")
+        } else if !database_ok {
+            ComponentHealth::skipped("database unreachable")
+        } else {
+            match probe.outbox_len().await {
+                Ok(pending) => {
+                    let mut health = if self.relays && pending > self.max_outbox_backlog {
+                        ComponentHealth::fail(format!(
+                            "more than {} nostr events waiting to be published",
+                            self.max_outbox_backlog
+                        ))
+                    } else {
+                        ComponentHealth::ok()
+                    };
+                    health.pending = Some(pending);
+                    health
+                }
+                Err(e) => ComponentHealth::fail(e.to_string()),
+            }
+        };
+        components.insert("outbox", outbox);
+
+        ReadinessResponse {
+            ready: components.values().all(|c| c.status != HealthStatus::Fail),
+            components,
+        }
+    }
+}
+
+/// The status the readiness report is served with, 503 when a component
+/// failed.
+pub fn status_code(report: &ReadinessResponse) -> StatusCode {
+    if report.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+/// What the readiness checks read from the database and the nostr publisher
+trait Probe {
+    async fn ping(&self) -> Result<(), Error>;
+
+    async fn has_pending_migrations(&self) -> Result<bool, Error>;
+
+    /// The oracle public key saved in the database's oracle metadata.
+    async fn metadata_public_key(&self) -> Result<Option<XOnlyPublicKey>, Error>;
+
+    /// The oracle's signing key.
+    fn public_key(&self) -> XOnlyPublicKey;
+
+    /// Whether the server signs nostr events, publishing them or keeping them
+    /// in the outbox.
+    fn nostr(&self) -> bool;
+
+    /// The status of each relay and whether it is connected.
+    async fn relays(&self) -> BTreeMap<String, (String, bool)>;
+
+    /// Number of nostr events waiting in the outbox.
+    async fn outbox_len(&self) -> Result<usize, Error>;
+}
+
+impl Probe for AppState {
+    async fn ping(&self) -> Result<(), Error> {
+        self.oracle.storage.ping(DATABASE_TIMEOUT).await
+    }
+
+    async fn has_pending_migrations(&self) -> Result<bool, Error> {
+        self.oracle.storage.has_pending_migrations().await
+    }
+
+    async fn metadata_public_key(&self) -> Result<Option<XOnlyPublicKey>, Error> {
+        self.oracle.storage.metadata_public_key().await
+    }
+
+    fn public_key(&self) -> XOnlyPublicKey {
+        self.oracle.public_key()
+    }
+
+    fn nostr(&self) -> bool {
+        self.oracle.observer().0.is_some()
+    }
+
+    async fn relays(&self) -> BTreeMap<String, (String, bool)> {
+        let Some(publisher) = self.oracle.observer().0.as_ref() else {
+            return BTreeMap::new();
+        };
+        publisher
+            .client()
+            .relays()
+            .await
+            .into_iter()
+            .map(|(url, r)| {
+                let status = r.status().to_string().to_lowercase();
+                (url.to_string(), (status, r.is_connected()))
+            })
+            .collect()
+    }
+
+    async fn outbox_len(&self) -> Result<usize, Error> {
+        match self.oracle.observer().0.as_ref() {
+            Some(publisher) => Ok(publisher.pending().await?.len()),
+            None => Ok(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::secp256k1::{Keypair, Secp256k1, SecretKey};
+
+    /// A database and publisher in the given state
+    struct MockProbe {
+        database: bool,
+        pending_migrations: bool,
+        metadata_key: Option<XOnlyPublicKey>,
+        nostr: bool,
+        relays: BTreeMap<String, (String, bool)>,
+        /// Events in the outbox, `None` when it cannot be read
+        outbox: Option<usize>,
+    }
+
+    impl Probe for MockProbe {
+        async fn ping(&self) -> Result<(), Error> {
+            self.database.then_some(()).ok_or(Error::StorageFailure)
+        }
+
+        async fn has_pending_migrations(&self) -> Result<bool, Error> {
+            Ok(self.pending_migrations)
+        }
+
+        async fn metadata_public_key(&self) -> Result<Option<XOnlyPublicKey>, Error> {
+            Ok(self.metadata_key)
+        }
+
+        fn public_key(&self) -> XOnlyPublicKey {
+            key(1)
+        }
+
+        fn nostr(&self) -> bool {
+            self.nostr
+        }
+
+        async fn relays(&self) -> BTreeMap<String, (String, bool)> {
+            self.relays.clone()
+        }
+
+        async fn outbox_len(&self) -> Result<usize, Error> {
+            self.outbox.ok_or(Error::StorageFailure)
+        }
+    }
+
+    fn key(byte: u8) -> XOnlyPublicKey {
+        let secret = SecretKey::from_slice(&[byte; 32]).unwrap();
+        Keypair::from_secret_key(&Secp256k1::new(), &secret)
+            .x_only_public_key()
+            .0
+    }
+
+    /// A ready server publishing to a connected relay
+    fn ready() -> MockProbe {
+        MockProbe {
+            database: true,
+            pending_migrations: false,
+            metadata_key: Some(key(1)),
+            nostr: true,
+            relays: BTreeMap::from([(
+                "wss://relay.example.com".to_string(),
+                ("connected".to_string(), true),
+            )]),
+            outbox: Some(0),
+        }
+    }
+
+    const PUBLISH: ReadinessChecks = ReadinessChecks {
+        relays: true,
+        max_outbox_backlog: 10,
+    };
+    const QUEUE: ReadinessChecks = ReadinessChecks {
+        relays: false,
+        max_outbox_backlog: 10,
+    };
+
+    fn status(report: &ReadinessResponse, component: &str) -> HealthStatus {
+        report.components[component].status
+    }
+
+    #[tokio::test]
+    async fn test_ready() {
+        let report = PUBLISH.check(&ready()).await;
+        assert!(report.ready);
+        assert_eq!(status_code(&report), StatusCode::OK);
+        assert!(report
+            .components
+            .values()
+            .all(|c| c.status == HealthStatus::Ok));
+        assert_eq!(report.components["outbox"].pending, Some(0));
+        assert_eq!(
+            report.components["relays"].relays,
+            Some(BTreeMap::from([(
+                "wss://relay.example.com".to_string(),
+                "connected".to_string()
+            )]))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_database_down() {
+        let probe = MockProbe {
+            database: false,
+            ..ready()
+        };
+        let report = PUBLISH.check(&probe).await;
+        assert!(!report.ready);
+        assert_eq!(status_code(&report), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(status(&report, "database"), HealthStatus::Fail);
+        assert_eq!(status(&report, "migrations"), HealthStatus::Skipped);
+        assert_eq!(status(&report, "oracle_key"), HealthStatus::Skipped);
+        assert_eq!(status(&report, "outbox"), HealthStatus::Skipped);
+        assert_eq!(status(&report, "relays"), HealthStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_unreadable() {
+        let probe = MockProbe {
+            outbox: None,
+            ..ready()
+        };
+        let report = PUBLISH.check(&probe).await;
+        assert!(!report.ready);
+        assert_eq!(status_code(&report), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(status(&report, "database"), HealthStatus::Ok);
+        assert_eq!(status(&report, "outbox"), HealthStatus::Fail);
+    }
+
+    #[tokio::test]
+    async fn test_nostr_off() {
+        let probe = MockProbe {
+            nostr: false,
+            relays: BTreeMap::new(),
+            ..ready()
+        };
+        let report = PUBLISH.check(&probe).await;
+        assert!(report.ready);
+        assert_eq!(status(&report, "relays"), HealthStatus::Skipped);
+        assert_eq!(status(&report, "outbox"), HealthStatus::Skipped);
+    }
+
+    #[tokio::test]
+    async fn test_queue_mode() {
+        // the relays are not connected to and the outbox grows until flushed
+        let probe = MockProbe {
+            relays: BTreeMap::new(),
+            outbox: Some(100),
+            ..ready()
+        };
+        let report = QUEUE.check(&probe).await;
+        assert!(report.ready);
+        assert_eq!(status(&report, "relays"), HealthStatus::Skipped);
+        assert_eq!(status(&report, "outbox"), HealthStatus::Ok);
+        assert_eq!(report.components["outbox"].pending, Some(100));
+    }
+
+    #[tokio::test]
+    async fn test_publishing_failures() {
+        let probe = MockProbe {
+            relays: BTreeMap::from([(
+                "wss://relay.example.com".to_string(),
+                ("disconnected".to_string(), false),
+            )]),
+            outbox: Some(11),
+            ..ready()
+        };
+        let report = PUBLISH.check(&probe).await;
+        assert!(!report.ready);
+        assert_eq!(status_code(&report), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(status(&report, "relays"), HealthStatus::Fail);
+        assert_eq!(status(&report, "outbox"), HealthStatus::Fail);
+    }
+
+    #[tokio::test]
+    async fn test_database_state() {
+        let probe = MockProbe {
+            pending_migrations: true,
+            metadata_key: Some(key(2)),
+            ..ready()
+        };
+        let report = PUBLISH.check(&probe).await;
+        assert!(!report.ready);
+        assert_eq!(status(&report, "migrations"), HealthStatus::Fail);
+        assert_eq!(status(&report, "oracle_key"), HealthStatus::Fail);
+
+        let probe = MockProbe {
+            metadata_key: None,
+            ..ready()
+        };
+        let report = PUBLISH.check(&probe).await;
+        assert_eq!(status(&report, "oracle_key"), HealthStatus::Fail);
+        assert_eq!(
+            report.components["oracle_key"].message.as_deref(),
+            Some("no oracle metadata")
+        );
+    }
+}
@@ -1,32 +1,46 @@
+use crate::models::api_key::ApiKey;
+use crate::models::webhook::WebhookDelivery;
 use anyhow::anyhow;
+use axum::http::header::ACCEPT;
+use axum::http::HeaderMap;
+use bitcoin::hashes::sha256;
 use bitcoin::XOnlyPublicKey;
 use chrono::{SecondsFormat, TimeZone, Utc};
 use dlc_messages::oracle_msgs::{OracleAnnouncement, OracleAttestation};
 use dlc_messages::ser_impls::write_as_tlv;
+use kormir::lightning::ln::wire::Type;
 use kormir::lightning::util::ser::Writeable;
-use kormir::storage::OracleEventData;
+use kormir::nostr_publisher::FlushResult;
+use kormir::schema as dlc;
+use kormir::storage::{OracleEventData, OutboxEvent};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct PubkeyResponse {
+    #[schema(value_type = String)]
     pub pubkey: XOnlyPublicKey,
+    /// The key the oracle publishes to nostr with
+    pub nostr_pubkey: String,
+    /// Signature by the oracle key binding it to the nostr key, when they differ
+    pub nostr_key_binding: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct CreateEnumEventRequest {
     pub event_id: String,
     pub outcomes: Vec<String>,
     pub event_maturity_epoch: u32,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct SignEnumEventRequest {
     pub event_id: String,
     pub outcome: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct CreateNumericEventRequest {
     pub event_id: String,
     pub num_digits: Option<u16>,
@@ -36,34 +50,87 @@ pub struct CreateNumericEventRequest {
     pub event_maturity_epoch: u32,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct SignNumericEventRequest {
     pub event_id: String,
     pub outcome: i64,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct WithdrawEventRequest {
+    pub event_id: String,
+}
+
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CreateApiKeyRequest {
+    pub key_id: String,
+    pub scopes: Vec<String>,
+    /// Limit the key to events whose id starts with this prefix
+    pub event_namespace: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ApiKeyResponse {
+    pub key_id: String,
+    pub scopes: Vec<String>,
+    pub event_namespace: Option<String>,
+    pub created_at: String,
+    pub revoked: bool,
+    /// Only returned when the key is created or rotated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+impl ApiKeyResponse {
+    pub fn with_secret(mut self, secret: String) -> Self {
+        self.secret = Some(secret);
+        self
+    }
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(key: ApiKey) -> Self {
+        ApiKeyResponse {
+            revoked: key.is_revoked(),
+            created_at: key
+                .created_at
+                .and_utc()
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            key_id: key.key_id,
+            scopes: key.scopes,
+            event_namespace: key.event_namespace,
+            secret: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct JsonEventResponse {
+    #[schema(value_type = dlc::OracleAnnouncement)]
     pub announcement: OracleAnnouncement,
+    #[schema(value_type = Option<dlc::OracleAttestation>)]
     pub attestation: Option<OracleAttestation>,
+    pub withdrawn: bool,
 }
 
 impl From<OracleEventData> for JsonEventResponse {
     fn from(d: OracleEventData) -> Self {
         JsonEventResponse {
             attestation: d.attestation(),
+            withdrawn: d.withdrawn,
             announcement: d.announcement,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct HexEventResponse {
     pub event_id: String,
     pub event_maturity_epoch: u32,
     pub event_maturity_iso: String,
     pub announcement: String,
     pub attestation: Option<String>,
+    pub withdrawn: bool,
 }
 
 impl From<OracleEventData> for HexEventResponse {
@@ -75,6 +142,7 @@ impl From<OracleEventData> for HexEventResponse {
             event_maturity_iso: epoch_to_iso(d.announcement.oracle_event.event_maturity_epoch),
             announcement: hex::encode(d.announcement.encode()),
             attestation: attestation.map(|a| hex::encode(a.encode())),
+            withdrawn: d.withdrawn,
         }
     }
 }
@@ -84,13 +152,14 @@ fn epoch_to_iso(epoch: u32) -> String {
         .to_rfc3339_opts(SecondsFormat::Secs, true)
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct TLVEventResponse {
     pub event_id: String,
     pub event_maturity_epoch: u32,
     pub event_maturity_iso: String,
     pub announcement: String,
     pub attestation: Option<String>,
+    pub withdrawn: bool,
 }
 
 impl From<OracleEventData> for TLVEventResponse {
@@ -100,40 +169,364 @@ impl From<OracleEventData> for TLVEventResponse {
             event_id: d.announcement.oracle_event.event_id.clone(),
             event_maturity_epoch: d.announcement.oracle_event.event_maturity_epoch,
             event_maturity_iso: epoch_to_iso(d.announcement.oracle_event.event_maturity_epoch),
-            announcement: {
-                let mut bytes = Vec::new();
-                write_as_tlv(&d.announcement, &mut bytes).unwrap();
-                hex::encode(bytes)
-            },
-            attestation: attestation.map(|a| {
-                let mut bytes = Vec::new();
-                write_as_tlv(&a, &mut bytes).unwrap();
-                hex::encode(bytes)
-            }),
+            announcement: hex::encode(tlv_bytes(&d.announcement)),
+            attestation: attestation.map(|a| hex::encode(tlv_bytes(&a))),
+            withdrawn: d.withdrawn,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Base64EventResponse {
+    pub event_id: String,
+    pub event_maturity_epoch: u32,
+    pub event_maturity_iso: String,
+    pub announcement: String,
+    pub attestation: Option<String>,
+    pub withdrawn: bool,
+}
+
+impl From<OracleEventData> for Base64EventResponse {
+    fn from(d: OracleEventData) -> Self {
+        let attestation = d.attestation();
+        Base64EventResponse {
+            event_id: d.announcement.oracle_event.event_id.clone(),
+            event_maturity_epoch: d.announcement.oracle_event.event_maturity_epoch,
+            event_maturity_iso: epoch_to_iso(d.announcement.oracle_event.event_maturity_epoch),
+            announcement: base64::encode(d.announcement.encode()),
+            attestation: attestation.map(|a| base64::encode(a.encode())),
+            withdrawn: d.withdrawn,
+        }
+    }
+}
+
+/// Serializes a DLC message with its TLV type and length prefix, as ddk expects.
+pub fn tlv_bytes<T: Type + Writeable>(msg: &T) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    write_as_tlv(msg, &mut bytes).expect("writing to a vec cannot fail");
+    bytes
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OutboxEventResponse {
+    pub event_id: String,
+    pub nostr_event_id: String,
+    pub kind: u16,
+    pub attempts: u32,
+    pub next_attempt: u64,
+    pub last_error: Option<String>,
+}
+
+impl From<OutboxEvent> for OutboxEventResponse {
+    fn from(e: OutboxEvent) -> Self {
+        OutboxEventResponse {
+            event_id: e.event_id,
+            nostr_event_id: e.nostr_event.id.to_hex(),
+            kind: e.nostr_event.kind.as_u16(),
+            attempts: e.attempts,
+            next_attempt: e.next_attempt.as_u64(),
+            last_error: e.last_error,
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FlushOutboxResponse {
+    pub published: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WebhookDeliveryResponse {
+    pub id: i64,
+    pub webhook_url: String,
+    pub event_id: String,
+    pub kind: String,
+    /// `pending`, `delivered` or `failed`
+    #[schema(value_type = String)]
+    pub status: &'static str,
+    pub attempts: i32,
+    /// Only set while the delivery is pending
+    pub next_attempt: Option<u64>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<u64>,
+    pub created_at: u64,
+    #[schema(value_type = Object)]
+    pub payload: serde_json::Value,
+}
+
+impl WebhookDeliveryResponse {
+    pub fn new(delivery: WebhookDelivery, max_attempts: i32) -> Self {
+        let status = match delivery.delivered_at {
+            Some(_) => "delivered",
+            None if delivery.attempts >= max_attempts => "failed",
+            None => "pending",
+        };
+        WebhookDeliveryResponse {
+            id: delivery.id,
+            status,
+            attempts: delivery.attempts,
+            next_attempt: (status == "pending")
+                .then(|| delivery.next_attempt.and_utc().timestamp() as u64),
+            last_error: delivery.last_error,
+            delivered_at: delivery
+                .delivered_at
+                .map(|t| t.and_utc().timestamp() as u64),
+            created_at: delivery.created_at.and_utc().timestamp() as u64,
+            payload: serde_json::from_str(&delivery.payload).unwrap_or_default(),
+            webhook_url: delivery.webhook_url,
+            event_id: delivery.event_id,
+            kind: delivery.kind,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct VerifyAuditLogResponse {
+    pub valid: bool,
+    /// Number of entries checked
+    pub entries: u64,
+    /// Hash of the last entry, commits to the whole log
+    #[schema(value_type = Option<String>)]
+    pub head: Option<sha256::Hash>,
+    /// Sequence number of the first entry that breaks the chain
+    pub first_invalid: Option<u64>,
+}
+
+/// Report of `/health/ready`, served with a 503 when not ready
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReadinessResponse {
+    pub ready: bool,
+    /// `database`, `migrations`, `oracle_key`, `relays` and `outbox`
+    #[schema(value_type = BTreeMap<String, ComponentHealth>)]
+    pub components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Fail,
+    /// Not checked, because the component is not used or depends on one that
+    /// failed
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Connection status of each relay
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relays: Option<BTreeMap<String, String>>,
+    /// Nostr events waiting in the outbox
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending: Option<usize>,
+}
+
+impl ComponentHealth {
+    pub fn ok() -> Self {
+        Self::new(HealthStatus::Ok, None)
+    }
+
+    pub fn fail(message: impl Into<String>) -> Self {
+        Self::new(HealthStatus::Fail, Some(message.into()))
+    }
+
+    pub fn skipped(message: impl Into<String>) -> Self {
+        Self::new(HealthStatus::Skipped, Some(message.into()))
+    }
+
+    fn new(status: HealthStatus, message: Option<String>) -> Self {
+        ComponentHealth {
+            status,
+            message,
+            relays: None,
+            pending: None,
+        }
+    }
+}
+
+impl From<FlushResult> for FlushOutboxResponse {
+    fn from(r: FlushResult) -> Self {
+        FlushOutboxResponse {
+            published: r.published.iter().map(|id| id.to_hex()).collect(),
+            failed: r.failed.iter().map(|id| id.to_hex()).collect(),
+        }
+    }
+}
+
+/// Encoding of the DLC messages returned by the read endpoints
+///
+/// `hex` and `base64` encode the plain message, `base64` being the content of
+/// the nostr events. `tlv` and `binary` prefix it with its TLV type and length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Format {
     Json,
     Hex,
     Tlv,
+    Base64,
+    Binary,
 }
 
 impl Format {
     pub fn from_query(params: &HashMap<String, String>) -> anyhow::Result<Self> {
         if let Some(format) = params.get("format") {
-            if format == "json" {
-                Ok(Format::Json)
-            } else if format == "hex" {
-                Ok(Format::Hex)
-            } else if format == "tlv" {
-                Ok(Format::Tlv)
-            } else {
-                Err(anyhow!("invalid format: {format}"))
+            match format.as_str() {
+                "json" => Ok(Format::Json),
+                "hex" => Ok(Format::Hex),
+                "tlv" => Ok(Format::Tlv),
+                "base64" => Ok(Format::Base64),
+                "binary" => Ok(Format::Binary),
+                _ => Err(anyhow!("invalid format: {format}")),
             }
         } else {
             Ok(Format::Json)
         }
     }
+
+    /// Picks the format from the `format` query parameter, falling back to the
+    /// supported media type the `Accept` header rates highest.
+    ///
+    /// Each media type takes the quality of the most specific range matching
+    /// it, ties going to the range listed first and then to JSON, and `q=0`
+    /// refuses the type.
+    pub fn from_request(
+        params: &HashMap<String, String>,
+        headers: &HeaderMap,
+    ) -> anyhow::Result<Self> {
+        if params.contains_key("format") {
+            return Self::from_query(params);
+        }
+        let Some(accept) = headers.get(ACCEPT) else {
+            return Ok(Format::Json);
+        };
+
+        let accept = accept.to_str()?;
+        let ranges: Vec<(String, f32)> = accept
+            .split(',')
+            .map(|range| {
+                let mut parts = range.split(';');
+                let media = parts.next().unwrap_or_default().trim().to_lowercase();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())
+                    .filter(|q| (0.0..=1.0).contains(q))
+                    .unwrap_or(0.0);
+                (media, quality)
+            })
+            .collect();
+
+        [
+            ("application/json", Format::Json),
+            ("application/octet-stream", Format::Binary),
+        ]
+        .into_iter()
+        .filter_map(|(media_type, format)| {
+            let (kind, _) = media_type.split_once('/')?;
+            let (_, position, quality) = ranges
+                .iter()
+                .enumerate()
+                .filter_map(|(position, (range, quality))| {
+                    let specificity = match range.split_once('/')? {
+                        _ if range == media_type => 2,
+                        (k, "*") if k == kind => 1,
+                        ("*", "*") => 0,
+                        _ => return None,
+                    };
+                    Some((specificity, position, *quality))
+                })
+                .max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)))?;
+            (quality > 0.0).then_some((quality, position, format))
+        })
+        .min_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)))
+        .map(|(_, _, format)| format)
+        .ok_or_else(|| anyhow!("unsupported media type: {accept}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn negotiate(format: Option<&str>, accept: Option<&str>) -> anyhow::Result<Format> {
+        let mut params = HashMap::new();
+        if let Some(format) = format {
+            params.insert("format".to_string(), format.to_string());
+        }
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert(ACCEPT, HeaderValue::from_str(accept).unwrap());
+        }
+        Format::from_request(&params, &headers)
+    }
+
+    #[test]
+    fn test_format_from_request() {
+        assert_eq!(negotiate(None, None).unwrap(), Format::Json);
+        assert_eq!(negotiate(None, Some("*/*")).unwrap(), Format::Json);
+        assert_eq!(
+            negotiate(None, Some("application/*")).unwrap(),
+            Format::Json
+        );
+        assert_eq!(
+            negotiate(None, Some("application/json; charset=utf-8")).unwrap(),
+            Format::Json
+        );
+        assert_eq!(
+            negotiate(None, Some("application/octet-stream")).unwrap(),
+            Format::Binary
+        );
+        assert_eq!(
+            negotiate(None, Some("application/octet-stream, application/json")).unwrap(),
+            Format::Binary
+        );
+        assert!(negotiate(None, Some("text/html")).is_err());
+        assert!(negotiate(None, Some("application/json;q=0")).is_err());
+    }
+
+    #[test]
+    fn test_format_query_over_header() {
+        assert_eq!(
+            negotiate(Some("hex"), Some("application/octet-stream")).unwrap(),
+            Format::Hex
+        );
+        assert_eq!(
+            negotiate(Some("binary"), Some("text/html")).unwrap(),
+            Format::Binary
+        );
+        assert!(negotiate(Some("xml"), Some("application/json")).is_err());
+    }
+
+    #[test]
+    fn test_format_quality() {
+        assert_eq!(
+            negotiate(
+                None,
+                Some("application/json;q=0.5, application/octet-stream")
+            )
+            .unwrap(),
+            Format::Binary
+        );
+        assert_eq!(
+            negotiate(None, Some("application/json;q=0, application/octet-stream")).unwrap(),
+            Format::Binary
+        );
+        assert_eq!(
+            negotiate(None, Some("application/json;q=0, */*")).unwrap(),
+            Format::Binary
+        );
+        assert_eq!(
+            negotiate(None, Some("application/octet-stream;q=0.1, */*;q=0.2")).unwrap(),
+            Format::Json
+        );
+        assert_eq!(
+            negotiate(None, Some("text/html, application/*;q=0.3")).unwrap(),
+            Format::Json
+        );
+        assert!(negotiate(None, Some("*/*;q=0")).is_err());
+        assert!(negotiate(None, Some("application/json;q=abc")).is_err());
+    }
 }
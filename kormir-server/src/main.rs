@@ -1,35 +1,92 @@
+use crate::auth::{authenticate, parse_scopes, AuthState, Scope, MAX_CLOCK_SKEW};
+use crate::error::ApiError;
+use crate::health::ReadinessChecks;
+use crate::metrics::Metrics;
 use crate::models::oracle_metadata::OracleMetadata;
 use crate::models::{PostgresStorage, MIGRATIONS};
+use crate::openapi::ApiRouter;
 use crate::routes::*;
-use axum::extract::State;
+use crate::stream::ActivityFeed;
+use crate::webhooks::{spawn_webhook_sender, Webhooks};
 use axum::http::{StatusCode, Uri};
-use axum::routing::{get, post};
-use axum::{body::Body, extract::Request};
-use axum::{middleware, response::IntoResponse};
-use axum::{middleware::Next, response::Response};
+use axum::middleware;
 use axum::{Extension, Router};
 use bitcoin::secp256k1::{Secp256k1, SecretKey};
 use diesel::r2d2::{ConnectionManager, Pool};
 use diesel::PgConnection;
 use diesel_migrations::MigrationHarness;
-use hmac::{Hmac, Mac};
-use http_body_util::BodyExt;
+use kormir::nostr_events::{DescriptorType, OracleProfile};
+use kormir::nostr_publisher::NostrPublisher;
 use kormir::Oracle;
-use nostr::Keys;
+use nostr::{Keys, Url};
 use nostr_sdk::Client;
-use sha2::Sha256;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::str::FromStr;
 use std::time::Duration;
 use tokio::signal;
 use tower_http::timeout::TimeoutLayer;
 
+mod approvals;
+mod auth;
+mod dm;
+mod error;
+mod health;
 mod json_models;
+mod metrics;
 mod models;
+mod openapi;
 mod routes;
+mod stream;
+mod webhooks;
+
+/// Notified of the oracle's announcements, attestations and cancellations
+type Observers = (
+    Option<NostrPublisher<PostgresStorage>>,
+    (Option<Webhooks>, ActivityFeed),
+);
 
 #[derive(Clone)]
 pub struct AppState {
-    oracle: Oracle<PostgresStorage>,
-    client: Client,
+    oracle: Oracle<PostgresStorage, Observers>,
+    /// Announcements and attestations streamed to clients
+    activity: ActivityFeed,
+    metrics: Metrics,
+    readiness: ReadinessChecks,
+    /// Distinct credentials that must approve an outcome before it is signed
+    approvals_required: usize,
+}
+
+/// How the server uses nostr, set with `KORMIR_NOSTR_MODE`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NostrMode {
+    /// Connect to the relays and publish events as they are created
+    Publish,
+    /// Sign events and keep them in the outbox until it is flushed
+    Queue,
+    /// Do not use nostr, events are only served over HTTP
+    Off,
+}
+
+impl FromStr for NostrMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "publish" => Ok(NostrMode::Publish),
+            "queue" => Ok(NostrMode::Queue),
+            "off" => Ok(NostrMode::Off),
+            _ => Err(anyhow::anyhow!("invalid nostr mode: {s}")),
+        }
+    }
+}
+
+impl NostrMode {
+    /// Whether the publisher keeps the events in the outbox instead of
+    /// sending them
+    fn queue_only(self) -> bool {
+        self == NostrMode::Queue
+    }
 }
 
 #[tokio::main]
@@ -83,10 +140,26 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
-    let oracle = Oracle::from_signing_key(
-        PostgresStorage::new(db_pool, signing_key.x_only_public_key(&secp).0)?,
-        signing_key,
-    )?;
+    let name = OracleMetadata::get(&mut conn)?
+        .map(|m| m.name)
+        .unwrap_or_else(|| "Kormir".to_string());
+
+    // attested events cached in memory, disabled by default
+    let cache_size: usize = std::env::var("KORMIR_EVENT_CACHE_SIZE")
+        .ok()
+        .map(|s| s.parse())
+        .transpose()?
+        .unwrap_or(0);
+
+    let storage = PostgresStorage::new(db_pool, signing_key.x_only_public_key(&secp).0)?
+        .with_cache(cache_size);
+    let mut oracle = Oracle::from_signing_key(storage.clone(), signing_key)?;
+
+    // optionally publish to nostr with a key other than the oracle key
+    if let Ok(nostr_key) = std::env::var("KORMIR_NOSTR_KEY") {
+        let secret_bytes = Keys::parse(&nostr_key)?.secret_key().secret_bytes();
+        oracle = oracle.with_nostr_key(SecretKey::from_slice(&secret_bytes)?);
+    }
 
     let relays = std::env::var("KORMIR_RELAYS")
         .unwrap_or("wss://relay.damus.io".to_string())
@@ -94,46 +167,170 @@ async fn main() -> anyhow::Result<()> {
         .map(|s| s.to_string())
         .collect::<Vec<_>>();
 
-    let hmac_secret = {
-        let val = std::env::var("KORMIR_HMAC_SECRET").expect("KORMIR_HMAC_SECRET must be set");
-        if val.to_lowercase() == "none" {
-            None
-        } else {
-            Some(val.as_bytes().to_vec())
-        }
+    // shared secret for clients without an API key, "none" disables authentication
+    let hmac_secret = std::env::var("KORMIR_HMAC_SECRET").ok();
+    // the shared secret predates the API keys, it keeps creating and signing
+    // events unless its scopes are set
+    let shared_scopes = std::env::var("KORMIR_HMAC_SECRET_SCOPES")
+        .ok()
+        .map(|s| parse_scopes(&s))
+        .transpose()?;
+    let metrics = Metrics::new()?;
+    let auth_state = AuthState {
+        storage: storage.clone(),
+        metrics: metrics.clone(),
+        enabled: hmac_secret.as_ref().map(|s| s.to_lowercase()) != Some("none".to_string()),
+        shared_secret: hmac_secret.map(|s| s.into_bytes()),
+        shared_scopes: shared_scopes
+            .clone()
+            .unwrap_or(vec![Scope::Create, Scope::Sign]),
+        nostr_operators: std::env::var("KORMIR_NOSTR_OPERATORS")
+            .map(|keys| dm::parse_allowlist(&keys))
+            .unwrap_or(Ok(HashSet::new()))?,
+        base_url: std::env::var("KORMIR_HTTP_ENDPOINT")
+            .ok()
+            .map(|url| Url::parse(&url))
+            .transpose()?,
     };
+    if !auth_state.enabled {
+        log::warn!("KORMIR_HMAC_SECRET=none, authentication is disabled and anyone can create, sign and administer events");
+    } else if auth_state.shared_secret.is_none() {
+        log::info!(
+            "KORMIR_HMAC_SECRET is not set, only API keys and nostr operators can authenticate"
+        );
+    } else if shared_scopes.is_none() {
+        log::warn!("KORMIR_HMAC_SECRET_SCOPES is not set, the shared secret can create and sign events, set it to the scopes the secret needs as this default is deprecated");
+    } else if auth_state.shared_scopes.contains(&Scope::Admin) {
+        log::warn!("KORMIR_HMAC_SECRET is deprecated for admin access, use an API key instead");
+    }
+
+    let nostr_mode = std::env::var("KORMIR_NOSTR_MODE")
+        .ok()
+        .map(|m| m.parse::<NostrMode>())
+        .transpose()?
+        .unwrap_or(NostrMode::Publish);
 
     let client = Client::new(oracle.nostr_keys());
     for relay in relays {
         client.add_relay(relay).await?;
     }
-    client.connect().await;
+    if nostr_mode == NostrMode::Publish {
+        client.connect().await;
+    }
 
-    let app_state = AppState { oracle, client };
+    let outbox_interval: u64 = std::env::var("KORMIR_OUTBOX_INTERVAL")
+        .ok()
+        .map(|p| p.parse::<u64>())
+        .transpose()?
+        .unwrap_or(30);
+
+    let profile = OracleProfile {
+        name,
+        description: std::env::var("KORMIR_DESCRIPTION").ok(),
+        http_endpoint: std::env::var("KORMIR_HTTP_ENDPOINT")
+            .ok()
+            .map(|url| Url::parse(&url))
+            .transpose()?,
+        descriptor_types: vec![DescriptorType::Enum, DescriptorType::DigitDecomposition],
+    };
+
+    let publisher = NostrPublisher::new(storage.clone(), oracle.nostr_keys(), client.clone())
+        .with_key_binding(oracle.nostr_key_binding())
+        .with_queue_only(nostr_mode.queue_only());
+
+    match std::env::args().nth(1).as_deref() {
+        None => {}
+        Some("rebroadcast") if nostr_mode == NostrMode::Off => {
+            anyhow::bail!("Cannot rebroadcast with KORMIR_NOSTR_MODE=off")
+        }
+        Some("rebroadcast") => return rebroadcast(&publisher).await,
+        Some("create-api-key") => {
+            let args = std::env::args().skip(2).collect::<Vec<_>>();
+            return create_api_key_command(&auth_state.storage, &args).await;
+        }
+        Some(command) => anyhow::bail!("Unknown command: {command}"),
+    }
+
+    if nostr_mode == NostrMode::Publish {
+        spawn_profile_publisher(publisher.clone(), profile);
+        spawn_outbox_publisher(publisher.clone(), Duration::from_secs(outbox_interval));
+    } else {
+        log::info!("Nostr publishing is disabled ({nostr_mode:?} mode)");
+    }
+
+    let approvals_required: usize = std::env::var("KORMIR_APPROVALS_REQUIRED")
+        .ok()
+        .map(|n| n.parse())
+        .transpose()?
+        .unwrap_or(1);
+    if approvals_required > 1 && !auth_state.enabled {
+        anyhow::bail!("KORMIR_APPROVALS_REQUIRED requires authentication");
+    }
+
+    // optionally POST event notifications to webhooks
+    let webhook_urls = std::env::var("KORMIR_WEBHOOK_URLS")
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(Url::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    let webhooks = if webhook_urls.is_empty() {
+        None
+    } else {
+        let secret = std::env::var("KORMIR_WEBHOOK_SECRET")
+            .map_err(|_| anyhow::anyhow!("KORMIR_WEBHOOK_URLS requires KORMIR_WEBHOOK_SECRET"))?;
+        let webhook_interval: u64 = std::env::var("KORMIR_WEBHOOK_INTERVAL")
+            .ok()
+            .map(|p| p.parse::<u64>())
+            .transpose()?
+            .unwrap_or(30);
+        let webhooks = Webhooks::new(storage.clone(), webhook_urls, secret.into_bytes()).await?;
+        spawn_webhook_sender(webhooks.clone(), Duration::from_secs(webhook_interval));
+        Some(webhooks)
+    };
+
+    let readiness = ReadinessChecks {
+        relays: nostr_mode == NostrMode::Publish,
+        max_outbox_backlog: std::env::var("KORMIR_MAX_OUTBOX_BACKLOG")
+            .ok()
+            .map(|n| n.parse())
+            .transpose()?
+            .unwrap_or(100),
+    };
+
+    let activity = ActivityFeed::new(storage.clone());
+    let app_state = AppState {
+        oracle: oracle.with_observer((
+            (nostr_mode != NostrMode::Off).then_some(publisher),
+            (webhooks, activity.clone()),
+        )),
+        activity,
+        metrics,
+        readiness,
+        approvals_required,
+    };
+
+    // optionally accept event requests over encrypted nostr DMs
+    if let Ok(allowlist) = std::env::var("KORMIR_DM_ALLOWLIST") {
+        if nostr_mode != NostrMode::Publish {
+            anyhow::bail!("KORMIR_DM_ALLOWLIST requires KORMIR_NOSTR_MODE=publish");
+        }
+        let allowlist = dm::parse_allowlist(&allowlist)?;
+        dm::spawn_dm_intake(app_state.clone(), client, allowlist).await?;
+    }
 
-    let addr: std::net::SocketAddr = format!("0.0.0.0:{port}")
+    if auth_state.enabled {
+        spawn_request_nonce_cleanup(auth_state.storage.clone());
+    }
+
+    let addr: SocketAddr = format!("0.0.0.0:{port}")
         .parse()
         .expect("Failed to parse bind/port for webserver");
 
     let server_router = Router::new()
+        .merge(Router::from(public_routes()))
         .merge(
-            Router::new()
-                .route("/health-check", get(health_check))
-                .route("/pubkey", get(get_pubkey))
-                .route("/list-events", get(list_events))
-                .route("/announcement/:event_id", get(get_oracle_announcement))
-                .route("/attestation/:event_id", get(get_oracle_attestation)),
-        )
-        .merge(
-            Router::new()
-                .route("/create-enum", post(create_enum_event))
-                .route("/create-numeric", post(create_numeric_event))
-                .route("/sign-enum", post(sign_enum_event))
-                .route("/sign-numeric", post(sign_numeric_event))
-                .layer(middleware::from_fn_with_state(
-                    hmac_secret,
-                    verify_hmac_signature,
-                )),
+            Router::from(authenticated_routes())
+                .layer(middleware::from_fn_with_state(auth_state, authenticate)),
         )
         .fallback(fallback)
         .layer(TimeoutLayer::new(Duration::from_secs(10)))
@@ -143,13 +340,135 @@ async fn main() -> anyhow::Result<()> {
 
     println!("Kormir server running on http://{addr}");
 
-    axum::serve(listener, server_router)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    // the peer address is recorded in the audit log
+    axum::serve(
+        listener,
+        server_router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
 
     Ok(())
 }
 
+/// Routes anyone can call
+fn public_routes() -> ApiRouter {
+    ApiRouter::default()
+        .get("/health-check", health_check)
+        .get("/health/live", liveness_check)
+        .get("/health/ready", readiness_check)
+        .get("/metrics", get_metrics)
+        .get("/openapi.json", get_openapi)
+        .get("/pubkey", get_pubkey)
+        .get("/list-events", list_events)
+        .get("/stream", stream_events)
+        .get("/announcement/:event_id", get_oracle_announcement)
+        .get("/attestation/:event_id", get_oracle_attestation)
+}
+
+/// Routes that require a signed request, see [`auth`]
+fn authenticated_routes() -> ApiRouter {
+    ApiRouter::default()
+        .post("/create-enum", create_enum_event)
+        .post("/create-numeric", create_numeric_event)
+        .post("/sign-enum", sign_enum_event)
+        .post("/sign-numeric", sign_numeric_event)
+        .post("/withdraw", withdraw_event)
+        .get("/approvals/:event_id", get_approvals)
+        .get("/outbox", list_outbox)
+        .post("/outbox/flush", flush_outbox)
+        .get("/api-keys", list_api_keys)
+        .post("/api-keys", create_api_key)
+        .post("/api-keys/:key_id/rotate", rotate_api_key)
+        .post("/api-keys/:key_id/revoke", revoke_api_key)
+        .get("/webhooks/deliveries", list_webhook_deliveries)
+        .get("/audit-log", list_audit_log)
+        .get("/audit-log/verify", verify_audit_log)
+}
+
+/// Republishes every stored announcement and attestation to the configured
+/// relays and prints a report of where each nostr event was sent.
+async fn rebroadcast(publisher: &NostrPublisher<PostgresStorage>) -> anyhow::Result<()> {
+    publisher.client().connect().await;
+    publisher
+        .client()
+        .wait_for_connection(Duration::from_secs(10))
+        .await;
+
+    let reports = publisher.rebroadcast(Duration::from_secs(10)).await?;
+    println!("{}", serde_json::to_string_pretty(&reports)?);
+
+    Ok(())
+}
+
+/// Creates an API key from the command line, to bootstrap the first admin key:
+/// `kormir-server create-api-key <key_id> <scopes> [event_namespace]`
+async fn create_api_key_command(storage: &PostgresStorage, args: &[String]) -> anyhow::Result<()> {
+    let (key_id, scopes) = match args {
+        [key_id, scopes] | [key_id, scopes, _] => (key_id, parse_scopes(scopes)?),
+        _ => {
+            anyhow::bail!("Usage: kormir-server create-api-key <key_id> <scopes> [event_namespace]")
+        }
+    };
+    let key = new_api_key(key_id.clone(), scopes, args.get(2).cloned())
+        .map_err(|e| anyhow::anyhow!(e.body.message))?;
+    let secret = key.secret.clone();
+
+    match storage.create_api_key(key).await? {
+        Some(_) => println!("{secret}"),
+        None => anyhow::bail!("API key {key_id} already exists"),
+    }
+
+    Ok(())
+}
+
+/// Publishes the oracle's nostr profile and relay list once the relays are connected.
+fn spawn_profile_publisher(publisher: NostrPublisher<PostgresStorage>, profile: OracleProfile) {
+    tokio::spawn(async move {
+        publisher
+            .client()
+            .wait_for_connection(Duration::from_secs(10))
+            .await;
+        match publisher.publish_profile(&profile).await {
+            Ok(()) => log::info!("Published nostr profile"),
+            Err(e) => log::error!("Failed to publish nostr profile: {e}"),
+        }
+    });
+}
+
+/// Periodically retries publishing the nostr events left in the outbox.
+fn spawn_outbox_publisher(publisher: NostrPublisher<PostgresStorage>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match publisher.flush(false).await {
+                Ok(res) if !res.published.is_empty() || !res.failed.is_empty() => {
+                    log::info!(
+                        "Flushed nostr outbox: {} published, {} failed",
+                        res.published.len(),
+                        res.failed.len()
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => log::error!("Failed to flush nostr outbox: {e}"),
+            }
+        }
+    });
+}
+
+/// Periodically deletes the request nonces that are too old to be replayed.
+fn spawn_request_nonce_cleanup(storage: PostgresStorage) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(MAX_CLOCK_SKEW);
+        loop {
+            interval.tick().await;
+            // failures are logged by the storage
+            let _ = storage.delete_expired_request_nonces().await;
+        }
+    });
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -174,58 +493,58 @@ async fn shutdown_signal() {
     }
 }
 
-async fn fallback(uri: Uri) -> (StatusCode, String) {
-    (StatusCode::NOT_FOUND, format!("No route for {uri}"))
+async fn fallback(uri: Uri) -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        "no_route",
+        format!("No route for {uri}"),
+    )
 }
 
-async fn verify_hmac_signature(
-    State(hmac_secret): State<Option<Vec<u8>>>,
-    req: Request,
-    next: Next,
-) -> Result<impl IntoResponse, Response> {
-    match hmac_secret {
-        None => Ok(next.run(req).await),
-        Some(secret) => {
-            let (parts, body_parts) = req.into_parts();
-
-            match parts.headers.get("X-Signature") {
-                Some(signature_header) => {
-                    let sig = std::str::from_utf8(signature_header.as_bytes())
-                        .map_err(|_| internal_server_error("invalid signature"))?;
-
-                    let bytes = body_parts
-                        .collect()
-                        .await
-                        .map_err(|_| internal_server_error("invalid request body"))?
-                        .to_bytes();
-
-                    let expected_sig = calculate_hmac(&bytes, &secret)
-                        .map_err(|_| internal_server_error("error calculating HMAC"))?;
-
-                    if sig == expected_sig {
-                        let req = Request::from_parts(parts, Body::from(bytes));
-                        Ok(next.run(req).await)
-                    } else {
-                        Err(unauthorized("wrong signature"))
-                    }
-                }
-                None => Err(unauthorized("missing signature")),
-            }
-        }
-    }
-}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use kormir::storage::MemoryStorage;
 
-fn calculate_hmac(payload: &[u8], secret: &[u8]) -> Result<String, ()> {
-    let mut mac = Hmac::<Sha256>::new_from_slice(secret).map_err(|_| ())?;
-    mac.update(payload);
-    let result = mac.finalize().into_bytes();
-    Ok(hex::encode(result))
-}
+    #[test]
+    fn test_nostr_mode() {
+        assert_eq!("publish".parse::<NostrMode>().unwrap(), NostrMode::Publish);
+        assert_eq!("queue".parse::<NostrMode>().unwrap(), NostrMode::Queue);
+        assert_eq!("off".parse::<NostrMode>().unwrap(), NostrMode::Off);
 
-fn internal_server_error(msg: &'static str) -> Response {
-    (StatusCode::INTERNAL_SERVER_ERROR, msg).into_response()
-}
+        for mode in ["", "Queue", "OFF", " publish", "queued", "none"] {
+            let err = mode.parse::<NostrMode>().unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid nostr mode: {mode}"));
+        }
+    }
 
-fn unauthorized(msg: &'static str) -> Response {
-    (StatusCode::UNAUTHORIZED, msg).into_response()
+    #[tokio::test]
+    async fn test_queue_mode_does_not_send() {
+        let signing_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let storage = MemoryStorage::default();
+        let oracle = Oracle::from_signing_key(storage.clone(), signing_key).unwrap();
+        let client = Client::new(oracle.nostr_keys());
+        client.add_relay("ws://127.0.0.1:1").await.unwrap();
+
+        let mode = "queue".parse::<NostrMode>().unwrap();
+        let publisher = NostrPublisher::new(storage, oracle.nostr_keys(), client.clone())
+            .with_queue_only(mode.queue_only());
+        let oracle = oracle.with_observer(publisher.clone());
+        oracle
+            .create_enum_event("queued".to_string(), vec!["a".to_string()], 0)
+            .await
+            .unwrap();
+        oracle
+            .sign_enum_event("queued".to_string(), "a".to_string())
+            .await
+            .unwrap();
+
+        let pending = publisher.pending().await.unwrap();
+        assert_eq!(pending.len(), 2);
+        assert!(pending.iter().all(|e| e.attempts == 0));
+        for relay in client.relays().await.values() {
+            assert_eq!(relay.stats().attempts(), 0);
+            assert_eq!(relay.stats().bytes_sent(), 0);
+        }
+    }
 }
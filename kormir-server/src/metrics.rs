@@ -0,0 +1,257 @@
+//! Prometheus metrics served at `/metrics`.
+
+use crate::models::PostgresStorage;
+use kormir::nostr_publisher::{NostrPublisher, RelayPublishStats};
+use nostr::RelayUrl;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// `type` label of the event metrics
+pub const ENUM: &str = "enum";
+pub const NUMERIC: &str = "numeric";
+
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    events_created: IntCounterVec,
+    events_signed: IntCounterVec,
+    signing_duration: HistogramVec,
+    auth_failures: IntCounterVec,
+    audit_failures: IntCounter,
+    nostr_publish: IntCounterVec,
+    db_connections: IntGaugeVec,
+    db_max_connections: IntGauge,
+    matured_unsigned: IntGauge,
+    /// Serializes the updates of the metrics read at scrape time
+    scrape: Arc<Mutex<()>>,
+}
+
+impl Metrics {
+    pub fn new() -> anyhow::Result<Self> {
+        let registry = Registry::new_custom(Some("kormir".to_string()), None)?;
+
+        let events_created = IntCounterVec::new(
+            Opts::new("events_created_total", "Events created"),
+            &["type"],
+        )?;
+        let events_signed = IntCounterVec::new(
+            Opts::new("events_signed_total", "Events attested"),
+            &["type"],
+        )?;
+        let signing_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "signing_duration_seconds",
+                "Time taken to attest an event, including notifying the observers",
+            )
+            .buckets(vec![
+                0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+            ]),
+            &["type"],
+        )?;
+        let auth_failures = IntCounterVec::new(
+            Opts::new("auth_failures_total", "Requests that failed authentication"),
+            &["scheme"],
+        )?;
+        let audit_failures = IntCounter::new(
+            "audit_failures_total",
+            "Actions that could not be appended to the audit log",
+        )?;
+        let nostr_publish = IntCounterVec::new(
+            Opts::new(
+                "nostr_publish_total",
+                "Nostr events sent to each relay, by result",
+            ),
+            &["relay", "result"],
+        )?;
+        let db_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Open database connections"),
+            &["state"],
+        )?;
+        let db_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Most database connections the pool opens",
+        )?;
+        let matured_unsigned = IntGauge::new(
+            "matured_unsigned_events",
+            "Events past their maturity that are neither attested nor withdrawn",
+        )?;
+
+        registry.register(Box::new(events_created.clone()))?;
+        registry.register(Box::new(events_signed.clone()))?;
+        registry.register(Box::new(signing_duration.clone()))?;
+        registry.register(Box::new(auth_failures.clone()))?;
+        registry.register(Box::new(audit_failures.clone()))?;
+        registry.register(Box::new(nostr_publish.clone()))?;
+        registry.register(Box::new(db_connections.clone()))?;
+        registry.register(Box::new(db_max_connections.clone()))?;
+        registry.register(Box::new(matured_unsigned.clone()))?;
+
+        Ok(Self {
+            registry,
+            events_created,
+            events_signed,
+            signing_duration,
+            auth_failures,
+            audit_failures,
+            nostr_publish,
+            db_connections,
+            db_max_connections,
+            matured_unsigned,
+            scrape: Arc::new(Mutex::new(())),
+        })
+    }
+
+    pub fn event_created(&self, kind: &str) {
+        self.events_created.with_label_values(&[kind]).inc();
+    }
+
+    pub fn event_signed(&self, kind: &str, duration: Duration) {
+        self.events_signed.with_label_values(&[kind]).inc();
+        self.signing_duration
+            .with_label_values(&[kind])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn auth_failed(&self, scheme: &str) {
+        self.auth_failures.with_label_values(&[scheme]).inc();
+    }
+
+    pub fn audit_failed(&self) {
+        self.audit_failures.inc();
+    }
+
+    /// Reads the current state of the database and publisher, and renders
+    /// every metric in the Prometheus text format.
+    pub async fn render(
+        &self,
+        storage: &PostgresStorage,
+        publisher: Option<&NostrPublisher<PostgresStorage>>,
+    ) -> anyhow::Result<String> {
+        let matured_unsigned = storage.count_matured_unsigned(now()).await;
+
+        {
+            let _guard = self.scrape.lock().unwrap();
+
+            let state = storage.pool_state();
+            let idle = state.idle_connections as i64;
+            self.db_connections.with_label_values(&["idle"]).set(idle);
+            self.db_connections
+                .with_label_values(&["in_use"])
+                .set(state.connections as i64 - idle);
+            self.db_max_connections.set(storage.pool_max_size() as i64);
+
+            self.publish_stats(publisher.map(|p| p.publish_stats()).unwrap_or_default());
+
+            match matured_unsigned {
+                Ok(count) => self.matured_unsigned.set(count),
+                Err(e) => log::error!("Failed to count matured unsigned events: {e}"),
+            }
+        }
+
+        self.encode()
+    }
+
+    /// Catches the relay counters up to the publisher's running totals.
+    fn publish_stats(&self, stats: HashMap<RelayUrl, RelayPublishStats>) {
+        for (relay, stats) in stats {
+            let relay = relay.to_string();
+            for (result, total) in [("success", stats.published), ("failure", stats.failed)] {
+                let counter = self.nostr_publish.with_label_values(&[&relay, result]);
+                counter.inc_by(total.saturating_sub(counter.get()));
+            }
+        }
+    }
+
+    fn encode(&self) -> anyhow::Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+fn now() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as u32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(metrics: &Metrics) -> Vec<String> {
+        let text = metrics.encode().unwrap();
+        text.lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| l.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_counters() {
+        let metrics = Metrics::new().unwrap();
+        metrics.event_created(ENUM);
+        metrics.event_created(ENUM);
+        metrics.event_created(NUMERIC);
+        metrics.auth_failed("hmac");
+        metrics.audit_failed();
+
+        let lines = lines(&metrics);
+        for expected in [
+            r#"kormir_events_created_total{type="enum"} 2"#,
+            r#"kormir_events_created_total{type="numeric"} 1"#,
+            r#"kormir_auth_failures_total{scheme="hmac"} 1"#,
+            "kormir_audit_failures_total 1",
+            r#"kormir_db_pool_max_connections 0"#,
+            r#"kormir_matured_unsigned_events 0"#,
+        ] {
+            assert!(lines.iter().any(|l| l == expected), "missing {expected}");
+        }
+    }
+
+    #[test]
+    fn test_signing_histogram() {
+        let metrics = Metrics::new().unwrap();
+        metrics.event_signed(NUMERIC, Duration::from_millis(20));
+
+        let lines = lines(&metrics);
+        for expected in [
+            r#"kormir_events_signed_total{type="numeric"} 1"#,
+            r#"kormir_signing_duration_seconds_bucket{type="numeric",le="0.01"} 0"#,
+            r#"kormir_signing_duration_seconds_bucket{type="numeric",le="0.025"} 1"#,
+            r#"kormir_signing_duration_seconds_bucket{type="numeric",le="+Inf"} 1"#,
+            r#"kormir_signing_duration_seconds_sum{type="numeric"} 0.02"#,
+            r#"kormir_signing_duration_seconds_count{type="numeric"} 1"#,
+        ] {
+            assert!(lines.iter().any(|l| l == expected), "missing {expected}");
+        }
+        assert!(!lines.iter().any(|l| l.contains(r#"type="enum""#)));
+    }
+
+    #[test]
+    fn test_publish_stats() {
+        let metrics = Metrics::new().unwrap();
+        let relay = RelayUrl::parse("wss://relay.example.com").unwrap();
+        let stats = |published, failed| {
+            HashMap::from([(relay.clone(), RelayPublishStats { published, failed })])
+        };
+
+        metrics.publish_stats(stats(3, 1));
+        // the totals only move forward between scrapes
+        metrics.publish_stats(stats(5, 1));
+
+        let lines = lines(&metrics);
+        for expected in [
+            r#"kormir_nostr_publish_total{relay="wss://relay.example.com",result="success"} 5"#,
+            r#"kormir_nostr_publish_total{relay="wss://relay.example.com",result="failure"} 1"#,
+        ] {
+            assert!(lines.iter().any(|l| l == expected), "missing {expected}");
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use super::event::Event;
+use super::schema::{event_activity, events};
+
+/// An announcement or attestation of an event
+#[derive(Queryable, Debug, Clone, PartialEq)]
+#[diesel(table_name = event_activity)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Activity {
+    pub id: i64,
+    pub event_id: String,
+    pub kind: String,
+    pub created_at: NaiveDateTime,
+}
+
+/// Filters for [`Activity::list_after`]
+#[derive(Debug, Clone, Default)]
+pub struct ActivityFilter {
+    pub event_id_prefix: Option<String>,
+    pub is_enum: Option<bool>,
+}
+
+impl ActivityFilter {
+    pub fn matches(&self, event_id: &str, is_enum: bool) -> bool {
+        self.event_id_prefix
+            .as_ref()
+            .is_none_or(|prefix| event_id.starts_with(prefix.as_str()))
+            && self.is_enum.is_none_or(|e| e == is_enum)
+    }
+}
+
+impl Activity {
+    /// Records the activity, returns its id.
+    pub fn insert(conn: &mut PgConnection, event_id: &str, kind: &str) -> anyhow::Result<i64> {
+        Ok(diesel::insert_into(event_activity::table)
+            .values((
+                event_activity::event_id.eq(event_id),
+                event_activity::kind.eq(kind),
+            ))
+            .returning(event_activity::id)
+            .get_result(conn)?)
+    }
+
+    /// The id of the latest activity, 0 when there is none.
+    pub fn latest_id(conn: &mut PgConnection) -> anyhow::Result<i64> {
+        Ok(event_activity::table
+            .select(diesel::dsl::max(event_activity::id))
+            .first::<Option<i64>>(conn)?
+            .unwrap_or(0))
+    }
+
+    /// The activity after the given id, oldest first, with its event.
+    pub fn list_after(
+        conn: &mut PgConnection,
+        after: i64,
+        filter: &ActivityFilter,
+        limit: i64,
+    ) -> anyhow::Result<Vec<(Self, Event)>> {
+        let mut query = event_activity::table
+            .inner_join(events::table)
+            .filter(event_activity::id.gt(after))
+            .into_boxed();
+
+        if let Some(prefix) = &filter.event_id_prefix {
+            let pattern = format!("{}%", super::event::escape_like(prefix));
+            query = query.filter(events::event_id.like(pattern).escape('\\'));
+        }
+        if let Some(is_enum) = filter.is_enum {
+            query = query.filter(events::is_enum.eq(is_enum));
+        }
+
+        Ok(query
+            .order_by(event_activity::id.asc())
+            .limit(limit)
+            .load::<(Self, Event)>(conn)?)
+    }
+}
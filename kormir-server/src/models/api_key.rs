@@ -0,0 +1,98 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::schema::api_keys;
+
+#[derive(
+    Queryable,
+    Insertable,
+    Identifiable,
+    AsChangeset,
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+#[diesel(primary_key(key_id))]
+#[diesel(table_name = api_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct ApiKey {
+    pub key_id: String,
+    secret: String,
+    pub scopes: Vec<String>,
+    pub event_namespace: Option<String>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = api_keys)]
+pub struct NewApiKey {
+    pub key_id: String,
+    pub secret: String,
+    pub scopes: Vec<String>,
+    pub event_namespace: Option<String>,
+}
+
+impl ApiKey {
+    /// The HMAC secret shared with the holder of the key
+    pub fn secret(&self) -> &[u8] {
+        self.secret.as_bytes()
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_at.is_some()
+    }
+
+    pub fn get(conn: &mut PgConnection, key_id: &str) -> anyhow::Result<Option<Self>> {
+        Ok(api_keys::table
+            .find(key_id)
+            .first::<Self>(conn)
+            .optional()?)
+    }
+
+    pub fn list(conn: &mut PgConnection) -> anyhow::Result<Vec<Self>> {
+        Ok(api_keys::table
+            .order_by(api_keys::created_at.asc())
+            .load::<Self>(conn)?)
+    }
+
+    /// Inserts the key, returns `None` if the key id is already used.
+    pub fn insert(conn: &mut PgConnection, key: NewApiKey) -> anyhow::Result<Option<Self>> {
+        Ok(diesel::insert_into(api_keys::table)
+            .values(&key)
+            .on_conflict_do_nothing()
+            .get_result::<Self>(conn)
+            .optional()?)
+    }
+
+    /// Replaces the secret of an active key.
+    pub fn rotate(
+        conn: &mut PgConnection,
+        key_id: &str,
+        secret: String,
+    ) -> anyhow::Result<Option<Self>> {
+        Ok(diesel::update(
+            api_keys::table
+                .find(key_id)
+                .filter(api_keys::revoked_at.is_null()),
+        )
+        .set(api_keys::secret.eq(secret))
+        .get_result::<Self>(conn)
+        .optional()?)
+    }
+
+    pub fn revoke(conn: &mut PgConnection, key_id: &str) -> anyhow::Result<Option<Self>> {
+        Ok(diesel::update(
+            api_keys::table
+                .find(key_id)
+                .filter(api_keys::revoked_at.is_null()),
+        )
+        .set(api_keys::revoked_at.eq(diesel::dsl::now))
+        .get_result::<Self>(conn)
+        .optional()?)
+    }
+}
@@ -0,0 +1,41 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::schema::attestation_approvals;
+
+#[derive(Queryable, Insertable, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[diesel(table_name = attestation_approvals)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Approval {
+    pub event_id: String,
+    pub key_id: String,
+    pub outcome: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = attestation_approvals)]
+pub struct NewApproval<'a> {
+    pub event_id: &'a str,
+    pub key_id: &'a str,
+    pub outcome: &'a str,
+}
+
+impl Approval {
+    /// Saves the approval, approving the same outcome twice is a no-op.
+    pub fn insert(conn: &mut PgConnection, approval: NewApproval) -> anyhow::Result<()> {
+        diesel::insert_into(attestation_approvals::table)
+            .values(&approval)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub fn list_by_event_id(conn: &mut PgConnection, event_id: &str) -> anyhow::Result<Vec<Self>> {
+        Ok(attestation_approvals::table
+            .filter(attestation_approvals::event_id.eq(event_id))
+            .order_by(attestation_approvals::created_at.asc())
+            .load::<Self>(conn)?)
+    }
+}
@@ -0,0 +1,85 @@
+use bitcoin::hashes::{sha256, Hash};
+use diesel::prelude::*;
+use kormir::audit::{AuditEntry, AuditRecord};
+
+use super::schema::audit_log;
+
+#[derive(Queryable, Insertable, Debug, Clone, PartialEq)]
+#[diesel(table_name = audit_log)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+struct AuditLogRow {
+    sequence: i64,
+    action: String,
+    event_id: String,
+    outcome: Option<String>,
+    key_id: Option<String>,
+    source: Option<String>,
+    recorded_at: i64,
+    result: String,
+    prev_hash: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+impl AuditLogRow {
+    fn from_entry(entry: &AuditEntry) -> Self {
+        Self {
+            sequence: entry.sequence as i64,
+            action: entry.record.action.clone(),
+            event_id: entry.record.event_id.clone(),
+            outcome: entry.record.outcome.clone(),
+            key_id: entry.record.key_id.clone(),
+            source: entry.record.source.clone(),
+            recorded_at: entry.record.timestamp as i64,
+            result: entry.record.result.clone(),
+            prev_hash: entry.prev_hash.to_byte_array().to_vec(),
+            hash: entry.hash.to_byte_array().to_vec(),
+        }
+    }
+
+    fn into_entry(self) -> anyhow::Result<AuditEntry> {
+        Ok(AuditEntry {
+            sequence: self.sequence as u64,
+            record: AuditRecord {
+                action: self.action,
+                event_id: self.event_id,
+                outcome: self.outcome,
+                key_id: self.key_id,
+                source: self.source,
+                timestamp: self.recorded_at as u64,
+                result: self.result,
+            },
+            prev_hash: sha256::Hash::from_slice(&self.prev_hash)?,
+            hash: sha256::Hash::from_slice(&self.hash)?,
+        })
+    }
+}
+
+/// Chains the record after the last entry of the log. Appends are serialized
+/// by locking the table, so this must run in a transaction.
+pub fn append(conn: &mut PgConnection, record: AuditRecord) -> anyhow::Result<AuditEntry> {
+    diesel::sql_query("LOCK TABLE audit_log IN EXCLUSIVE MODE").execute(conn)?;
+    let last = audit_log::table
+        .order_by(audit_log::sequence.desc())
+        .first::<AuditLogRow>(conn)
+        .optional()?
+        .map(AuditLogRow::into_entry)
+        .transpose()?;
+
+    let entry = AuditEntry::new(last.as_ref(), record);
+    diesel::insert_into(audit_log::table)
+        .values(AuditLogRow::from_entry(&entry))
+        .execute(conn)?;
+
+    Ok(entry)
+}
+
+pub fn list(conn: &mut PgConnection, from: u64, limit: usize) -> anyhow::Result<Vec<AuditEntry>> {
+    audit_log::table
+        .filter(audit_log::sequence.ge(i64::try_from(from).unwrap_or(i64::MAX)))
+        .order_by(audit_log::sequence.asc())
+        .limit(limit as i64)
+        .load::<AuditLogRow>(conn)?
+        .into_iter()
+        .map(AuditLogRow::into_entry)
+        .collect()
+}
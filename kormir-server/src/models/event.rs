@@ -5,7 +5,8 @@ use kormir::lightning::util::ser::Readable;
 use nostr::EventId;
 use serde::{Deserialize, Serialize};
 
-use super::schema::events;
+use super::schema::{events, webhook_deliveries};
+use super::{EventAction, EventFilter, EventStatus};
 
 #[derive(
     Queryable,
@@ -31,6 +32,12 @@ pub struct Event {
     created_at: chrono::NaiveDateTime,
     updated_at: chrono::NaiveDateTime,
     pub event_id: String,
+    pub withdrawn: bool,
+    pub maturity: i64,
+    pub attested: bool,
+    pub created_by: Option<String>,
+    pub attested_by: Option<String>,
+    pub withdrawn_by: Option<String>,
 }
 
 #[derive(Insertable, AsChangeset)]
@@ -41,6 +48,7 @@ pub struct NewEvent<'a> {
     pub oracle_event: Vec<u8>,
     pub name: &'a str,
     pub is_enum: bool,
+    pub maturity: i64,
 }
 
 impl Event {
@@ -80,6 +88,25 @@ impl Event {
             .optional()?)
     }
 
+    /// Locks the event row until the end of the transaction.
+    pub fn lock(conn: &mut PgConnection, event_id: &str) -> anyhow::Result<()> {
+        events::table
+            .find(event_id)
+            .select(events::event_id)
+            .for_update()
+            .first::<String>(conn)?;
+        Ok(())
+    }
+
+    /// Gets the event and locks its row until the end of the transaction.
+    pub fn get_for_update(conn: &mut PgConnection, event_id: &str) -> anyhow::Result<Option<Self>> {
+        Ok(events::table
+            .find(event_id)
+            .for_update()
+            .first::<Self>(conn)
+            .optional()?)
+    }
+
     pub fn get_by_name(conn: &mut PgConnection, name: &str) -> anyhow::Result<Option<Self>> {
         Ok(events::table
             .filter(events::name.eq(name))
@@ -87,7 +114,153 @@ impl Event {
             .optional()?)
     }
 
+    /// Withdraws the event unless it was attested, returns whether it was.
+    pub fn set_withdrawn(conn: &mut PgConnection, event_id: &str) -> anyhow::Result<bool> {
+        let updated = diesel::update(events::table.find(event_id))
+            .filter(events::attested.eq(false))
+            .set(events::withdrawn.eq(true))
+            .execute(conn)?;
+        Ok(updated > 0)
+    }
+
+    pub fn set_attested(conn: &mut PgConnection, event_id: &str) -> anyhow::Result<()> {
+        diesel::update(events::table.find(event_id))
+            .set(events::attested.eq(true))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    /// Records the credential that performed the action on the event.
+    pub fn set_actor(
+        conn: &mut PgConnection,
+        event_id: &str,
+        action: EventAction,
+        key_id: &str,
+    ) -> anyhow::Result<()> {
+        let update = diesel::update(events::table.find(event_id));
+        match action {
+            EventAction::Created => update.set(events::created_by.eq(key_id)).execute(conn)?,
+            EventAction::Attested => update.set(events::attested_by.eq(key_id)).execute(conn)?,
+            EventAction::Withdrawn => update.set(events::withdrawn_by.eq(key_id)).execute(conn)?,
+        };
+        Ok(())
+    }
+
+    pub fn count_matured_unsigned(conn: &mut PgConnection, now: u32) -> anyhow::Result<i64> {
+        Ok(events::table
+            .filter(events::attested.eq(false))
+            .filter(events::withdrawn.eq(false))
+            .filter(events::maturity.le(now as i64))
+            .count()
+            .get_result(conn)?)
+    }
+
+    /// Pending events that matured before `now` and have no `kind`
+    /// notification for the webhook yet, ordered by maturity.
+    pub fn list_matured_unnotified(
+        conn: &mut PgConnection,
+        since: u32,
+        now: u32,
+        webhook_url: &str,
+        kind: &str,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Self>> {
+        let notified = webhook_deliveries::table
+            .filter(webhook_deliveries::event_id.eq(events::event_id))
+            .filter(webhook_deliveries::webhook_url.eq(webhook_url))
+            .filter(webhook_deliveries::kind.eq(kind));
+
+        Ok(events::table
+            .filter(events::attested.eq(false))
+            .filter(events::withdrawn.eq(false))
+            .filter(events::maturity.ge(since as i64))
+            .filter(events::maturity.le(now as i64))
+            .filter(diesel::dsl::not(diesel::dsl::exists(notified)))
+            .order_by((events::maturity.asc(), events::event_id.asc()))
+            .limit(limit)
+            .load::<Self>(conn)?)
+    }
+
+    /// Returns a page of events ordered by maturity then event id.
+    pub fn list_filtered(
+        conn: &mut PgConnection,
+        filter: &EventFilter,
+    ) -> anyhow::Result<Vec<Self>> {
+        let mut query = events::table.into_boxed();
+
+        match filter.status {
+            Some(EventStatus::Pending) => {
+                query = query.filter(events::attested.eq(false).and(events::withdrawn.eq(false)))
+            }
+            Some(EventStatus::Attested) => query = query.filter(events::attested.eq(true)),
+            Some(EventStatus::Withdrawn) => query = query.filter(events::withdrawn.eq(true)),
+            None => {}
+        }
+        if let Some(from) = filter.maturity_from {
+            query = query.filter(events::maturity.ge(from as i64));
+        }
+        if let Some(until) = filter.maturity_until {
+            query = query.filter(events::maturity.lt(until as i64));
+        }
+        if let Some(is_enum) = filter.is_enum {
+            query = query.filter(events::is_enum.eq(is_enum));
+        }
+        if let Some(prefix) = &filter.event_id_prefix {
+            let pattern = format!("{}%", escape_like(prefix));
+            query = query.filter(events::event_id.like(pattern).escape('\\'));
+        }
+
+        if let Some((maturity, event_id)) = &filter.after {
+            let maturity = *maturity as i64;
+            let event_id = event_id.clone();
+            query = if filter.descending {
+                query.filter(
+                    events::maturity.lt(maturity).or(events::maturity
+                        .eq(maturity)
+                        .and(events::event_id.lt(event_id))),
+                )
+            } else {
+                query.filter(
+                    events::maturity.gt(maturity).or(events::maturity
+                        .eq(maturity)
+                        .and(events::event_id.gt(event_id))),
+                )
+            };
+        }
+
+        query = if filter.descending {
+            query.order_by((events::maturity.desc(), events::event_id.desc()))
+        } else {
+            query.order_by((events::maturity.asc(), events::event_id.asc()))
+        };
+
+        Ok(query.limit(filter.limit).load::<Self>(conn)?)
+    }
+
     pub fn list(conn: &mut PgConnection) -> anyhow::Result<Vec<Self>> {
-        Ok(events::table.load::<Self>(conn)?)
+        Ok(events::table
+            .order_by((events::maturity.asc(), events::event_id.asc()))
+            .load::<Self>(conn)?)
+    }
+}
+
+/// Escapes the LIKE wildcards so the prefix is matched literally.
+pub(super) fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod test {
+    // the benchmark includes the models without running their tests, so
+    // nothing is imported here
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(super::escape_like("btc-usd"), "btc-usd");
+        assert_eq!(super::escape_like("100%"), "100\\%");
+        assert_eq!(super::escape_like("btc_usd"), "btc\\_usd");
+        assert_eq!(super::escape_like("a\\%"), "a\\\\\\%");
     }
 }
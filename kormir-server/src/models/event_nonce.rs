@@ -78,4 +78,15 @@ impl EventNonce {
             .order_by(event_nonces::index.asc())
             .get_results(conn)?)
     }
+
+    /// Returns the nonces of all the given events, ordered by event and index.
+    pub fn get_by_event_ids(
+        conn: &mut PgConnection,
+        event_ids: &[String],
+    ) -> anyhow::Result<Vec<Self>> {
+        Ok(event_nonces::table
+            .filter(event_nonces::event_id.eq_any(event_ids))
+            .order_by((event_nonces::event_id.asc(), event_nonces::index.asc()))
+            .get_results(conn)?)
+    }
 }
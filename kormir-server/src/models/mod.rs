@@ -1,31 +1,111 @@
+use crate::models::activity::{Activity, ActivityFilter};
+use crate::models::api_key::{ApiKey, NewApiKey};
+use crate::models::approval::{Approval, NewApproval};
 use crate::models::event::{Event, NewEvent};
 use crate::models::event_nonce::{EventNonce, NewEventNonce};
+use crate::models::oracle_metadata::OracleMetadata;
+use crate::models::outbox::OutboxEntry;
+use crate::models::request_nonce::NewRequestNonce;
+use crate::models::webhook::{DeliveryFilter, NewWebhookDelivery, Webhook, WebhookDelivery};
 use anyhow::anyhow;
 use bitcoin::secp256k1::schnorr::Signature;
 use bitcoin::secp256k1::XOnlyPublicKey;
+use chrono::NaiveDateTime;
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool};
-use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use dlc_messages::oracle_msgs::{EventDescriptor, OracleAnnouncement};
+use kormir::audit::{AuditEntry, AuditRecord};
 use kormir::error::Error;
 use kormir::lightning::util::ser::Writeable;
-use kormir::storage::{OracleEventData, Storage};
+use kormir::storage::{OracleEventData, OutboxEvent, Storage};
 use nostr::EventId;
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
+pub mod activity;
+pub mod api_key;
+pub mod approval;
+mod audit_log;
 mod event;
 mod event_nonce;
 pub mod oracle_metadata;
+mod outbox;
+mod request_nonce;
 mod schema;
+pub mod webhook;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!();
 
+/// Lifecycle state of an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventStatus {
+    /// Neither attested nor withdrawn
+    Pending,
+    Attested,
+    Withdrawn,
+}
+
+/// An action performed on an event by an API credential
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventAction {
+    Created,
+    Attested,
+    Withdrawn,
+}
+
+/// Filters and pagination for [`PostgresStorage::list_events_filtered`]
+#[derive(Debug, Clone)]
+pub struct EventFilter {
+    pub status: Option<EventStatus>,
+    /// Only events maturing at or after this time
+    pub maturity_from: Option<u32>,
+    /// Only events maturing before this time
+    pub maturity_until: Option<u32>,
+    pub is_enum: Option<bool>,
+    pub event_id_prefix: Option<String>,
+    /// Sort by descending maturity instead of ascending
+    pub descending: bool,
+    /// Only events after this maturity and event id in the sort order
+    pub after: Option<(u32, String)>,
+    pub limit: i64,
+}
+
+/// In memory cache of attested events.
+///
+/// Attested events can no longer change apart from their nostr event ids, so
+/// setting those invalidates the entry. Once full, new events are not cached.
+#[derive(Clone)]
+struct EventCache {
+    entries: Arc<RwLock<HashMap<String, OracleEventData>>>,
+    capacity: usize,
+}
+
+impl EventCache {
+    fn get(&self, event_id: &str) -> Option<OracleEventData> {
+        self.entries.read().unwrap().get(event_id).cloned()
+    }
+
+    fn insert(&self, data: &OracleEventData) {
+        let mut entries = self.entries.write().unwrap();
+        if entries.len() < self.capacity {
+            entries.insert(data.event_id.clone(), data.clone());
+        }
+    }
+
+    fn remove(&self, event_id: &str) {
+        self.entries.write().unwrap().remove(event_id);
+    }
+}
+
 #[derive(Clone)]
 pub struct PostgresStorage {
     db_pool: Pool<ConnectionManager<PgConnection>>,
     oracle_public_key: XOnlyPublicKey,
     current_index: Arc<AtomicU32>,
+    cache: Option<EventCache>,
 }
 
 impl PostgresStorage {
@@ -40,52 +120,438 @@ impl PostgresStorage {
             db_pool,
             oracle_public_key,
             current_index: Arc::new(AtomicU32::new(current_index as u32)),
+            cache: None,
+        })
+    }
+
+    /// Keeps up to `capacity` attested events in memory, a capacity of 0
+    /// disables the cache.
+    pub fn with_cache(mut self, capacity: usize) -> Self {
+        self.cache = (capacity > 0).then(|| EventCache {
+            entries: Arc::new(RwLock::new(HashMap::with_capacity(capacity))),
+            capacity,
+        });
+        self
+    }
+
+    /// Connections of the database pool that are open and idle.
+    pub fn pool_state(&self) -> diesel::r2d2::State {
+        self.db_pool.state()
+    }
+
+    /// Most connections the database pool opens.
+    pub fn pool_max_size(&self) -> u32 {
+        self.db_pool.max_size()
+    }
+
+    /// Opens a connection and runs a trivial query, failing if the database
+    /// cannot be reached within `timeout`.
+    pub async fn ping(&self, timeout: Duration) -> Result<(), Error> {
+        let mut conn = self.db_pool.get_timeout(timeout).map_err(|e| {
+            log::error!("Failed to reach the database: {e}");
+            Error::StorageFailure
+        })?;
+        diesel::sql_query("SELECT 1")
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to query the database: {e}");
+                Error::StorageFailure
+            })?;
+        Ok(())
+    }
+
+    /// Whether some of the server's migrations have not been run.
+    pub async fn has_pending_migrations(&self) -> Result<bool, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        conn.has_pending_migration(MIGRATIONS).map_err(|e| {
+            log::error!("Failed to check pending migrations: {e}");
+            Error::StorageFailure
         })
     }
 
-    pub async fn list_events(&self) -> Result<Vec<OracleEventData>, Error> {
+    /// The oracle public key saved in the database's oracle metadata.
+    pub async fn metadata_public_key(&self) -> Result<Option<XOnlyPublicKey>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        let metadata = OracleMetadata::get(&mut conn).map_err(|e| {
+            log::error!("Failed to get oracle metadata: {e}");
+            Error::StorageFailure
+        })?;
+        Ok(metadata.map(|m| m.pubkey()))
+    }
+
+    /// Number of pending events that matured before `now`.
+    pub async fn count_matured_unsigned(&self, now: u32) -> Result<i64, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        Event::count_matured_unsigned(&mut conn, now).map_err(|e| {
+            log::error!("Failed to count matured events: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// Returns a page of events matching the filter, loading the nonces of
+    /// the whole page in a single query.
+    pub async fn list_events_filtered(
+        &self,
+        filter: &EventFilter,
+    ) -> Result<Vec<OracleEventData>, Error> {
         let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
 
         conn.transaction::<_, anyhow::Error, _>(|conn| {
-            let events = Event::list(conn)?;
+            let events = Event::list_filtered(conn, filter)?;
+            self.load_event_data(conn, events)
+        })
+        .map_err(|e| {
+            log::error!("Failed to list events: {e}");
+            Error::StorageFailure
+        })
+    }
 
-            let mut oracle_events = Vec::with_capacity(events.len());
-            for event in events {
-                let mut event_nonces = EventNonce::get_by_event_id(conn, event.event_id.clone())?;
-                event_nonces.sort_by_key(|nonce| nonce.index);
-
-                let indexes = event_nonces
-                    .iter()
-                    .map(|nonce| nonce.index as u32)
-                    .collect::<Vec<_>>();
-
-                let signatures = event_nonces
-                    .into_iter()
-                    .flat_map(|nonce| nonce.outcome_and_sig())
-                    .collect();
-
-                let announcement_event_id =
-                    event.announcement_event_id().map(|ann| ann.to_string());
-                let attestation_event_id = event.attestation_event_id().map(|att| att.to_string());
-
-                let data = OracleEventData {
-                    event_id: event.oracle_event().event_id,
-                    announcement: OracleAnnouncement {
-                        announcement_signature: event.announcement_signature(),
-                        oracle_public_key: self.oracle_public_key,
-                        oracle_event: event.oracle_event(),
-                    },
-                    indexes,
-                    signatures,
-                    announcement_event_id,
-                    attestation_event_id,
-                };
-                oracle_events.push(data);
+    /// Records the API credential that performed the action on the event.
+    pub async fn set_event_actor(
+        &self,
+        event_id: &str,
+        action: EventAction,
+        key_id: &str,
+    ) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        Event::set_actor(&mut conn, event_id, action, key_id).map_err(|e| {
+            log::error!("Failed to record {action:?} by {key_id} on {event_id}: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// Records the nonce of an authenticated request until it expires,
+    /// returns false if the key already used it.
+    pub async fn use_request_nonce(
+        &self,
+        key_id: &str,
+        nonce: &str,
+        expires_at: NaiveDateTime,
+    ) -> Result<bool, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        let nonce = NewRequestNonce {
+            key_id,
+            nonce,
+            expires_at,
+        };
+        nonce.insert(&mut conn).map_err(|e| {
+            log::error!("Failed to save request nonce: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// Deletes the request nonces that can no longer be replayed.
+    pub async fn delete_expired_request_nonces(&self) -> Result<usize, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        request_nonce::delete_expired(&mut conn).map_err(|e| {
+            log::error!("Failed to delete expired request nonces: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// Records the credential's approval of an outcome, returns all the
+    /// approvals of the event.
+    pub async fn add_approval(
+        &self,
+        event_id: &str,
+        key_id: &str,
+        outcome: &str,
+    ) -> Result<Vec<Approval>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        conn.transaction::<_, anyhow::Error, _>(|conn| {
+            // concurrent approvals of an event must see each other
+            Event::lock(conn, event_id)?;
+            let approval = NewApproval {
+                event_id,
+                key_id,
+                outcome,
+            };
+            Approval::insert(conn, approval)?;
+            Approval::list_by_event_id(conn, event_id)
+        })
+        .map_err(|e| {
+            log::error!("Failed to add approval: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    pub async fn list_approvals(&self, event_id: &str) -> Result<Vec<Approval>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        Approval::list_by_event_id(&mut conn, event_id).map_err(|e| {
+            log::error!("Failed to list approvals: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// Pending events that matured between `since` and `now` without a `kind`
+    /// notification for the webhook.
+    pub async fn list_matured_unnotified(
+        &self,
+        since: u32,
+        now: u32,
+        webhook_url: &str,
+        kind: &str,
+        limit: i64,
+    ) -> Result<Vec<OracleEventData>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        conn.transaction::<_, anyhow::Error, _>(|conn| {
+            let events =
+                Event::list_matured_unnotified(conn, since, now, webhook_url, kind, limit)?;
+            self.load_event_data(conn, events)
+        })
+        .map_err(|e| {
+            log::error!("Failed to list matured events: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// Records an announcement or attestation, returns the id of the activity.
+    pub async fn add_activity(&self, event_id: &str, kind: &str) -> Result<i64, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        Activity::insert(&mut conn, event_id, kind).map_err(|e| {
+            log::error!("Failed to add activity: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// The id of the latest activity, 0 when there is none.
+    pub async fn latest_activity_id(&self) -> Result<i64, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        Activity::latest_id(&mut conn).map_err(|e| {
+            log::error!("Failed to get the latest activity: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// The activity after the given id, oldest first, with the data of its event.
+    pub async fn list_activity(
+        &self,
+        after: i64,
+        filter: &ActivityFilter,
+        limit: i64,
+    ) -> Result<Vec<(Activity, OracleEventData)>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+
+        conn.transaction::<_, anyhow::Error, _>(|conn| {
+            let rows = Activity::list_after(conn, after, filter, limit)?;
+            // an event is listed twice once it is attested, load it once
+            let mut events = HashMap::new();
+            for (_, event) in &rows {
+                events
+                    .entry(event.event_id.clone())
+                    .or_insert_with(|| event.clone());
             }
+            let data = self.load_event_data(conn, events.into_values().collect())?;
+            let data = data
+                .into_iter()
+                .map(|d| (d.event_id.clone(), d))
+                .collect::<HashMap<_, _>>();
 
-            Ok(oracle_events)
+            Ok(rows
+                .into_iter()
+                .map(|(activity, _)| {
+                    let event = data[&activity.event_id].clone();
+                    (activity, event)
+                })
+                .collect())
+        })
+        .map_err(|e| {
+            log::error!("Failed to list activity: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// Records a configured webhook, returns when it was first configured.
+    pub async fn register_webhook(&self, url: &str) -> Result<Webhook, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        Webhook::register(&mut conn, url).map_err(|e| {
+            log::error!("Failed to register webhook: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// Queues a webhook notification, returns false if it was already queued.
+    pub async fn add_webhook_delivery(
+        &self,
+        delivery: NewWebhookDelivery<'_>,
+    ) -> Result<bool, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        WebhookDelivery::insert(&mut conn, delivery).map_err(|e| {
+            log::error!("Failed to add webhook delivery: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    pub async fn list_due_webhook_deliveries(
+        &self,
+        max_attempts: i32,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        WebhookDelivery::list_due(&mut conn, max_attempts, limit).map_err(|e| {
+            log::error!("Failed to list due webhook deliveries: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    pub async fn list_webhook_deliveries(
+        &self,
+        filter: &DeliveryFilter,
+        max_attempts: i32,
+    ) -> Result<Vec<WebhookDelivery>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        WebhookDelivery::list(&mut conn, filter, max_attempts).map_err(|e| {
+            log::error!("Failed to list webhook deliveries: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    pub async fn set_webhook_delivered(&self, id: i64) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        WebhookDelivery::set_delivered(&mut conn, id).map_err(|e| {
+            log::error!("Failed to mark webhook delivery as delivered: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    pub async fn set_webhook_failed(
+        &self,
+        id: i64,
+        error: &str,
+        next_attempt: NaiveDateTime,
+    ) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        WebhookDelivery::set_failed(&mut conn, id, error, next_attempt).map_err(|e| {
+            log::error!("Failed to mark webhook delivery as failed: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    pub async fn get_api_key(&self, key_id: &str) -> Result<Option<ApiKey>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        ApiKey::get(&mut conn, key_id).map_err(|e| {
+            log::error!("Failed to get api key: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    pub async fn list_api_keys(&self) -> Result<Vec<ApiKey>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        ApiKey::list(&mut conn).map_err(|e| {
+            log::error!("Failed to list api keys: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// Saves a new API key, returns `None` if the key id is already used.
+    pub async fn create_api_key(&self, key: NewApiKey) -> Result<Option<ApiKey>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        ApiKey::insert(&mut conn, key).map_err(|e| {
+            log::error!("Failed to create api key: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// Replaces the secret of an API key, returns `None` if there is no such
+    /// active key.
+    pub async fn rotate_api_key(
+        &self,
+        key_id: &str,
+        secret: String,
+    ) -> Result<Option<ApiKey>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        ApiKey::rotate(&mut conn, key_id, secret).map_err(|e| {
+            log::error!("Failed to rotate api key: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    /// Revokes an API key, returns `None` if there is no such key. Revoking
+    /// a revoked key leaves it unchanged.
+    pub async fn revoke_api_key(&self, key_id: &str) -> Result<Option<ApiKey>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        let res = match ApiKey::revoke(&mut conn, key_id) {
+            Ok(None) => ApiKey::get(&mut conn, key_id),
+            res => res,
+        };
+        res.map_err(|e| {
+            log::error!("Failed to revoke api key: {e}");
+            Error::StorageFailure
         })
-        .map_err(|_| Error::StorageFailure)
+    }
+
+    /// Builds the event data of the given rows, taking attested events from
+    /// the cache and loading the nonces of the others in a single query.
+    fn load_event_data(
+        &self,
+        conn: &mut PgConnection,
+        events: Vec<Event>,
+    ) -> anyhow::Result<Vec<OracleEventData>> {
+        let mut cached = HashMap::new();
+        if let Some(cache) = &self.cache {
+            for event in events.iter().filter(|e| e.attested) {
+                if let Some(data) = cache.get(&event.event_id) {
+                    cached.insert(event.event_id.clone(), data);
+                }
+            }
+        }
+
+        let missing = events
+            .iter()
+            .filter(|e| !cached.contains_key(&e.event_id))
+            .map(|e| e.event_id.clone())
+            .collect::<Vec<_>>();
+        let mut nonces: HashMap<String, Vec<EventNonce>> = HashMap::new();
+        if !missing.is_empty() {
+            for nonce in EventNonce::get_by_event_ids(conn, &missing)? {
+                if let Some(event_id) = nonce.event_id.clone() {
+                    nonces.entry(event_id).or_default().push(nonce);
+                }
+            }
+        }
+
+        Ok(events
+            .into_iter()
+            .map(|event| {
+                if let Some(data) = cached.remove(&event.event_id) {
+                    return data;
+                }
+                let attested = event.attested;
+                let event_nonces = nonces.remove(&event.event_id).unwrap_or_default();
+                let data = self.oracle_event_data(event, event_nonces);
+                if let Some(cache) = self.cache.as_ref().filter(|_| attested) {
+                    cache.insert(&data);
+                }
+                data
+            })
+            .collect())
+    }
+
+    /// Builds the event data from its row and its nonces sorted by index.
+    fn oracle_event_data(&self, event: Event, event_nonces: Vec<EventNonce>) -> OracleEventData {
+        let indexes = event_nonces
+            .iter()
+            .map(|nonce| nonce.index as u32)
+            .collect::<Vec<_>>();
+
+        let signatures = event_nonces
+            .into_iter()
+            .flat_map(|nonce| nonce.outcome_and_sig())
+            .collect();
+
+        OracleEventData {
+            announcement_event_id: event.announcement_event_id().map(|id| id.to_hex()),
+            attestation_event_id: event.attestation_event_id().map(|id| id.to_hex()),
+            announcement: OracleAnnouncement {
+                announcement_signature: event.announcement_signature(),
+                oracle_public_key: self.oracle_public_key,
+                oracle_event: event.oracle_event(),
+            },
+            event_id: event.event_id,
+            indexes,
+            signatures,
+            withdrawn: event.withdrawn,
+        }
     }
 
     // pub fn get_oracle_event_by_event_id(
@@ -124,55 +590,12 @@ impl PostgresStorage {
     //         attestation_event_id,
     //     }))
     // }
-
-    pub async fn add_announcement_event_id(
-        &self,
-        event_id: String,
-        nostr_event_id: EventId,
-    ) -> Result<(), Error> {
-        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
-
-        diesel::update(schema::events::table)
-            .filter(schema::events::event_id.eq(event_id))
-            .set(schema::events::announcement_event_id.eq(Some(nostr_event_id.as_bytes().to_vec())))
-            .execute(&mut conn)
-            .map_err(|e| {
-                log::error!("Failed to add announcement event id: {}", e);
-                Error::StorageFailure
-            })?;
-
-        Ok(())
-    }
-
-    pub async fn add_attestation_event_id(
-        &self,
-        event_id: String,
-        nostr_event_id: EventId,
-    ) -> Result<(), Error> {
-        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
-
-        diesel::update(schema::events::table)
-            .filter(schema::events::event_id.eq(event_id))
-            .set(schema::events::attestation_event_id.eq(Some(nostr_event_id.as_bytes().to_vec())))
-            .execute(&mut conn)
-            .map_err(|e| {
-                log::error!("Failed to add announcement event id: {}", e);
-                Error::StorageFailure
-            })?;
-
-        Ok(())
-    }
 }
 
 impl Storage for PostgresStorage {
     async fn get_next_nonce_indexes(&self, num: usize) -> Result<Vec<u32>, Error> {
-        let mut current_index = self.current_index.fetch_add(num as u32, Ordering::SeqCst);
-        let mut indexes = Vec::with_capacity(num);
-        for _ in 0..num {
-            indexes.push(current_index);
-            current_index += 1;
-        }
-        Ok(indexes)
+        let current_index = self.current_index.fetch_add(num as u32, Ordering::SeqCst);
+        Ok((current_index..current_index + num as u32).collect())
     }
 
     async fn save_announcement(
@@ -190,6 +613,7 @@ impl Storage for PostgresStorage {
             oracle_event: announcement.oracle_event.encode(),
             name: &announcement.oracle_event.event_id,
             is_enum,
+            maturity: announcement.oracle_event.event_maturity_epoch as i64,
         };
 
         let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
@@ -197,7 +621,17 @@ impl Storage for PostgresStorage {
             let event_id: String = diesel::insert_into(schema::events::table)
                 .values(&new_event)
                 .returning(schema::events::event_id)
-                .get_result(conn)?;
+                .get_result(conn)
+                .map_err(|e| match e {
+                    // a concurrent create inserted the same event first
+                    diesel::result::Error::DatabaseError(
+                        diesel::result::DatabaseErrorKind::UniqueViolation,
+                        _,
+                    ) => anyhow::Error::from(Error::EventAlreadyExists {
+                        event_id: new_event.event_id.clone(),
+                    }),
+                    e => e.into(),
+                })?;
 
             let new_event_nonces = indexes
                 .into_iter()
@@ -216,7 +650,13 @@ impl Storage for PostgresStorage {
 
             Ok(event_id)
         })
-        .map_err(|_| Error::StorageFailure)
+        .map_err(|e| match e.downcast::<Error>() {
+            Ok(e) => e,
+            Err(e) => {
+                log::error!("Failed to save announcement: {e}");
+                Error::StorageFailure
+            }
+        })
     }
 
     async fn save_signatures(
@@ -227,8 +667,16 @@ impl Storage for PostgresStorage {
         let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
 
         conn.transaction(|conn| {
-            let event =
-                Event::get_by_event_id(conn, event_id.clone())?.ok_or(anyhow!("Not Found"))?;
+            // a concurrent withdrawal waits for the signatures, or the other way around
+            let event = Event::get_for_update(conn, &event_id)?.ok_or(Error::NotFound {
+                event_id: event_id.clone(),
+            })?;
+            if event.withdrawn {
+                return Err(Error::EventWithdrawn { event_id }.into());
+            }
+            if event.attested {
+                return Err(Error::EventAlreadySigned { event_id }.into());
+            }
 
             let mut event_nonces = EventNonce::get_by_event_id(conn, event_id.clone())?;
             if event_nonces.len() != signatures.len() {
@@ -248,6 +696,7 @@ impl Storage for PostgresStorage {
                     Ok(nonce.id as u32)
                 })
                 .collect::<anyhow::Result<Vec<_>>>()?;
+            Event::set_attested(conn, &event_id)?;
 
             Ok(OracleEventData {
                 event_id,
@@ -258,47 +707,150 @@ impl Storage for PostgresStorage {
                 },
                 indexes,
                 signatures,
+                withdrawn: event.withdrawn,
                 announcement_event_id: event.announcement_event_id().map(|id| id.to_hex()),
                 attestation_event_id: event.attestation_event_id().map(|id| id.to_hex()),
             })
         })
-        .map_err(|_| Error::StorageFailure)
+        .map_err(|e: anyhow::Error| match e.downcast::<Error>() {
+            Ok(e) => e,
+            Err(e) => {
+                log::error!("Failed to save signatures: {e}");
+                Error::StorageFailure
+            }
+        })
     }
 
     async fn get_event(&self, event_id: String) -> Result<Option<OracleEventData>, Error> {
+        if let Some(data) = self.cache.as_ref().and_then(|c| c.get(&event_id)) {
+            return Ok(Some(data));
+        }
+
         let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
 
         conn.transaction::<_, anyhow::Error, _>(|conn| {
-            let Some(event) = Event::get_by_event_id(conn, event_id.clone())? else {
+            let Some(event) = Event::get_by_event_id(conn, event_id)? else {
                 return Ok(None);
             };
+            Ok(self.load_event_data(conn, vec![event])?.pop())
+        })
+        .map_err(|_| Error::StorageFailure)
+    }
 
-            let mut event_nonces = EventNonce::get_by_event_id(conn, event_id.clone())?;
-            event_nonces.sort_by_key(|nonce| nonce.index);
+    async fn list_events(&self) -> Result<Vec<OracleEventData>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
 
-            let indexes = event_nonces
-                .iter()
-                .map(|nonce| nonce.index as u32)
-                .collect::<Vec<_>>();
+        conn.transaction::<_, anyhow::Error, _>(|conn| {
+            let events = Event::list(conn)?;
+            self.load_event_data(conn, events)
+        })
+        .map_err(|_| Error::StorageFailure)
+    }
 
-            let signatures = event_nonces
-                .into_iter()
-                .flat_map(|nonce| nonce.outcome_and_sig())
-                .collect();
+    async fn withdraw_event(&self, event_id: String) -> Result<OracleEventData, Error> {
+        {
+            let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+            let withdrawn = Event::set_withdrawn(&mut conn, &event_id).map_err(|e| {
+                log::error!("Failed to withdraw event: {}", e);
+                Error::StorageFailure
+            })?;
+            // the event was attested since it was checked, or does not exist
+            if !withdrawn {
+                return match self.get_event(event_id.clone()).await? {
+                    Some(_) => Err(Error::EventAlreadySigned { event_id }),
+                    None => Err(Error::NotFound { event_id }),
+                };
+            }
+        }
 
-            Ok(Some(OracleEventData {
-                event_id,
-                announcement: OracleAnnouncement {
-                    announcement_signature: event.announcement_signature(),
-                    oracle_public_key: self.oracle_public_key,
-                    oracle_event: event.oracle_event(),
-                },
-                indexes,
-                signatures,
-                announcement_event_id: event.announcement_event_id().map(|id| id.to_hex()),
-                attestation_event_id: event.attestation_event_id().map(|id| id.to_hex()),
-            }))
+        self.get_event(event_id.clone())
+            .await?
+            .ok_or(Error::NotFound { event_id })
+    }
+
+    async fn append_audit_record(&self, record: AuditRecord) -> Result<AuditEntry, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        conn.transaction(|conn| audit_log::append(conn, record))
+            .map_err(|e| {
+                log::error!("Failed to append to the audit log: {e}");
+                Error::StorageFailure
+            })
+    }
+
+    async fn list_audit_entries(&self, from: u64, limit: usize) -> Result<Vec<AuditEntry>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+        audit_log::list(&mut conn, from, limit).map_err(|e| {
+            log::error!("Failed to list the audit log: {e}");
+            Error::StorageFailure
+        })
+    }
+
+    async fn add_announcement_event_id(
+        &self,
+        event_id: String,
+        nostr_event_id: EventId,
+    ) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+
+        diesel::update(schema::events::table)
+            .filter(schema::events::event_id.eq(&event_id))
+            .set(schema::events::announcement_event_id.eq(Some(nostr_event_id.as_bytes().to_vec())))
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to add announcement event id: {}", e);
+                Error::StorageFailure
+            })?;
+        if let Some(cache) = &self.cache {
+            cache.remove(&event_id);
+        }
+
+        Ok(())
+    }
+
+    async fn add_attestation_event_id(
+        &self,
+        event_id: String,
+        nostr_event_id: EventId,
+    ) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+
+        diesel::update(schema::events::table)
+            .filter(schema::events::event_id.eq(&event_id))
+            .set(schema::events::attestation_event_id.eq(Some(nostr_event_id.as_bytes().to_vec())))
+            .execute(&mut conn)
+            .map_err(|e| {
+                log::error!("Failed to add attestation event id: {}", e);
+                Error::StorageFailure
+            })?;
+        if let Some(cache) = &self.cache {
+            cache.remove(&event_id);
+        }
+
+        Ok(())
+    }
+
+    async fn save_outbox_event(&self, event: OutboxEvent) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+
+        OutboxEntry::upsert(&mut conn, event.into()).map_err(|e| {
+            log::error!("Failed to save outbox event: {}", e);
+            Error::StorageFailure
+        })
+    }
+
+    async fn list_outbox_events(&self) -> Result<Vec<OutboxEvent>, Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+
+        let entries = OutboxEntry::list(&mut conn).map_err(|_| Error::StorageFailure)?;
+        Ok(entries.iter().map(|e| e.outbox_event()).collect())
+    }
+
+    async fn remove_outbox_event(&self, nostr_event_id: EventId) -> Result<(), Error> {
+        let mut conn = self.db_pool.get().map_err(|_| Error::StorageFailure)?;
+
+        OutboxEntry::delete(&mut conn, nostr_event_id.as_bytes()).map_err(|e| {
+            log::error!("Failed to remove outbox event: {}", e);
+            Error::StorageFailure
         })
-        .map_err(|_| Error::StorageFailure)
     }
 }
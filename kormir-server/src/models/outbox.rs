@@ -0,0 +1,92 @@
+use chrono::{DateTime, NaiveDateTime};
+use diesel::prelude::*;
+use kormir::storage::OutboxEvent;
+use nostr::{Event, JsonUtil, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use super::schema::nostr_outbox;
+
+#[derive(
+    Queryable,
+    Insertable,
+    Identifiable,
+    AsChangeset,
+    Serialize,
+    Deserialize,
+    Debug,
+    Clone,
+    PartialEq,
+)]
+#[diesel(primary_key(nostr_event_id))]
+#[diesel(table_name = nostr_outbox)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct OutboxEntry {
+    nostr_event_id: Vec<u8>,
+    pub event_id: String,
+    nostr_event: String,
+    pub attempts: i32,
+    next_attempt: NaiveDateTime,
+    pub last_error: Option<String>,
+    created_at: NaiveDateTime,
+    updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = nostr_outbox)]
+#[diesel(treat_none_as_null = true)]
+pub struct NewOutboxEntry {
+    pub nostr_event_id: Vec<u8>,
+    pub event_id: String,
+    pub nostr_event: String,
+    pub attempts: i32,
+    pub next_attempt: NaiveDateTime,
+    pub last_error: Option<String>,
+}
+
+impl From<OutboxEvent> for NewOutboxEntry {
+    fn from(value: OutboxEvent) -> Self {
+        Self {
+            nostr_event_id: value.nostr_event.id.as_bytes().to_vec(),
+            event_id: value.event_id,
+            nostr_event: value.nostr_event.as_json(),
+            attempts: value.attempts as i32,
+            next_attempt: DateTime::from_timestamp(value.next_attempt.as_u64() as i64, 0)
+                .expect("valid timestamp")
+                .naive_utc(),
+            last_error: value.last_error,
+        }
+    }
+}
+
+impl OutboxEntry {
+    pub fn outbox_event(&self) -> OutboxEvent {
+        OutboxEvent {
+            event_id: self.event_id.clone(),
+            nostr_event: Event::from_json(&self.nostr_event).expect("invalid nostr event"),
+            attempts: self.attempts as u32,
+            next_attempt: Timestamp::from(self.next_attempt.and_utc().timestamp() as u64),
+            last_error: self.last_error.clone(),
+        }
+    }
+
+    pub fn upsert(conn: &mut PgConnection, entry: NewOutboxEntry) -> anyhow::Result<()> {
+        diesel::insert_into(nostr_outbox::table)
+            .values(&entry)
+            .on_conflict(nostr_outbox::nostr_event_id)
+            .do_update()
+            .set(&entry)
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub fn list(conn: &mut PgConnection) -> anyhow::Result<Vec<Self>> {
+        Ok(nostr_outbox::table
+            .order_by(nostr_outbox::created_at.asc())
+            .load::<Self>(conn)?)
+    }
+
+    pub fn delete(conn: &mut PgConnection, nostr_event_id: &[u8]) -> anyhow::Result<()> {
+        diesel::delete(nostr_outbox::table.find(nostr_event_id)).execute(conn)?;
+        Ok(())
+    }
+}
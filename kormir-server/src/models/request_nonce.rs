@@ -0,0 +1,31 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+
+use super::schema::request_nonces;
+
+#[derive(Insertable)]
+#[diesel(table_name = request_nonces)]
+pub struct NewRequestNonce<'a> {
+    pub key_id: &'a str,
+    pub nonce: &'a str,
+    pub expires_at: NaiveDateTime,
+}
+
+impl NewRequestNonce<'_> {
+    /// Saves the nonce, returns false if the key already used it.
+    pub fn insert(&self, conn: &mut PgConnection) -> anyhow::Result<bool> {
+        let inserted = diesel::insert_into(request_nonces::table)
+            .values(self)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(inserted == 1)
+    }
+}
+
+/// Deletes the nonces that expired, returns how many were deleted.
+pub fn delete_expired(conn: &mut PgConnection) -> anyhow::Result<usize> {
+    Ok(diesel::delete(
+        request_nonces::table.filter(request_nonces::expires_at.lt(diesel::dsl::now)),
+    )
+    .execute(conn)?)
+}
@@ -1,5 +1,50 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    attestation_approvals (event_id, key_id, outcome) {
+        event_id -> Text,
+        key_id -> Text,
+        outcome -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    api_keys (key_id) {
+        key_id -> Text,
+        secret -> Text,
+        scopes -> Array<Text>,
+        event_namespace -> Nullable<Text>,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    audit_log (sequence) {
+        sequence -> Int8,
+        action -> Text,
+        event_id -> Text,
+        outcome -> Nullable<Text>,
+        key_id -> Nullable<Text>,
+        source -> Nullable<Text>,
+        recorded_at -> Int8,
+        result -> Text,
+        prev_hash -> Bytea,
+        hash -> Bytea,
+    }
+}
+
+diesel::table! {
+    event_activity (id) {
+        id -> Int8,
+        event_id -> Text,
+        kind -> Text,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     event_nonces (id) {
         id -> Int4,
@@ -24,6 +69,25 @@ diesel::table! {
         created_at -> Timestamp,
         updated_at -> Timestamp,
         event_id -> Text,
+        withdrawn -> Bool,
+        maturity -> Int8,
+        attested -> Bool,
+        created_by -> Nullable<Text>,
+        attested_by -> Nullable<Text>,
+        withdrawn_by -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    nostr_outbox (nostr_event_id) {
+        nostr_event_id -> Bytea,
+        event_id -> Text,
+        nostr_event -> Text,
+        attempts -> Int4,
+        next_attempt -> Timestamp,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -37,6 +101,53 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    request_nonces (key_id, nonce) {
+        key_id -> Text,
+        nonce -> Text,
+        expires_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Int8,
+        webhook_url -> Text,
+        event_id -> Text,
+        kind -> Text,
+        payload -> Text,
+        attempts -> Int4,
+        next_attempt -> Timestamp,
+        last_error -> Nullable<Text>,
+        delivered_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    webhooks (url) {
+        url -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::joinable!(attestation_approvals -> events (event_id));
+diesel::joinable!(event_activity -> events (event_id));
 diesel::joinable!(event_nonces -> events (event_id));
+diesel::joinable!(nostr_outbox -> events (event_id));
+diesel::joinable!(webhook_deliveries -> events (event_id));
 
-diesel::allow_tables_to_appear_in_same_query!(event_nonces, events, oracle_metadata,);
+diesel::allow_tables_to_appear_in_same_query!(
+    api_keys,
+    attestation_approvals,
+    audit_log,
+    event_activity,
+    event_nonces,
+    events,
+    nostr_outbox,
+    oracle_metadata,
+    request_nonces,
+    webhook_deliveries,
+    webhooks,
+);
@@ -0,0 +1,166 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+
+use super::schema::{webhook_deliveries, webhooks};
+
+/// Delivery state of a webhook notification
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Not delivered yet and will be retried
+    Pending,
+    Delivered,
+    /// Ran out of attempts
+    Failed,
+}
+
+#[derive(Queryable, Serialize, Debug, Clone, PartialEq)]
+#[diesel(table_name = webhook_deliveries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub webhook_url: String,
+    pub event_id: String,
+    pub kind: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub next_attempt: NaiveDateTime,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct NewWebhookDelivery<'a> {
+    pub webhook_url: &'a str,
+    pub event_id: &'a str,
+    pub kind: &'a str,
+    pub payload: &'a str,
+}
+
+/// A webhook the server was configured with
+#[derive(Queryable, Debug, Clone, PartialEq)]
+#[diesel(table_name = webhooks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Webhook {
+    pub url: String,
+    /// When the server was first configured with the webhook
+    pub created_at: NaiveDateTime,
+}
+
+impl Webhook {
+    /// Records the webhook if it is new, returns it either way.
+    pub fn register(conn: &mut PgConnection, url: &str) -> anyhow::Result<Self> {
+        diesel::insert_into(webhooks::table)
+            .values(webhooks::url.eq(url))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(webhooks::table.find(url).first::<Self>(conn)?)
+    }
+}
+
+/// Filters for [`WebhookDelivery::list`]
+#[derive(Debug, Clone)]
+pub struct DeliveryFilter {
+    pub status: Option<DeliveryStatus>,
+    pub event_id: Option<String>,
+    /// Only deliveries with a smaller id, newest first
+    pub before: Option<i64>,
+    pub limit: i64,
+}
+
+impl WebhookDelivery {
+    /// Queues the notification, returns false if the webhook was already
+    /// notified of this change of the event.
+    pub fn insert(conn: &mut PgConnection, delivery: NewWebhookDelivery) -> anyhow::Result<bool> {
+        let inserted = diesel::insert_into(webhook_deliveries::table)
+            .values(&delivery)
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(inserted == 1)
+    }
+
+    /// Undelivered notifications whose retry time has come, oldest first.
+    pub fn list_due(
+        conn: &mut PgConnection,
+        max_attempts: i32,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Self>> {
+        Ok(webhook_deliveries::table
+            .filter(webhook_deliveries::delivered_at.is_null())
+            .filter(webhook_deliveries::attempts.lt(max_attempts))
+            .filter(webhook_deliveries::next_attempt.le(diesel::dsl::now))
+            .order_by(webhook_deliveries::id.asc())
+            .limit(limit)
+            .load::<Self>(conn)?)
+    }
+
+    pub fn list(
+        conn: &mut PgConnection,
+        filter: &DeliveryFilter,
+        max_attempts: i32,
+    ) -> anyhow::Result<Vec<Self>> {
+        let mut query = webhook_deliveries::table.into_boxed();
+
+        match filter.status {
+            Some(DeliveryStatus::Pending) => {
+                query = query.filter(
+                    webhook_deliveries::delivered_at
+                        .is_null()
+                        .and(webhook_deliveries::attempts.lt(max_attempts)),
+                )
+            }
+            Some(DeliveryStatus::Delivered) => {
+                query = query.filter(webhook_deliveries::delivered_at.is_not_null())
+            }
+            Some(DeliveryStatus::Failed) => {
+                query = query.filter(
+                    webhook_deliveries::delivered_at
+                        .is_null()
+                        .and(webhook_deliveries::attempts.ge(max_attempts)),
+                )
+            }
+            None => {}
+        }
+        if let Some(event_id) = &filter.event_id {
+            query = query.filter(webhook_deliveries::event_id.eq(event_id.clone()));
+        }
+        if let Some(before) = filter.before {
+            query = query.filter(webhook_deliveries::id.lt(before));
+        }
+
+        Ok(query
+            .order_by(webhook_deliveries::id.desc())
+            .limit(filter.limit)
+            .load::<Self>(conn)?)
+    }
+
+    pub fn set_delivered(conn: &mut PgConnection, id: i64) -> anyhow::Result<()> {
+        diesel::update(webhook_deliveries::table.find(id))
+            .set((
+                webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1),
+                webhook_deliveries::delivered_at.eq(diesel::dsl::now),
+                webhook_deliveries::last_error.eq(None::<String>),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub fn set_failed(
+        conn: &mut PgConnection,
+        id: i64,
+        error: &str,
+        next_attempt: NaiveDateTime,
+    ) -> anyhow::Result<()> {
+        diesel::update(webhook_deliveries::table.find(id))
+            .set((
+                webhook_deliveries::attempts.eq(webhook_deliveries::attempts + 1),
+                webhook_deliveries::next_attempt.eq(next_attempt),
+                webhook_deliveries::last_error.eq(error),
+            ))
+            .execute(conn)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,477 @@
+//! OpenAPI document of the server, served at `/openapi.json`.
+//!
+//! The operations are declared on the route handlers with `#[utoipa::path]`
+//! and the schemas are derived from the request and response types, the DLC
+//! messages are described by [`kormir::schema`]. The
+//! routes are added through [`ApiRouter`], which records them so the tests
+//! can check that the document describes exactly the routes that are served.
+//! The document is also checked in as `kormir-server/openapi.json` for
+//! generating clients, run the tests with `UPDATE_OPENAPI=1` to update it.
+
+use crate::json_models::*;
+use crate::routes::*;
+use axum::handler::Handler;
+use axum::http::Method;
+use axum::routing::{self, MethodRouter};
+use axum::Router;
+use serde::Serialize;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi, ToSchema};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Kormir",
+        description = "DLC oracle announcing and attesting events over HTTP and nostr"
+    ),
+    paths(
+        health_check,
+        liveness_check,
+        readiness_check,
+        get_metrics,
+        get_openapi,
+        get_pubkey,
+        list_events,
+        stream_events,
+        get_oracle_announcement,
+        get_oracle_attestation,
+        create_enum_event,
+        create_numeric_event,
+        sign_enum_event,
+        sign_numeric_event,
+        withdraw_event,
+        get_approvals,
+        list_outbox,
+        flush_outbox,
+        list_api_keys,
+        create_api_key,
+        rotate_api_key,
+        revoke_api_key,
+        list_webhook_deliveries,
+        list_audit_log,
+        verify_audit_log,
+    ),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "events", description = "Announcements and attestations of the oracle"),
+        (name = "oracle", description = "Creating and attesting events"),
+        (name = "admin", description = "Administration of the server"),
+        (name = "health", description = "Health checks and metrics"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Security schemes of the authenticated routes, see [`crate::auth`]
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "hmac",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-Signature",
+                "Hex HMAC-SHA256 of the request, sent with the X-Key-Id, X-Timestamp \
+                 and X-Nonce headers",
+            ))),
+        );
+        components.add_security_scheme(
+            "nip98",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "Authorization",
+                "NIP-98 `Nostr <base64 event>` of an operator in KORMIR_NOSTR_OPERATORS",
+            ))),
+        );
+    }
+}
+
+/// Body of `/list-events`, depending on the `format` parameter
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum EventList {
+    Json(Vec<JsonEventResponse>),
+    Hex(Vec<HexEventResponse>),
+    Tlv(Vec<TLVEventResponse>),
+    Base64(Vec<Base64EventResponse>),
+}
+
+/// Router that records the method and path of its routes
+#[derive(Default)]
+pub struct ApiRouter {
+    router: Router,
+    #[cfg_attr(not(test), allow(dead_code))]
+    routes: Vec<(Method, &'static str)>,
+}
+
+impl ApiRouter {
+    pub fn get<H: Handler<T, ()>, T: 'static>(self, path: &'static str, handler: H) -> Self {
+        self.route(Method::GET, path, routing::get(handler))
+    }
+
+    pub fn post<H: Handler<T, ()>, T: 'static>(self, path: &'static str, handler: H) -> Self {
+        self.route(Method::POST, path, routing::post(handler))
+    }
+
+    fn route(mut self, method: Method, path: &'static str, route: MethodRouter) -> Self {
+        self.router = self.router.route(path, route);
+        self.routes.push((method, path));
+        self
+    }
+
+    /// The routes as `METHOD /path`, with the path parameters written like
+    /// in the document.
+    #[cfg(test)]
+    fn routes(&self) -> Vec<String> {
+        self.routes
+            .iter()
+            .map(|(method, path)| {
+                let path = path
+                    .split('/')
+                    .map(|segment| match segment.strip_prefix(':') {
+                        Some(param) => format!("{{{param}}}"),
+                        None => segment.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("/");
+                format!("{method} {path}")
+            })
+            .collect()
+    }
+}
+
+impl From<ApiRouter> for Router {
+    fn from(router: ApiRouter) -> Self {
+        router.router
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::approvals::ApprovalStatus;
+    use crate::error::{ApiError, ErrorResponse};
+    use crate::models::approval::Approval;
+    use crate::models::webhook::WebhookDelivery;
+    use crate::{authenticated_routes, public_routes};
+    use bitcoin::secp256k1::SecretKey;
+    use chrono::NaiveDateTime;
+    use kormir::audit::{AuditAction, AuditEntry, AuditRecord, RESULT_OK};
+    use kormir::nostr::{EventBuilder, Keys, Timestamp};
+    use kormir::nostr_publisher::FlushResult;
+    use kormir::schema as dlc;
+    use kormir::storage::{MemoryStorage, OutboxEvent, Storage};
+    use kormir::Oracle;
+    use serde_json::{json, Value};
+    use std::collections::BTreeSet;
+    use utoipa::openapi::path::Operation;
+
+    /// The operations of the document as `METHOD /path`
+    fn operations(spec: &utoipa::openapi::OpenApi) -> Vec<(String, &Operation)> {
+        let mut operations = vec![];
+        for (path, item) in &spec.paths.paths {
+            for (method, operation) in [(Method::GET, &item.get), (Method::POST, &item.post)] {
+                if let Some(operation) = operation {
+                    operations.push((format!("{method} {path}"), operation));
+                }
+            }
+        }
+        operations
+    }
+
+    #[test]
+    fn test_spec_matches_router() {
+        let spec = ApiDoc::openapi();
+        let public = public_routes().routes();
+        let authenticated = authenticated_routes().routes();
+
+        let routed = public.iter().chain(&authenticated).collect::<BTreeSet<_>>();
+        let documented = operations(&spec)
+            .into_iter()
+            .map(|(route, _)| route)
+            .collect::<Vec<_>>();
+        let documented = documented.iter().collect::<BTreeSet<_>>();
+        assert_eq!(
+            routed.difference(&documented).collect::<Vec<_>>(),
+            Vec::<&&String>::new(),
+            "routes missing from the OpenAPI document"
+        );
+        assert_eq!(
+            documented.difference(&routed).collect::<Vec<_>>(),
+            Vec::<&&String>::new(),
+            "documented operations that are not routed"
+        );
+
+        // the authenticated routes, and only them, need a signature
+        for (route, operation) in operations(&spec) {
+            let secured = operation.security.as_ref().is_some_and(|s| !s.is_empty());
+            assert_eq!(
+                secured,
+                authenticated.contains(&route),
+                "security of {route}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_spec_is_up_to_date() {
+        let spec = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+        let file = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+        if std::env::var("UPDATE_OPENAPI").is_ok() {
+            std::fs::write(file, &spec).unwrap();
+        }
+        let saved = std::fs::read_to_string(file).unwrap_or_default();
+        assert!(
+            saved == spec,
+            "openapi.json is out of date, run the tests with UPDATE_OPENAPI=1"
+        );
+    }
+
+    /// Follows the reference of a schema, if it is one.
+    fn resolve<'a>(spec: &'a Value, schema: &'a Value, at: &str) -> Result<&'a Value, String> {
+        match schema.get("$ref").and_then(|r| r.as_str()) {
+            Some(reference) => {
+                let name = reference.trim_start_matches("#/components/schemas/");
+                let schema = &spec["components"]["schemas"][name];
+                if schema.is_null() {
+                    return Err(format!("{at}: missing schema {name}"));
+                }
+                resolve(spec, schema, at)
+            }
+            None => Ok(schema),
+        }
+    }
+
+    /// Checks that the keys of the objects in `value` are the properties of
+    /// their schema.
+    fn check_schema(spec: &Value, schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+        let schema = resolve(spec, schema, at)?;
+        if value.is_null() {
+            return Ok(());
+        }
+        if let Some(variants) = schema.get("oneOf").and_then(|v| v.as_array()) {
+            return variants
+                .iter()
+                .find_map(|variant| check_schema(spec, variant, value, at).ok())
+                .ok_or_else(|| format!("{at}: no variant matches {value}"));
+        }
+        if let Some(parts) = schema.get("allOf").and_then(|v| v.as_array()) {
+            // a flattened struct has the properties of all its parts
+            let mut merged = json!({ "properties": {}, "required": [] });
+            for part in parts {
+                let part = resolve(spec, part, at)?;
+                for (key, property) in part["properties"].as_object().into_iter().flatten() {
+                    merged["properties"][key] = property.clone();
+                }
+                for required in part["required"].as_array().into_iter().flatten() {
+                    merged["required"]
+                        .as_array_mut()
+                        .unwrap()
+                        .push(required.clone());
+                }
+            }
+            return check_schema(spec, &merged, value, at);
+        }
+        if let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) {
+            let object = value
+                .as_object()
+                .ok_or_else(|| format!("{at}: not an object"))?;
+            for (key, field) in object {
+                let property = properties
+                    .get(key)
+                    .ok_or_else(|| format!("{at}: {key} is not documented"))?;
+                check_schema(spec, property, field, &format!("{at}.{key}"))?;
+            }
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let required = required.as_str().unwrap_or_default();
+                if !object.contains_key(required) {
+                    return Err(format!("{at}: missing {required}"));
+                }
+            }
+        }
+        if let Some(properties) = schema.get("additionalProperties").filter(|p| p.is_object()) {
+            let object = value
+                .as_object()
+                .ok_or_else(|| format!("{at}: not an object"))?;
+            for (key, field) in object {
+                check_schema(spec, properties, field, &format!("{at}.{key}"))?;
+            }
+        }
+        if let Some(items) = schema.get("items") {
+            let array = value
+                .as_array()
+                .ok_or_else(|| format!("{at}: not an array"))?;
+            for (i, item) in array.iter().enumerate() {
+                check_schema(spec, items, item, &format!("{at}[{i}]"))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_response<T: ToSchema>(spec: &Value, value: impl Serialize) {
+        let name = T::name();
+        let schema = json!({ "$ref": format!("#/components/schemas/{name}") });
+        let value = serde_json::to_value(value).unwrap();
+        if let Err(e) = check_schema(spec, &schema, &value, &name) {
+            panic!("{e}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_schemas_match_responses() {
+        let spec = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let signing_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let oracle = Oracle::from_signing_key(MemoryStorage::default(), signing_key).unwrap();
+
+        let outcomes = vec!["a".to_string(), "b".to_string()];
+        let announcement = oracle
+            .create_enum_event("enum".to_string(), outcomes, 0)
+            .await
+            .unwrap();
+        check_response::<dlc::OracleAnnouncement>(&spec, announcement);
+        let attestation = oracle
+            .sign_enum_event("enum".to_string(), "a".to_string())
+            .await
+            .unwrap();
+        check_response::<dlc::OracleAttestation>(&spec, attestation);
+        let announcement = oracle
+            .create_numeric_event("numeric".to_string(), 8, true, 0, "BTC".to_string(), 0)
+            .await
+            .unwrap();
+        check_response::<dlc::OracleAnnouncement>(&spec, announcement);
+        let attestation = oracle
+            .sign_numeric_event("numeric".to_string(), -42)
+            .await
+            .unwrap();
+        check_response::<dlc::OracleAttestation>(&spec, attestation);
+        oracle
+            .create_enum_event("pending".to_string(), vec!["a".to_string()], 0)
+            .await
+            .unwrap();
+
+        let events = oracle.storage.list_events().await.unwrap();
+        let events = || events.iter().cloned();
+        check_response::<EventList>(&spec, EventList::Json(events().map(Into::into).collect()));
+        check_response::<EventList>(&spec, EventList::Hex(events().map(Into::into).collect()));
+        check_response::<EventList>(&spec, EventList::Tlv(events().map(Into::into).collect()));
+        check_response::<EventList>(&spec, EventList::Base64(events().map(Into::into).collect()));
+
+        check_response::<PubkeyResponse>(
+            &spec,
+            PubkeyResponse {
+                pubkey: oracle.public_key(),
+                nostr_pubkey: oracle.nostr_keys().public_key().to_hex(),
+                nostr_key_binding: Some("signature".to_string()),
+            },
+        );
+
+        let approvals = ["alice", "bob"]
+            .into_iter()
+            .map(|key_id| Approval {
+                event_id: "pending".to_string(),
+                key_id: key_id.to_string(),
+                outcome: "a".to_string(),
+                created_at: NaiveDateTime::default(),
+            })
+            .collect();
+        let status = ApprovalStatus::new("pending".to_string(), 3, approvals);
+        check_response::<ApprovalStatus>(&spec, status);
+
+        for secret in [None, Some("secret".to_string())] {
+            let key = ApiKeyResponse {
+                key_id: "ops".to_string(),
+                scopes: vec!["sign".to_string()],
+                event_namespace: Some("btc-".to_string()),
+                created_at: "2026-10-19T00:00:00Z".to_string(),
+                revoked: false,
+                secret,
+            };
+            check_response::<ApiKeyResponse>(&spec, key);
+        }
+
+        let nostr_event = EventBuilder::text_note("")
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+        let outbox = OutboxEvent {
+            event_id: "enum".to_string(),
+            nostr_event: nostr_event.clone(),
+            attempts: 1,
+            next_attempt: Timestamp::from(0),
+            last_error: Some("no relay".to_string()),
+        };
+        check_response::<OutboxEventResponse>(&spec, OutboxEventResponse::from(outbox));
+        let flushed = FlushResult {
+            published: vec![nostr_event.id],
+            failed: vec![nostr_event.id],
+        };
+        check_response::<FlushOutboxResponse>(&spec, FlushOutboxResponse::from(flushed));
+
+        for delivered_at in [None, Some(NaiveDateTime::default())] {
+            let delivery = WebhookDelivery {
+                id: 1,
+                webhook_url: "https://example.com/hook".to_string(),
+                event_id: "enum".to_string(),
+                kind: "event.attested".to_string(),
+                payload: r#"{"type":"event.attested"}"#.to_string(),
+                attempts: 1,
+                next_attempt: NaiveDateTime::default(),
+                last_error: Some("timeout".to_string()),
+                delivered_at,
+                created_at: NaiveDateTime::default(),
+                updated_at: NaiveDateTime::default(),
+            };
+            check_response::<WebhookDeliveryResponse>(
+                &spec,
+                WebhookDeliveryResponse::new(delivery, 5),
+            );
+        }
+
+        let record = AuditRecord {
+            action: AuditAction::SignEnumEvent.into(),
+            event_id: "enum".to_string(),
+            outcome: Some("a".to_string()),
+            key_id: None,
+            source: Some("127.0.0.1".to_string()),
+            timestamp: 0,
+            result: RESULT_OK.to_string(),
+        };
+        let entry = AuditEntry::new(None, record);
+        check_response::<VerifyAuditLogResponse>(
+            &spec,
+            VerifyAuditLogResponse {
+                valid: true,
+                entries: 1,
+                head: Some(entry.hash),
+                first_invalid: None,
+            },
+        );
+        check_response::<AuditEntry>(&spec, entry);
+
+        let mut relays = ComponentHealth::ok();
+        relays.relays = Some(
+            [(
+                "wss://relay.example.com".to_string(),
+                "connected".to_string(),
+            )]
+            .into(),
+        );
+        let mut outbox = ComponentHealth::fail("backed up");
+        outbox.pending = Some(12);
+        let readiness = ReadinessResponse {
+            ready: false,
+            components: [
+                ("database", ComponentHealth::ok()),
+                ("relays", relays),
+                ("outbox", outbox),
+                (
+                    "migrations",
+                    ComponentHealth::skipped("database unreachable"),
+                ),
+            ]
+            .into(),
+        };
+        check_response::<ReadinessResponse>(&spec, readiness);
+
+        let error = ApiError::not_found("enum");
+        check_response::<ErrorResponse>(&spec, error.body);
+    }
+}
@@ -1,361 +1,1423 @@
+use crate::approvals::{self, ApprovalStatus, Decision};
+use crate::auth::{generate_secret, Caller, Scope, NOSTR_KEY_ID_PREFIX, SHARED_KEY_ID};
+use crate::error::{ApiError, ErrorResponse};
+use crate::health;
 use crate::json_models::*;
+use crate::metrics;
+use crate::models::activity::ActivityFilter;
+use crate::models::api_key::NewApiKey;
+use crate::models::webhook::{DeliveryFilter, DeliveryStatus};
+use crate::models::{EventAction, EventFilter, EventStatus};
+use crate::openapi::{ApiDoc, EventList};
+use crate::webhooks;
 use crate::AppState;
-use anyhow::Error;
+use axum::body::Body;
 use axum::extract::Path;
 use axum::extract::Query;
-use axum::http::StatusCode;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
 use axum::{Extension, Json};
 use dlc_messages::oracle_msgs::OracleAnnouncement;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use kormir::audit::{verify_chain, AuditAction, AuditEntry, AuditRecord, RESULT_OK};
+use kormir::error::Error;
+use kormir::lightning::ln::wire::Type;
+use kormir::lightning::util::ser::Writeable;
+use kormir::schema as dlc;
 use kormir::storage::{OracleEventData, Storage};
-use kormir::OracleAttestation;
-use nostr::{EventId, JsonUtil};
-use serde_json::Value;
+use serde::Serialize;
 use std::collections::HashMap;
-use std::time::SystemTime;
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+use utoipa::OpenApi;
 
-pub async fn health_check() -> Result<Json<()>, (StatusCode, String)> {
+const DEFAULT_LIST_LIMIT: i64 = 100;
+const MAX_LIST_LIMIT: i64 = 1000;
+const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+/// Liveness, the process is up and serving requests.
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    responses(
+        (status = 200, description = "The server is running")
+    )
+)]
+pub async fn liveness_check() -> Result<Json<()>, ApiError> {
     Ok(Json(()))
 }
 
+/// Liveness, kept for the deployments that already poll it.
+#[utoipa::path(
+    get,
+    path = "/health-check",
+    tag = "health",
+    responses(
+        (status = 200, description = "The server is running")
+    )
+)]
+pub async fn health_check() -> Result<Json<()>, ApiError> {
+    liveness_check().await
+}
+
+/// Readiness, the status of each component the server depends on.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    responses(
+        (status = 200, description = "Every component is ready", body = ReadinessResponse),
+        (status = 503, description = "A component failed", body = ReadinessResponse)
+    )
+)]
+pub async fn readiness_check(Extension(state): Extension<AppState>) -> Response {
+    let report = state.readiness.run(&state).await;
+    (health::status_code(&report), Json(report)).into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses(
+        (status = 200, description = "Prometheus metrics", content_type = "text/plain", body = String)
+    )
+)]
+pub async fn get_metrics(Extension(state): Extension<AppState>) -> Result<Response, ApiError> {
+    let body = state
+        .metrics
+        .render(&state.oracle.storage, state.oracle.observer().0.as_ref())
+        .await
+        .map_err(|e| {
+            log::error!("Error rendering metrics: {e}");
+            ApiError::internal("Failed to render metrics")
+        })?;
+
+    Ok(([(CONTENT_TYPE, "text/plain; version=0.0.4")], body).into_response())
+}
+
+/// The OpenAPI document of the server.
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "health",
+    responses(
+        (status = 200, description = "OpenAPI 3.1 document", content_type = "application/json", body = Object)
+    )
+)]
+pub async fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// The oracle's public key and nostr key.
+#[utoipa::path(
+    get,
+    path = "/pubkey",
+    tag = "events",
+    responses(
+        (status = 200, body = PubkeyResponse)
+    )
+)]
 pub async fn get_pubkey(
     Extension(state): Extension<AppState>,
-) -> Result<Json<PubkeyResponse>, (StatusCode, String)> {
+) -> Result<Json<PubkeyResponse>, ApiError> {
     Ok(Json(PubkeyResponse {
         pubkey: state.oracle.public_key(),
+        nostr_pubkey: state.oracle.nostr_keys().public_key.to_hex(),
+        nostr_key_binding: state
+            .oracle
+            .nostr_key_binding()
+            .map(|b| hex::encode(b.signature.serialize())),
     }))
 }
 
+/// Lists events, `limit` at a time. The cursor of the next page, if any, is
+/// returned in the `X-Next-Cursor` header.
+#[utoipa::path(
+    get,
+    path = "/list-events",
+    tag = "events",
+    params(
+        ("format" = Option<String>, Query, description = "`json`, `hex`, `tlv`, `base64` or `binary`, defaults to the `Accept` header"),
+        ("status" = Option<String>, Query, description = "`pending`, `attested` or `withdrawn`"),
+        ("kind" = Option<String>, Query, description = "`enum` or `numeric`"),
+        ("prefix" = Option<String>, Query, description = "Prefix of the event ids"),
+        ("maturity_from" = Option<u32>, Query, description = "Only events maturing at or after this unix time"),
+        ("maturity_until" = Option<u32>, Query, description = "Only events maturing before this unix time, exclusive"),
+        ("order" = Option<String>, Query, description = "`asc` or `desc` maturity, defaults to `asc`"),
+        ("limit" = Option<i64>, Query, description = "Events per page, 1 to 1000, defaults to 100"),
+        ("cursor" = Option<String>, Query, description = "The `X-Next-Cursor` of the previous page")
+    ),
+    responses(
+        (status = 200, description = "A page of events, encoded according to `format`", body = EventList, headers(("X-Next-Cursor" = String, description = "Cursor of the next page, if any"))),
+        (status = 400, description = "Invalid parameter", body = ErrorResponse),
+        (status = 406, description = "Events can not be listed in the requested format", body = ErrorResponse)
+    )
+)]
 pub async fn list_events(
     Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
     Extension(state): Extension<AppState>,
-) -> Result<Json<Value>, (StatusCode, String)> {
-    let events = state.oracle.storage.list_events().await.map_err(|_| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Failed to list events".to_string(),
-        )
-    })?;
-    match Format::from_query(&params) {
-        Err(err) => Err((StatusCode::BAD_REQUEST, format!("{err}"))),
-        Ok(format) => match format {
-            Format::Json => Ok(list_events_json(&events)),
-            Format::Hex => Ok(list_events_hex(&events)),
-            Format::Tlv => Ok(list_events_tlv(&events)),
-        },
-    }
-}
+) -> Result<Response, ApiError> {
+    let format = request_format(&params, &headers)?;
+    let filter = event_filter(&params)?;
+    let events = state
+        .oracle
+        .storage
+        .list_events_filtered(&filter)
+        .await
+        .map_err(|e| {
+            log::error!("Error listing events: {e}");
+            ApiError::from(e)
+        })?;
 
-pub async fn get_oracle_announcement_impl(
-    state: &AppState,
-    event_id: String,
-) -> anyhow::Result<OracleAnnouncement> {
-    if let Some(event) = state.oracle.storage.get_event(event_id).await? {
-        Ok(event.announcement)
-    } else {
-        Err(anyhow::anyhow!(
-            "Announcement by event id is not found in storage."
-        ))
+    let next_cursor = match events.last() {
+        Some(last) if events.len() as i64 == filter.limit => Some(encode_cursor(
+            last.announcement.oracle_event.event_maturity_epoch,
+            &last.event_id,
+        )),
+        _ => None,
+    };
+    let events = events.into_iter();
+    let body = match format {
+        Format::Json => EventList::Json(events.map(Into::into).collect()),
+        Format::Hex => EventList::Hex(events.map(Into::into).collect()),
+        Format::Tlv => EventList::Tlv(events.map(Into::into).collect()),
+        Format::Base64 => EventList::Base64(events.map(Into::into).collect()),
+        Format::Binary => {
+            return Err(ApiError::not_acceptable(
+                "Events can not be listed in binary",
+            ))
+        }
+    };
+
+    let mut response = Json(body).into_response();
+    if let Some(cursor) = next_cursor {
+        response.headers_mut().insert(
+            NEXT_CURSOR_HEADER,
+            HeaderValue::from_str(&cursor).expect("cursor is url safe"),
+        );
     }
+    Ok(response)
 }
 
-pub async fn get_oracle_announcement(
+/// Streams the announcements and attestations as server-sent events,
+/// filtered by `kind` and `prefix` like `/list-events`. Resumes after the
+/// `Last-Event-ID` header, or the `cursor` parameter, when given.
+#[utoipa::path(
+    get,
+    path = "/stream",
+    tag = "events",
+    params(
+        ("kind" = Option<String>, Query, description = "`enum` or `numeric`"),
+        ("prefix" = Option<String>, Query, description = "Prefix of the event ids"),
+        ("cursor" = Option<i64>, Query, description = "Resume after this event id, `0` replays the whole history"),
+        ("Last-Event-ID" = Option<i64>, Header, description = "Resume after this event id, takes precedence over `cursor`")
+    ),
+    responses(
+        (status = 200, description = "Server-sent `announcement` and `attestation` events", content_type = "text/event-stream", body = String),
+        (status = 400, description = "Invalid parameter", body = ErrorResponse)
+    )
+)]
+pub async fn stream_events(
     Extension(state): Extension<AppState>,
-    Path(event_id): Path<String>,
-) -> Result<Json<OracleAnnouncement>, (StatusCode, String)> {
-    match get_oracle_announcement_impl(&state, event_id).await {
-        Ok(res) => Ok(Json(res)),
-        Err(e) => {
-            eprintln!("Error getting announcement by event_id. {:?}", e);
-            Err((
-                StatusCode::NOT_FOUND,
-                "Could not find announcement from event_id.".to_string(),
-            ))
-        }
-    }
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, ApiError> {
+    let is_enum = match params.get("kind").map(|s| s.as_str()) {
+        None => None,
+        Some("enum") => Some(true),
+        Some("numeric") => Some(false),
+        Some(kind) => return Err(ApiError::bad_request(format!("invalid kind: {kind}"))),
+    };
+    let filter = ActivityFilter {
+        event_id_prefix: params.get("prefix").cloned(),
+        is_enum,
+    };
+    let cursor = headers
+        .get("Last-Event-ID")
+        .and_then(|h| h.to_str().ok())
+        .or(params.get("cursor").map(|c| c.as_str()))
+        .map(|c| {
+            c.parse::<i64>()
+                .map_err(|_| ApiError::bad_request("invalid cursor"))
+        })
+        .transpose()?;
+
+    let stream = state.activity.subscribe(filter, cursor);
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
 }
 
-pub async fn get_oracle_attestation_impl(
-    state: &AppState,
-    event_id: String,
-) -> anyhow::Result<OracleAttestation> {
-    let Some(event) = state.oracle.storage.get_event(event_id.clone()).await? else {
-        return Err(anyhow::anyhow!(
-            "Announcement by event id is not found in storage."
-        ));
+fn event_filter(params: &HashMap<String, String>) -> Result<EventFilter, ApiError> {
+    fn parse<T: FromStr>(
+        params: &HashMap<String, String>,
+        name: &str,
+    ) -> Result<Option<T>, ApiError> {
+        params
+            .get(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| ApiError::bad_request(format!("invalid {name}: {value}")))
+            })
+            .transpose()
+    }
+
+    let status = match params.get("status").map(|s| s.as_str()) {
+        None => None,
+        Some("pending") => Some(EventStatus::Pending),
+        Some("attested") => Some(EventStatus::Attested),
+        Some("withdrawn") => Some(EventStatus::Withdrawn),
+        Some(status) => return Err(ApiError::bad_request(format!("invalid status: {status}"))),
+    };
+    let is_enum = match params.get("kind").map(|s| s.as_str()) {
+        None => None,
+        Some("enum") => Some(true),
+        Some("numeric") => Some(false),
+        Some(kind) => return Err(ApiError::bad_request(format!("invalid kind: {kind}"))),
+    };
+    let descending = match params.get("order").map(|s| s.as_str()) {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(order) => return Err(ApiError::bad_request(format!("invalid order: {order}"))),
     };
+    let limit = parse::<i64>(params, "limit")?.unwrap_or(DEFAULT_LIST_LIMIT);
+    if !(1..=MAX_LIST_LIMIT).contains(&limit) {
+        return Err(ApiError::bad_request(format!(
+            "limit must be between 1 and {MAX_LIST_LIMIT}"
+        )));
+    }
+    let after = params
+        .get("cursor")
+        .map(|cursor| decode_cursor(cursor).ok_or_else(|| ApiError::bad_request("invalid cursor")))
+        .transpose()?;
+
+    Ok(EventFilter {
+        status,
+        maturity_from: parse(params, "maturity_from")?,
+        maturity_until: parse(params, "maturity_until")?,
+        is_enum,
+        event_id_prefix: params.get("prefix").cloned(),
+        descending,
+        after,
+        limit,
+    })
+}
+
+/// The cursor is the opaque encoding of the maturity and event id of the last
+/// event of a page.
+fn encode_cursor(maturity: u32, event_id: &str) -> String {
+    base64::encode_config(format!("{maturity}:{event_id}"), base64::URL_SAFE_NO_PAD)
+}
+
+fn decode_cursor(cursor: &str) -> Option<(u32, String)> {
+    let bytes = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()?;
+    let cursor = String::from_utf8(bytes).ok()?;
+    let (maturity, event_id) = cursor.split_once(':')?;
+    Some((maturity.parse().ok()?, event_id.to_string()))
+}
 
-    if event.signatures.is_empty() {
-        return Err(anyhow::anyhow!("Attestation not signed."));
+async fn get_event(state: &AppState, event_id: String) -> Result<OracleEventData, ApiError> {
+    match state.oracle.storage.get_event(event_id.clone()).await {
+        Ok(Some(event)) => Ok(event),
+        Ok(None) => Err(ApiError::not_found(event_id)),
+        Err(e) => {
+            log::error!("Error getting event {event_id}: {e}");
+            Err(e.into())
+        }
     }
+}
 
-    event
-        .attestation()
-        .ok_or(anyhow::anyhow!("Attestation is missing."))
+#[utoipa::path(
+    get,
+    path = "/announcement/{event_id}",
+    tag = "events",
+    params(
+        ("event_id" = String, Path),
+        ("format" = Option<String>, Query, description = "`json`, `hex`, `tlv`, `base64` or `binary`, defaults to the `Accept` header")
+    ),
+    responses(
+        (status = 200, description = "The announcement in the requested format", content(
+            (dlc::OracleAnnouncement = "application/json"),
+            (String = "text/plain"),
+            ("application/octet-stream")
+        )),
+        (status = 400, description = "Invalid format", body = ErrorResponse),
+        (status = 404, description = "Unknown event", body = ErrorResponse),
+        (status = 406, description = "Unsupported `Accept` header", body = ErrorResponse)
+    )
+)]
+pub async fn get_oracle_announcement(
+    Extension(state): Extension<AppState>,
+    Path(event_id): Path<String>,
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let format = request_format(&params, &headers)?;
+    let event = get_event(&state, event_id).await?;
+    Ok(message_response(&event.announcement, format))
 }
 
+#[utoipa::path(
+    get,
+    path = "/attestation/{event_id}",
+    tag = "events",
+    params(
+        ("event_id" = String, Path),
+        ("format" = Option<String>, Query, description = "`json`, `hex`, `tlv`, `base64` or `binary`, defaults to the `Accept` header")
+    ),
+    responses(
+        (status = 200, description = "The attestation in the requested format", content(
+            (dlc::OracleAttestation = "application/json"),
+            (String = "text/plain"),
+            ("application/octet-stream")
+        )),
+        (status = 400, description = "Invalid format", body = ErrorResponse),
+        (status = 404, description = "Unknown event", body = ErrorResponse),
+        (status = 406, description = "Unsupported `Accept` header", body = ErrorResponse),
+        (status = 409, description = "The event was withdrawn", body = ErrorResponse),
+        (status = 425, description = "The event is not attested yet", body = ErrorResponse)
+    )
+)]
 pub async fn get_oracle_attestation(
     Extension(state): Extension<AppState>,
     Path(event_id): Path<String>,
-) -> Result<Json<OracleAttestation>, (StatusCode, String)> {
-    match get_oracle_attestation_impl(&state, event_id).await {
-        Ok(res) => Ok(Json(res)),
-        Err(e) => {
-            eprintln!("Error getting attestation by event_id. {:?}", e);
-            Err((
-                StatusCode::NOT_FOUND,
-                "Could not find attestation from event_id.".to_string(),
-            ))
-        }
+    Query(params): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let format = request_format(&params, &headers)?;
+    let event = get_event(&state, event_id.clone()).await?;
+    match event.attestation() {
+        Some(attestation) => Ok(message_response(&attestation, format)),
+        None if event.withdrawn => Err(Error::EventWithdrawn { event_id }.into()),
+        None => Err(ApiError::not_attested(event_id)),
     }
 }
 
-async fn create_enum_event_impl(
+#[utoipa::path(
+    post,
+    path = "/create-enum",
+    tag = "oracle",
+    request_body = CreateEnumEventRequest,
+    responses(
+        (status = 200, description = "The announcement of the new event", body = dlc::OracleAnnouncement),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse),
+        (status = 409, description = "The event already exists", body = ErrorResponse),
+        (status = 422, description = "Invalid event", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn create_enum_event(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+    Json(body): Json<CreateEnumEventRequest>,
+) -> Result<Json<OracleAnnouncement>, ApiError> {
+    let event_id = body.event_id.clone();
+    let result = create_enum(&state, &caller, body).await;
+    audit(
+        &state,
+        &caller,
+        AuditAction::CreateEnumEvent,
+        event_id,
+        None,
+        audit_result(&result),
+    )
+    .await;
+    result.map(Json)
+}
+
+async fn create_enum(
     state: &AppState,
+    caller: &Caller,
     body: CreateEnumEventRequest,
-) -> anyhow::Result<OracleAnnouncement> {
+) -> Result<OracleAnnouncement, ApiError> {
+    caller.authorize(Scope::Create, Some(&body.event_id))?;
+
+    if body.outcomes.is_empty() {
+        return Err(ApiError::invalid_argument("Must have at least one outcome"));
+    }
+
+    if body.event_maturity_epoch < now() {
+        return Err(ApiError::invalid_argument(
+            "Event maturity epoch must be in the future",
+        ));
+    }
+
     let ann = state
         .oracle
-        .create_enum_event(
-            body.event_id.clone(),
-            body.outcomes,
-            body.event_maturity_epoch,
-        )
-        .await?;
+        .create_enum_event(body.event_id, body.outcomes, body.event_maturity_epoch)
+        .await
+        .map_err(|e| {
+            log::error!("Error creating enum event: {e}");
+            ApiError::from(e)
+        })?;
 
+    record_actor(
+        state,
+        caller,
+        &ann.oracle_event.event_id,
+        EventAction::Created,
+    )
+    .await;
+    state.metrics.event_created(metrics::ENUM);
     log::info!("Created enum event: {}", &ann.oracle_event.event_id);
 
-    let event = kormir::nostr_events::create_announcement_event(&state.oracle.nostr_keys(), &ann)?;
+    Ok(ann)
+}
 
-    log::debug!("Broadcasting nostr event: {}", event.as_json());
+#[utoipa::path(
+    post,
+    path = "/sign-enum",
+    tag = "oracle",
+    request_body = SignEnumEventRequest,
+    responses(
+        (status = 200, description = "The attestation", body = dlc::OracleAttestation),
+        (status = 202, description = "The approval was recorded, the outcome needs more approvals", body = ApprovalStatus),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse),
+        (status = 404, description = "Unknown event", body = ErrorResponse),
+        (status = 409, description = "The event is already attested, withdrawn or its approvals conflict", body = ErrorResponse),
+        (status = 422, description = "Invalid outcome", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn sign_enum_event(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+    Json(body): Json<SignEnumEventRequest>,
+) -> Result<Response, ApiError> {
+    let event_id = body.event_id.clone();
+    let outcome = Some(body.outcome.clone());
+    let result = sign_enum(&state, &caller, body).await;
+    audit(
+        &state,
+        &caller,
+        AuditAction::SignEnumEvent,
+        event_id,
+        outcome,
+        sign_audit_result(&result),
+    )
+    .await;
+    result
+}
 
-    state
-        .oracle
-        .storage
-        .add_announcement_event_id(body.event_id, event.id)
-        .await?;
+async fn sign_enum(
+    state: &AppState,
+    caller: &Caller,
+    body: SignEnumEventRequest,
+) -> Result<Response, ApiError> {
+    caller.authorize(Scope::Sign, Some(&body.event_id))?;
+
+    if state.approvals_required > 1 {
+        get_event(state, body.event_id.clone())
+            .await?
+            .check_enum_outcome(&body.outcome)?;
+        let decision = approvals::approve(state, caller, &body.event_id, &body.outcome).await?;
+        if let Decision::Pending(status) = decision {
+            return Ok((StatusCode::ACCEPTED, Json(status)).into_response());
+        }
+    }
 
-    log::debug!(
-        "Added announcement event id to storage: {}",
-        event.id.to_hex()
-    );
+    let started = Instant::now();
+    let att = state
+        .oracle
+        .sign_enum_event(body.event_id, body.outcome)
+        .await
+        .map_err(|e| {
+            log::error!("Error signing enum event: {e}");
+            ApiError::from(e)
+        })?;
 
-    state.client.send_event(&event).await?;
+    record_actor(state, caller, &att.event_id, EventAction::Attested).await;
+    state.metrics.event_signed(metrics::ENUM, started.elapsed());
+    log::info!("Signed enum event: {}", &att.event_id);
 
-    Ok(ann)
+    Ok(Json(att).into_response())
 }
 
-pub async fn create_enum_event(
+#[utoipa::path(
+    post,
+    path = "/create-numeric",
+    tag = "oracle",
+    request_body = CreateNumericEventRequest,
+    responses(
+        (status = 200, description = "The announcement of the new event", body = dlc::OracleAnnouncement),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse),
+        (status = 409, description = "The event already exists", body = ErrorResponse),
+        (status = 422, description = "Invalid event", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn create_numeric_event(
     Extension(state): Extension<AppState>,
-    Json(body): Json<CreateEnumEventRequest>,
-) -> Result<Json<OracleAnnouncement>, (StatusCode, String)> {
-    if body.outcomes.is_empty() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Must have at least one outcome".to_string(),
+    Extension(caller): Extension<Caller>,
+    Json(body): Json<CreateNumericEventRequest>,
+) -> Result<Json<OracleAnnouncement>, ApiError> {
+    let event_id = body.event_id.clone();
+    let result = create_numeric(&state, &caller, body).await;
+    audit(
+        &state,
+        &caller,
+        AuditAction::CreateNumericEvent,
+        event_id,
+        None,
+        audit_result(&result),
+    )
+    .await;
+    result.map(Json)
+}
+
+async fn create_numeric(
+    state: &AppState,
+    caller: &Caller,
+    body: CreateNumericEventRequest,
+) -> Result<OracleAnnouncement, ApiError> {
+    caller.authorize(Scope::Create, Some(&body.event_id))?;
+
+    if body.num_digits.is_some() && body.num_digits.unwrap_or(0) == 0 {
+        return Err(ApiError::invalid_argument(
+            "Number of digits must be greater than 0",
         ));
     }
 
     if body.event_maturity_epoch < now() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Event maturity epoch must be in the future".to_string(),
+        return Err(ApiError::invalid_argument(
+            "Event maturity epoch must be in the future",
         ));
     }
 
-    match create_enum_event_impl(&state, body).await {
-        Ok(res) => Ok(Json(res)),
-        Err(e) => {
-            eprintln!("Error creating enum event: {:?}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error creating enum event".to_string(),
-            ))
-        }
-    }
+    let ann = state
+        .oracle
+        .create_numeric_event(
+            body.event_id,
+            body.num_digits.unwrap_or(18),
+            body.is_signed.unwrap_or(false),
+            body.precision.unwrap_or(0),
+            body.unit,
+            body.event_maturity_epoch,
+        )
+        .await
+        .map_err(|e| {
+            log::error!("Error creating numeric event: {e}");
+            ApiError::from(e)
+        })?;
+
+    record_actor(
+        state,
+        caller,
+        &ann.oracle_event.event_id,
+        EventAction::Created,
+    )
+    .await;
+    state.metrics.event_created(metrics::NUMERIC);
+    log::info!("Created numeric event: {}", &ann.oracle_event.event_id);
+
+    Ok(ann)
+}
+
+#[utoipa::path(
+    post,
+    path = "/sign-numeric",
+    tag = "oracle",
+    request_body = SignNumericEventRequest,
+    responses(
+        (status = 200, description = "The attestation", body = dlc::OracleAttestation),
+        (status = 202, description = "The approval was recorded, the outcome needs more approvals", body = ApprovalStatus),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse),
+        (status = 404, description = "Unknown event", body = ErrorResponse),
+        (status = 409, description = "The event is already attested, withdrawn or its approvals conflict", body = ErrorResponse),
+        (status = 422, description = "Invalid outcome", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn sign_numeric_event(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+    Json(body): Json<SignNumericEventRequest>,
+) -> Result<Response, ApiError> {
+    let event_id = body.event_id.clone();
+    let outcome = Some(body.outcome.to_string());
+    let result = sign_numeric(&state, &caller, body).await;
+    audit(
+        &state,
+        &caller,
+        AuditAction::SignNumericEvent,
+        event_id,
+        outcome,
+        sign_audit_result(&result),
+    )
+    .await;
+    result
 }
 
-async fn sign_enum_event_impl(
+async fn sign_numeric(
     state: &AppState,
-    body: SignEnumEventRequest,
-) -> anyhow::Result<OracleAttestation> {
+    caller: &Caller,
+    body: SignNumericEventRequest,
+) -> Result<Response, ApiError> {
+    caller.authorize(Scope::Sign, Some(&body.event_id))?;
+
+    if state.approvals_required > 1 {
+        get_event(state, body.event_id.clone())
+            .await?
+            .check_numeric_outcome(body.outcome)?;
+        let decision =
+            approvals::approve(state, caller, &body.event_id, &body.outcome.to_string()).await?;
+        if let Decision::Pending(status) = decision {
+            return Ok((StatusCode::ACCEPTED, Json(status)).into_response());
+        }
+    }
+
+    let started = Instant::now();
     let att = state
         .oracle
-        .sign_enum_event(body.event_id.clone(), body.outcome)
-        .await?;
+        .sign_numeric_event(body.event_id, body.outcome)
+        .await
+        .map_err(|e| {
+            log::error!("Error signing numeric event: {e}");
+            ApiError::from(e)
+        })?;
 
-    log::info!("Signed enum event: {}", &att.event_id);
+    record_actor(state, caller, &att.event_id, EventAction::Attested).await;
+    state
+        .metrics
+        .event_signed(metrics::NUMERIC, started.elapsed());
+    log::info!("Signed numeric event: {}", &att.event_id);
+
+    Ok(Json(att).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/withdraw",
+    tag = "oracle",
+    request_body = WithdrawEventRequest,
+    responses(
+        (status = 200, description = "The announcement of the withdrawn event", body = dlc::OracleAnnouncement),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse),
+        (status = 404, description = "Unknown event", body = ErrorResponse),
+        (status = 409, description = "The event is already attested or withdrawn", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn withdraw_event(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+    Json(body): Json<WithdrawEventRequest>,
+) -> Result<Json<OracleAnnouncement>, ApiError> {
+    let event_id = body.event_id.clone();
+    let result = withdraw(&state, &caller, body).await;
+    audit(
+        &state,
+        &caller,
+        AuditAction::WithdrawEvent,
+        event_id,
+        None,
+        audit_result(&result),
+    )
+    .await;
+    result.map(Json)
+}
+
+async fn withdraw(
+    state: &AppState,
+    caller: &Caller,
+    body: WithdrawEventRequest,
+) -> Result<OracleAnnouncement, ApiError> {
+    caller.authorize(Scope::Create, Some(&body.event_id))?;
 
     let data = state
         .oracle
-        .storage
-        .get_event(body.event_id.clone())
-        .await?;
-    let event_id = get_event_id(data)?;
+        .withdraw_event(body.event_id)
+        .await
+        .map_err(|e| {
+            log::error!("Error withdrawing event: {e}");
+            ApiError::from(e)
+        })?;
 
-    let event =
-        kormir::nostr_events::create_attestation_event(&state.oracle.nostr_keys(), &att, event_id)?;
+    record_actor(state, caller, &data.event_id, EventAction::Withdrawn).await;
+    log::info!("Withdrew event: {}", &data.event_id);
 
-    log::debug!("Broadcasting nostr event: {}", event.as_json());
+    Ok(data.announcement)
+}
 
-    state
+#[utoipa::path(
+    get,
+    path = "/approvals/{event_id}",
+    tag = "oracle",
+    params(
+        ("event_id" = String, Path)
+    ),
+    responses(
+        (status = 200, body = ApprovalStatus),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse),
+        (status = 404, description = "Unknown event", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn get_approvals(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+    Path(event_id): Path<String>,
+) -> Result<Json<ApprovalStatus>, ApiError> {
+    caller.authorize(Scope::Sign, Some(&event_id))?;
+
+    get_event(&state, event_id.clone()).await?;
+    let approvals = state.oracle.storage.list_approvals(&event_id).await?;
+    Ok(Json(ApprovalStatus::new(
+        event_id,
+        state.approvals_required,
+        approvals,
+    )))
+}
+
+#[utoipa::path(
+    get,
+    path = "/outbox",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The nostr events waiting to be published", body = Vec<OutboxEventResponse>),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse),
+        (status = 503, description = "Nostr is disabled", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn list_outbox(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+) -> Result<Json<Vec<OutboxEventResponse>>, ApiError> {
+    caller.authorize(Scope::Admin, None)?;
+    let publisher = state
         .oracle
-        .storage
-        .add_attestation_event_id(body.event_id, event.id)
-        .await?;
+        .observer()
+        .0
+        .as_ref()
+        .ok_or_else(ApiError::nostr_disabled)?;
+    let pending = publisher.pending().await.map_err(|e| {
+        log::error!("Error listing outbox: {e}");
+        ApiError::from(e)
+    })?;
 
-    log::debug!(
-        "Added announcement event id to storage: {}",
-        event.id.to_hex()
-    );
+    Ok(Json(pending.into_iter().map(|e| e.into()).collect()))
+}
 
-    state.client.send_event(&event).await?;
+#[utoipa::path(
+    post,
+    path = "/outbox/flush",
+    tag = "admin",
+    responses(
+        (status = 200, body = FlushOutboxResponse),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse),
+        (status = 503, description = "Nostr is disabled", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn flush_outbox(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+) -> Result<Json<FlushOutboxResponse>, ApiError> {
+    let result = flush(&state, &caller).await;
+    audit(
+        &state,
+        &caller,
+        AuditAction::FlushOutbox,
+        String::new(),
+        None,
+        audit_result(&result),
+    )
+    .await;
+    result.map(Json)
+}
+
+async fn flush(state: &AppState, caller: &Caller) -> Result<FlushOutboxResponse, ApiError> {
+    caller.authorize(Scope::Admin, None)?;
+    let publisher = state
+        .oracle
+        .observer()
+        .0
+        .as_ref()
+        .ok_or_else(ApiError::nostr_disabled)?;
+    // in queue mode the relays are only connected to when flushing
+    publisher.client().connect().await;
+    publisher
+        .client()
+        .wait_for_connection(Duration::from_secs(5))
+        .await;
+
+    let res = publisher.flush(true).await.map_err(|e| {
+        log::error!("Error flushing outbox: {e}");
+        ApiError::from(e)
+    })?;
 
-    Ok(att)
+    Ok(res.into())
 }
 
-pub async fn sign_enum_event(
+#[utoipa::path(
+    get,
+    path = "/api-keys",
+    tag = "admin",
+    responses(
+        (status = 200, body = Vec<ApiKeyResponse>),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn list_api_keys(
     Extension(state): Extension<AppState>,
-    Json(body): Json<SignEnumEventRequest>,
-) -> Result<Json<OracleAttestation>, (StatusCode, String)> {
-    match sign_enum_event_impl(&state, body).await {
-        Ok(res) => Ok(Json(res)),
-        Err(e) => {
-            eprintln!("Error signing enum event: {:?}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error signing enum event".to_string(),
-            ))
-        }
-    }
+    Extension(caller): Extension<Caller>,
+) -> Result<Json<Vec<ApiKeyResponse>>, ApiError> {
+    caller.authorize(Scope::Admin, None)?;
+
+    let keys = state.oracle.storage.list_api_keys().await?;
+    Ok(Json(keys.into_iter().map(|k| k.into()).collect()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api-keys",
+    tag = "admin",
+    request_body = CreateApiKeyRequest,
+    responses(
+        (status = 200, description = "The new key, with its secret", body = ApiKeyResponse),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse),
+        (status = 409, description = "The key already exists", body = ErrorResponse),
+        (status = 422, description = "Invalid key id, scope or namespace", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn create_api_key(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+    Json(body): Json<CreateApiKeyRequest>,
+) -> Result<Json<ApiKeyResponse>, ApiError> {
+    let key_id = body.key_id.clone();
+    let result = create_key(&state, &caller, body).await;
+    audit(
+        &state,
+        &caller,
+        ApiKeyAction::Create,
+        key_id,
+        None,
+        audit_result(&result),
+    )
+    .await;
+    result.map(Json)
 }
 
-async fn create_numeric_event_impl(
+async fn create_key(
     state: &AppState,
-    body: CreateNumericEventRequest,
-) -> anyhow::Result<OracleAnnouncement> {
-    let ann = state
+    caller: &Caller,
+    body: CreateApiKeyRequest,
+) -> Result<ApiKeyResponse, ApiError> {
+    caller.authorize(Scope::Admin, None)?;
+
+    let scopes = body
+        .scopes
+        .iter()
+        .map(|s| s.parse())
+        .collect::<Result<Vec<Scope>, _>>()
+        .map_err(|e| ApiError::invalid_argument(format!("{e}")))?;
+    let key = new_api_key(body.key_id.clone(), scopes, body.event_namespace)?;
+    let secret = key.secret.clone();
+
+    let Some(key) = state.oracle.storage.create_api_key(key).await? else {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "api_key_already_exists",
+            format!("API key {} already exists", body.key_id),
+        ));
+    };
+    log::info!("Created API key {}", key.key_id);
+
+    Ok(ApiKeyResponse::from(key).with_secret(secret))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api-keys/{key_id}/rotate",
+    tag = "admin",
+    params(
+        ("key_id" = String, Path)
+    ),
+    responses(
+        (status = 200, description = "The key, with its new secret", body = ApiKeyResponse),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse),
+        (status = 404, description = "Unknown key", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn rotate_api_key(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+    Path(key_id): Path<String>,
+) -> Result<Json<ApiKeyResponse>, ApiError> {
+    let result = rotate_key(&state, &caller, &key_id).await;
+    audit(
+        &state,
+        &caller,
+        ApiKeyAction::Rotate,
+        key_id,
+        None,
+        audit_result(&result),
+    )
+    .await;
+    result.map(Json)
+}
+
+async fn rotate_key(
+    state: &AppState,
+    caller: &Caller,
+    key_id: &str,
+) -> Result<ApiKeyResponse, ApiError> {
+    caller.authorize(Scope::Admin, None)?;
+
+    let secret = generate_secret();
+    let key = state
         .oracle
-        .create_numeric_event(
-            body.event_id.clone(),
-            body.num_digits.unwrap_or(18),
-            body.is_signed.unwrap_or(false),
-            body.precision.unwrap_or(0),
-            body.unit,
-            body.event_maturity_epoch,
-        )
-        .await?;
+        .storage
+        .rotate_api_key(key_id, secret.clone())
+        .await?
+        .ok_or_else(|| api_key_not_found(key_id))?;
+    log::info!("Rotated API key {key_id}");
 
-    log::info!("Created numeric event: {}", &ann.oracle_event.event_id);
+    Ok(ApiKeyResponse::from(key).with_secret(secret))
+}
 
-    let event = kormir::nostr_events::create_announcement_event(&state.oracle.nostr_keys(), &ann)?;
+#[utoipa::path(
+    post,
+    path = "/api-keys/{key_id}/revoke",
+    tag = "admin",
+    params(
+        ("key_id" = String, Path)
+    ),
+    responses(
+        (status = 200, body = ApiKeyResponse),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse),
+        (status = 404, description = "Unknown key", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn revoke_api_key(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+    Path(key_id): Path<String>,
+) -> Result<Json<ApiKeyResponse>, ApiError> {
+    let result = revoke_key(&state, &caller, &key_id).await;
+    audit(
+        &state,
+        &caller,
+        ApiKeyAction::Revoke,
+        key_id,
+        None,
+        audit_result(&result),
+    )
+    .await;
+    result.map(Json)
+}
 
-    log::debug!("Broadcasting nostr event: {}", event.as_json());
+async fn revoke_key(
+    state: &AppState,
+    caller: &Caller,
+    key_id: &str,
+) -> Result<ApiKeyResponse, ApiError> {
+    caller.authorize(Scope::Admin, None)?;
 
-    state
+    let key = state
         .oracle
         .storage
-        .add_announcement_event_id(body.event_id, event.id)
-        .await?;
+        .revoke_api_key(key_id)
+        .await?
+        .ok_or_else(|| api_key_not_found(key_id))?;
+    log::info!("Revoked API key {key_id}");
 
-    log::debug!(
-        "Added announcement event id to storage: {}",
-        event.id.to_hex()
-    );
+    Ok(key.into())
+}
 
-    state.client.send_event(&event).await?;
+/// Lists the webhook deliveries, newest first. Filtered by `status`
+/// (`pending`, `delivered` or `failed`) and `event_id`, and paginated with
+/// `limit` and `before`, the id of the last delivery of the previous page.
+#[utoipa::path(
+    get,
+    path = "/webhooks/deliveries",
+    tag = "admin",
+    params(
+        ("status" = Option<String>, Query, description = "`pending`, `delivered` or `failed`"),
+        ("event_id" = Option<String>, Query),
+        ("before" = Option<i64>, Query, description = "Id of the last delivery of the previous page"),
+        ("limit" = Option<i64>, Query, description = "Deliveries per page, 1 to 1000, defaults to 100")
+    ),
+    responses(
+        (status = 200, body = Vec<WebhookDeliveryResponse>),
+        (status = 400, description = "Invalid parameter", body = ErrorResponse),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn list_webhook_deliveries(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<Vec<WebhookDeliveryResponse>>, ApiError> {
+    caller.authorize(Scope::Admin, None)?;
 
-    Ok(ann)
+    let status = match params.get("status").map(|s| s.as_str()) {
+        None => None,
+        Some("pending") => Some(DeliveryStatus::Pending),
+        Some("delivered") => Some(DeliveryStatus::Delivered),
+        Some("failed") => Some(DeliveryStatus::Failed),
+        Some(status) => return Err(ApiError::bad_request(format!("Invalid status: {status}"))),
+    };
+    let before = match params.get("before") {
+        Some(before) => Some(
+            before
+                .parse::<i64>()
+                .map_err(|_| ApiError::bad_request(format!("Invalid before: {before}")))?,
+        ),
+        None => None,
+    };
+    let limit = match params.get("limit") {
+        Some(limit) => limit
+            .parse::<i64>()
+            .ok()
+            .filter(|l| (1..=MAX_LIST_LIMIT).contains(l))
+            .ok_or_else(|| ApiError::bad_request(format!("Invalid limit: {limit}")))?,
+        None => DEFAULT_LIST_LIMIT,
+    };
+    let filter = DeliveryFilter {
+        status,
+        event_id: params.get("event_id").cloned(),
+        before,
+        limit,
+    };
+
+    let deliveries = state
+        .oracle
+        .storage
+        .list_webhook_deliveries(&filter, webhooks::MAX_ATTEMPTS)
+        .await?;
+    Ok(Json(
+        deliveries
+            .into_iter()
+            .map(|d| WebhookDeliveryResponse::new(d, webhooks::MAX_ATTEMPTS))
+            .collect(),
+    ))
 }
 
-pub async fn create_numeric_event(
+/// Lists the audit log starting at the `from` sequence number. With
+/// `format=jsonl` the rest of the log is exported as JSON lines instead.
+#[utoipa::path(
+    get,
+    path = "/audit-log",
+    tag = "admin",
+    params(
+        ("from" = Option<u64>, Query, description = "Sequence number of the first entry, defaults to 0"),
+        ("limit" = Option<i64>, Query, description = "Entries per page, 1 to 1000, defaults to 100"),
+        ("format" = Option<String>, Query, description = "`json` or `jsonl`")
+    ),
+    responses(
+        (status = 200, description = "Entries of the log, or the rest of the log as JSON lines with `format=jsonl`", content(
+            (Vec<AuditEntry> = "application/json"),
+            (String = "application/x-ndjson")
+        )),
+        (status = 400, description = "Invalid parameter", body = ErrorResponse),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn list_audit_log(
     Extension(state): Extension<AppState>,
-    Json(body): Json<CreateNumericEventRequest>,
-) -> Result<Json<OracleAnnouncement>, (StatusCode, String)> {
-    if body.num_digits.is_some() && body.num_digits.unwrap_or(0) == 0 {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Number of digits must be greater than 0".to_string(),
-        ));
+    Extension(caller): Extension<Caller>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Response, ApiError> {
+    caller.authorize(Scope::Admin, None)?;
+
+    let from = match params.get("from") {
+        Some(from) => from
+            .parse::<u64>()
+            .map_err(|_| ApiError::bad_request(format!("Invalid from: {from}")))?,
+        None => 0,
+    };
+
+    match params.get("format").map(|f| f.as_str()) {
+        None | Some("json") => {
+            let limit = match params.get("limit") {
+                Some(limit) => limit
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|l| (1..=MAX_LIST_LIMIT).contains(l))
+                    .ok_or_else(|| ApiError::bad_request(format!("Invalid limit: {limit}")))?,
+                None => DEFAULT_LIST_LIMIT,
+            };
+            let entries = state
+                .oracle
+                .storage
+                .list_audit_entries(from, limit as usize)
+                .await?;
+            Ok(Json(entries).into_response())
+        }
+        Some("jsonl") => {
+            // read the first page before answering, so a failure is an error
+            // response rather than a truncated body
+            let mut lines = Box::pin(audit_log_lines(state, from));
+            let first = lines.try_next().await?.unwrap_or_default();
+            let body = Body::from_stream(stream::once(async { Ok(first) }).chain(lines));
+            Ok(([(CONTENT_TYPE, "application/x-ndjson")], body).into_response())
+        }
+        Some(format) => Err(ApiError::bad_request(format!("Invalid format: {format}"))),
     }
+}
 
-    if body.event_maturity_epoch < now() {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Event maturity epoch must be in the future".to_string(),
-        ));
+/// Checks the hash chain of the whole audit log.
+#[utoipa::path(
+    get,
+    path = "/audit-log/verify",
+    tag = "admin",
+    responses(
+        (status = 200, body = VerifyAuditLogResponse),
+        (status = 401, description = "The request is not signed or the signature is invalid", body = ErrorResponse),
+        (status = 403, description = "The credential lacks the scope or event namespace", body = ErrorResponse)
+    ),
+    security(("hmac" = []), ("nip98" = []))
+)]
+pub async fn verify_audit_log(
+    Extension(state): Extension<AppState>,
+    Extension(caller): Extension<Caller>,
+) -> Result<Json<VerifyAuditLogResponse>, ApiError> {
+    caller.authorize(Scope::Admin, None)?;
+
+    let mut last: Option<AuditEntry> = None;
+    let mut entries = 0;
+    let mut first_invalid = None;
+    for_each_audit_page(&state, 0, |page| {
+        if first_invalid.is_some() {
+            return;
+        }
+        // include the last entry of the previous page to check the link
+        let chain = last.iter().chain(page).cloned().collect::<Vec<_>>();
+        if let Err(sequence) = verify_chain(&chain) {
+            first_invalid = Some(sequence);
+        }
+        entries += page.len() as u64;
+        last = page.last().cloned();
+    })
+    .await?;
+
+    Ok(Json(VerifyAuditLogResponse {
+        valid: first_invalid.is_none(),
+        entries,
+        head: last.map(|e| e.hash).filter(|_| first_invalid.is_none()),
+        first_invalid,
+    }))
+}
+
+/// Reads the audit log a page at a time, starting at the `from` sequence number.
+async fn for_each_audit_page(
+    state: &AppState,
+    mut from: u64,
+    mut f: impl FnMut(&[AuditEntry]),
+) -> Result<(), ApiError> {
+    loop {
+        let page = state
+            .oracle
+            .storage
+            .list_audit_entries(from, MAX_LIST_LIMIT as usize)
+            .await?;
+        f(&page);
+        match page.last() {
+            Some(last) if page.len() == MAX_LIST_LIMIT as usize => from = last.sequence + 1,
+            _ => return Ok(()),
+        }
     }
+}
 
-    match create_numeric_event_impl(&state, body).await {
-        Ok(res) => Ok(Json(res)),
-        Err(e) => {
-            eprintln!("Error creating numeric event: {:?}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error creating numeric event".to_string(),
-            ))
+/// Streams the audit log from the `from` sequence number as JSON lines, reading
+/// it a page at a time.
+fn audit_log_lines(state: AppState, from: u64) -> impl Stream<Item = Result<String, Error>> {
+    stream::try_unfold(Some(from), move |from| {
+        let state = state.clone();
+        async move {
+            let Some(from) = from else {
+                return Ok(None);
+            };
+            let page = state
+                .oracle
+                .storage
+                .list_audit_entries(from, MAX_LIST_LIMIT as usize)
+                .await?;
+            let next = match page.last() {
+                Some(last) if page.len() == MAX_LIST_LIMIT as usize => Some(last.sequence + 1),
+                _ => None,
+            };
+            let mut lines = String::new();
+            for entry in &page {
+                lines.push_str(&serde_json::to_string(entry).expect("serializable"));
+                lines.push('\n');
+            }
+            Ok(Some((lines, next)))
         }
+    })
+}
+
+/// Validates a new API key and generates its secret.
+pub fn new_api_key(
+    key_id: String,
+    scopes: Vec<Scope>,
+    event_namespace: Option<String>,
+) -> Result<NewApiKey, ApiError> {
+    if key_id.is_empty() || key_id == SHARED_KEY_ID || key_id.starts_with(NOSTR_KEY_ID_PREFIX) {
+        return Err(ApiError::invalid_argument(format!(
+            "Invalid key id: {key_id:?}"
+        )));
     }
+    if scopes.is_empty() {
+        return Err(ApiError::invalid_argument("Must have at least one scope"));
+    }
+
+    Ok(NewApiKey {
+        key_id,
+        secret: generate_secret(),
+        scopes: scopes.iter().map(|s| s.to_string()).collect(),
+        event_namespace: event_namespace.filter(|n| !n.is_empty()),
+    })
 }
 
-async fn sign_numeric_event_impl(
-    state: &AppState,
-    body: SignNumericEventRequest,
-) -> anyhow::Result<OracleAttestation> {
-    let att = state
-        .oracle
-        .sign_numeric_event(body.event_id.clone(), body.outcome)
-        .await?;
+fn api_key_not_found(key_id: &str) -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        "api_key_not_found",
+        format!("API key {key_id} not found"),
+    )
+}
 
-    log::info!("Signed numeric event: {}", &att.event_id);
+/// Records the credential that performed an action, the action already
+/// succeeded so a failure is only logged.
+async fn record_actor(state: &AppState, caller: &Caller, event_id: &str, action: EventAction) {
+    if let Some(key_id) = &caller.key_id {
+        if let Err(e) = state
+            .oracle
+            .storage
+            .set_event_actor(event_id, action, key_id)
+            .await
+        {
+            log::warn!("Failed to record {key_id} as the actor of {event_id}: {e}");
+        }
+    }
+}
 
-    let data = state
-        .oracle
-        .storage
-        .get_event(body.event_id.clone())
-        .await?;
-    let event_id = get_event_id(data)?;
+/// Result recorded when an attestation is waiting for more approvals
+const RESULT_APPROVAL_PENDING: &str = "approval_pending";
 
-    let event =
-        kormir::nostr_events::create_attestation_event(&state.oracle.nostr_keys(), &att, event_id)?;
+/// The result of a request as recorded in the audit log
+fn audit_result<T>(result: &Result<T, ApiError>) -> String {
+    match result {
+        Ok(_) => RESULT_OK.to_string(),
+        Err(e) => e.body.code.to_string(),
+    }
+}
 
-    log::debug!("Broadcasting nostr event: {}", event.as_json());
+/// Like [`audit_result`], for the attestations that may be waiting for more
+/// approvals
+fn sign_audit_result(result: &Result<Response, ApiError>) -> String {
+    match result {
+        Ok(res) if res.status() == StatusCode::ACCEPTED => RESULT_APPROVAL_PENDING.to_string(),
+        _ => audit_result(result),
+    }
+}
 
-    state
-        .oracle
-        .storage
-        .add_attestation_event_id(body.event_id, event.id)
-        .await?;
+/// Changes to the API keys recorded in the audit log, next to the oracle's
+/// [`AuditAction`]s
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiKeyAction {
+    Create,
+    Rotate,
+    Revoke,
+}
 
-    log::debug!(
-        "Added announcement event id to storage: {}",
-        event.id.to_hex()
-    );
+impl ApiKeyAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiKeyAction::Create => "create_api_key",
+            ApiKeyAction::Rotate => "rotate_api_key",
+            ApiKeyAction::Revoke => "revoke_api_key",
+        }
+    }
+}
 
-    state.client.send_event(&event).await?;
+impl From<ApiKeyAction> for String {
+    fn from(action: ApiKeyAction) -> Self {
+        action.as_str().to_string()
+    }
+}
 
-    Ok(att)
+/// Appends the request to the audit log. The action has already happened, so
+/// a failure to record it is logged and counted in the metrics.
+async fn audit(
+    state: &AppState,
+    caller: &Caller,
+    action: impl Into<String>,
+    event_id: String,
+    outcome: Option<String>,
+    result: String,
+) {
+    let record = AuditRecord {
+        action: action.into(),
+        event_id,
+        outcome,
+        key_id: caller.key_id.clone(),
+        source: caller.source.clone(),
+        timestamp: now() as u64,
+        result,
+    };
+    if let Err(e) = state
+        .oracle
+        .storage
+        .append_audit_record(record.clone())
+        .await
+    {
+        log::error!("Failed to append {record:?} to the audit log: {e}");
+        state.metrics.audit_failed();
+    }
 }
 
-fn get_event_id(data: Option<OracleEventData>) -> Result<EventId, Error> {
-    data.and_then(|d| {
-        d.announcement_event_id
-            .and_then(|s| EventId::from_hex(&s).ok())
-    })
-    .ok_or_else(|| anyhow::anyhow!("Failed to get announcement event id"))
+fn request_format(
+    params: &HashMap<String, String>,
+    headers: &HeaderMap,
+) -> Result<Format, ApiError> {
+    match Format::from_request(params, headers) {
+        Ok(format) => Ok(format),
+        // an unknown `format` parameter is a bad request, an unsupported Accept
+        // header is not acceptable
+        Err(e) if params.contains_key("format") => Err(ApiError::bad_request(format!("{e}"))),
+        Err(e) => Err(ApiError::not_acceptable(format!("{e}"))),
+    }
 }
 
-pub async fn sign_numeric_event(
-    Extension(state): Extension<AppState>,
-    Json(body): Json<SignNumericEventRequest>,
-) -> Result<Json<OracleAttestation>, (StatusCode, String)> {
-    match crate::routes::sign_numeric_event_impl(&state, body).await {
-        Ok(res) => Ok(Json(res)),
-        Err(e) => {
-            eprintln!("Error signing numeric event: {:?}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error signing numeric event".to_string(),
-            ))
+/// Renders a single DLC message, the encoded formats are returned as plain text.
+fn message_response<T: Type + Writeable + Serialize>(msg: &T, format: Format) -> Response {
+    match format {
+        Format::Json => Json(msg).into_response(),
+        Format::Hex => hex::encode(msg.encode()).into_response(),
+        Format::Tlv => hex::encode(tlv_bytes(msg)).into_response(),
+        Format::Base64 => base64::encode(msg.encode()).into_response(),
+        Format::Binary => {
+            ([(CONTENT_TYPE, "application/octet-stream")], tlv_bytes(msg)).into_response()
         }
     }
 }
@@ -367,17 +1429,223 @@ fn now() -> u32 {
         .as_secs() as u32
 }
 
-fn list_events_json(events: &[OracleEventData]) -> Json<Value> {
-    let events: Vec<JsonEventResponse> = events.iter().map(|e| e.clone().into()).collect();
-    Json(serde_json::to_value(events).unwrap())
-}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::{PostgresStorage, MIGRATIONS};
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel::PgConnection;
+    use diesel_migrations::MigrationHarness;
+    use kormir::Oracle;
 
-fn list_events_hex(events: &[OracleEventData]) -> Json<Value> {
-    let hex_events: Vec<HexEventResponse> = events.iter().map(|e| e.clone().into()).collect();
-    Json(serde_json::to_value(hex_events).unwrap())
-}
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_cursor() {
+        let cursor = encode_cursor(1_700_000_000, "btc:usd/2026");
+        assert!(HeaderValue::from_str(&cursor).is_ok());
+        assert_eq!(
+            decode_cursor(&cursor),
+            Some((1_700_000_000, "btc:usd/2026".to_string()))
+        );
+        assert_eq!(
+            decode_cursor(&encode_cursor(0, "")),
+            Some((0, String::new()))
+        );
+
+        let encode = |s: &[u8]| base64::encode_config(s, base64::URL_SAFE_NO_PAD);
+        for cursor in [
+            String::new(),
+            "not base64!".to_string(),
+            encode(b"no separator"),
+            encode(b"-1:btc"),
+            encode(b"4294967296:btc"),
+            encode(b"soon:btc"),
+            encode(&[0xff, b':', b'a']),
+        ] {
+            assert_eq!(decode_cursor(&cursor), None, "{cursor}");
+        }
+    }
+
+    #[test]
+    fn test_event_filter() {
+        let filter = event_filter(&HashMap::new()).unwrap();
+        assert_eq!(filter.status, None);
+        assert!(!filter.descending);
+        assert_eq!(filter.limit, DEFAULT_LIST_LIMIT);
+        assert_eq!(filter.after, None);
 
-fn list_events_tlv(events: &[OracleEventData]) -> Json<Value> {
-    let tlv_events: Vec<TLVEventResponse> = events.iter().map(|e| e.clone().into()).collect();
-    Json(serde_json::to_value(tlv_events).unwrap())
+        let filter = event_filter(&params(&[
+            ("status", "attested"),
+            ("kind", "numeric"),
+            ("order", "desc"),
+            ("limit", "1000"),
+            ("maturity_from", "10"),
+            ("maturity_until", "20"),
+            ("prefix", "btc_%"),
+            ("cursor", &encode_cursor(15, "btc")),
+        ]))
+        .unwrap();
+        assert_eq!(filter.status, Some(EventStatus::Attested));
+        assert_eq!(filter.is_enum, Some(false));
+        assert!(filter.descending);
+        assert_eq!(filter.limit, 1000);
+        assert_eq!(filter.maturity_from, Some(10));
+        assert_eq!(filter.maturity_until, Some(20));
+        assert_eq!(filter.event_id_prefix.as_deref(), Some("btc_%"));
+        assert_eq!(filter.after, Some((15, "btc".to_string())));
+
+        for (name, value) in [
+            ("status", "done"),
+            ("kind", "Enum"),
+            ("order", "up"),
+            ("limit", "0"),
+            ("limit", "1001"),
+            ("limit", "ten"),
+            ("maturity_from", "-1"),
+            ("maturity_until", "tomorrow"),
+            ("cursor", "garbage"),
+        ] {
+            let Err(err) = event_filter(&params(&[(name, value)])) else {
+                panic!("{name}={value} was accepted");
+            };
+            assert_eq!(err.status, StatusCode::BAD_REQUEST, "{name}={value}");
+        }
+    }
+
+    /// Pages through the events of the filter like a client following
+    /// `X-Next-Cursor`.
+    async fn list_pages(
+        storage: &PostgresStorage,
+        mut params: HashMap<String, String>,
+    ) -> Vec<Vec<String>> {
+        let mut pages = Vec::new();
+        loop {
+            let filter = event_filter(&params).unwrap();
+            let page = storage.list_events_filtered(&filter).await.unwrap();
+            let ids = page.iter().map(|e| e.event_id.clone()).collect::<Vec<_>>();
+            match page.last() {
+                Some(last) if page.len() as i64 == filter.limit => {
+                    let maturity = last.announcement.oracle_event.event_maturity_epoch;
+                    params.insert(
+                        "cursor".to_string(),
+                        encode_cursor(maturity, &last.event_id),
+                    );
+                    pages.push(ids);
+                }
+                _ => {
+                    pages.push(ids);
+                    return pages;
+                }
+            }
+        }
+    }
+
+    /// Runs against the database in `KORMIR_TEST_DATABASE_URL`, events are
+    /// created under a random prefix so runs do not see each other's.
+    #[tokio::test]
+    async fn test_list_events_pages() {
+        let Ok(pg_url) = std::env::var("KORMIR_TEST_DATABASE_URL") else {
+            println!("KORMIR_TEST_DATABASE_URL is not set, skipping");
+            return;
+        };
+        let db_pool = Pool::builder()
+            .max_size(2)
+            .build(ConnectionManager::<PgConnection>::new(&pg_url))
+            .unwrap();
+        db_pool
+            .get()
+            .unwrap()
+            .run_pending_migrations(MIGRATIONS)
+            .unwrap();
+        let signing_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let public_key = signing_key.x_only_public_key(&Secp256k1::new()).0;
+        let storage = PostgresStorage::new(db_pool, public_key).unwrap();
+        let oracle = Oracle::from_signing_key(storage.clone(), signing_key).unwrap();
+
+        let run = generate_secret()[..12].to_string();
+        let create = |event_id: String, maturity: u32| {
+            let oracle = &oracle;
+            async move {
+                oracle
+                    .create_enum_event(event_id, vec!["a".to_string()], maturity)
+                    .await
+                    .unwrap();
+            }
+        };
+        // five events on the page boundaries share their maturity
+        for name in ["e", "a", "d", "b", "c"] {
+            create(format!("{run}-{name}"), 200).await;
+        }
+        create(format!("{run}-z"), 100).await;
+        create(format!("{run}-0"), 300).await;
+
+        let prefix = |extra: &[(&str, &str)]| {
+            let mut params = params(extra);
+            params.insert("prefix".to_string(), format!("{run}-"));
+            params
+        };
+        let ids = |names: &[&str]| -> Vec<String> {
+            names.iter().map(|n| format!("{run}-{n}")).collect()
+        };
+
+        let pages = list_pages(&storage, prefix(&[("limit", "2")])).await;
+        assert_eq!(
+            pages,
+            vec![
+                ids(&["z", "a"]),
+                ids(&["b", "c"]),
+                ids(&["d", "e"]),
+                ids(&["0"])
+            ]
+        );
+        let pages = list_pages(&storage, prefix(&[("limit", "3"), ("order", "desc")])).await;
+        assert_eq!(
+            pages,
+            vec![ids(&["0", "e", "d"]), ids(&["c", "b", "a"]), ids(&["z"])]
+        );
+
+        // the maturity range includes its start but not its end
+        let pages = list_pages(
+            &storage,
+            prefix(&[("maturity_from", "100"), ("maturity_until", "200")]),
+        )
+        .await;
+        assert_eq!(pages, vec![ids(&["z"])]);
+
+        // LIKE wildcards in the prefix are matched literally
+        create(format!("{run}%_x"), 100).await;
+        create(format!("{run}%yx"), 100).await;
+        create(format!("{run}a_x"), 100).await;
+        let mut params = HashMap::new();
+        params.insert("prefix".to_string(), format!("{run}%_"));
+        let pages = list_pages(&storage, params).await;
+        assert_eq!(pages, vec![vec![format!("{run}%_x")]]);
+    }
+
+    #[test]
+    fn test_new_api_key_reserved_ids() {
+        let pubkey = nostr::Keys::generate().public_key();
+        for key_id in [
+            String::new(),
+            SHARED_KEY_ID.to_string(),
+            crate::auth::nostr_key_id(&pubkey),
+            "nostr:".to_string(),
+        ] {
+            let Err(err) = new_api_key(key_id.clone(), vec![Scope::Sign], None) else {
+                panic!("{key_id:?} is reserved");
+            };
+            assert_eq!(err.status, StatusCode::UNPROCESSABLE_ENTITY, "{key_id}");
+        }
+
+        let key = new_api_key("nostr-ops".to_string(), vec![Scope::Sign], None).unwrap();
+        assert_eq!(key.key_id, "nostr-ops");
+        assert!(new_api_key("ops".to_string(), vec![], None).is_err());
+    }
 }
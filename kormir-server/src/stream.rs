@@ -0,0 +1,460 @@
+//! Server-sent events stream of the announcements and attestations.
+//!
+//! Every announcement and attestation is saved to the `event_activity` table
+//! and its id is sent as the SSE `id`, so a client that disconnects can resume
+//! with the `Last-Event-ID` header, or the `cursor` query parameter, and be
+//! sent what it missed before the live activity.
+
+use crate::models::activity::ActivityFilter;
+use crate::models::PostgresStorage;
+use axum::response::sse;
+use dlc_messages::oracle_msgs::{EventDescriptor, OracleAnnouncement, OracleAttestation};
+use futures::channel::mpsc;
+use futures::{SinkExt, Stream, StreamExt};
+use kormir::error::Error;
+use kormir::observer::OracleObserver;
+use kormir::storage::{OracleEventData, Storage};
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Live activity buffered for slow clients before they have to catch up from
+/// the database
+const LIVE_CAPACITY: usize = 1024;
+/// Activity read from the database at once when resuming
+const REPLAY_BATCH_SIZE: i64 = 100;
+/// Ids remembered per client to skip what it was already sent
+const SENT_WINDOW: usize = LIVE_CAPACITY;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActivityKind {
+    Announcement,
+    Attestation,
+}
+
+impl ActivityKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ActivityKind::Announcement => "announcement",
+            ActivityKind::Attestation => "attestation",
+        }
+    }
+}
+
+impl FromStr for ActivityKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "announcement" => Ok(ActivityKind::Announcement),
+            "attestation" => Ok(ActivityKind::Attestation),
+            _ => Err(anyhow::anyhow!("invalid activity kind: {s}")),
+        }
+    }
+}
+
+/// An announcement or attestation sent to the stream
+#[derive(Debug, Clone)]
+struct ActivityEvent {
+    id: i64,
+    kind: ActivityKind,
+    event: Arc<OracleEventData>,
+}
+
+impl ActivityEvent {
+    fn is_enum(&self) -> bool {
+        matches!(
+            self.event.announcement.oracle_event.event_descriptor,
+            EventDescriptor::EnumEvent(_)
+        )
+    }
+
+    fn to_sse(&self) -> sse::Event {
+        let message = StreamMessage {
+            event_id: &self.event.event_id,
+            announcement: (self.kind == ActivityKind::Announcement)
+                .then_some(&self.event.announcement),
+            attestation: match self.kind {
+                ActivityKind::Attestation => self.event.attestation(),
+                ActivityKind::Announcement => None,
+            },
+        };
+        sse::Event::default()
+            .id(self.id.to_string())
+            .event(self.kind.as_str())
+            .json_data(message)
+            .expect("serializable")
+    }
+}
+
+/// `data` of the stream's events
+#[derive(Debug, Clone, Serialize)]
+struct StreamMessage<'a> {
+    event_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    announcement: Option<&'a OracleAnnouncement>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attestation: Option<OracleAttestation>,
+}
+
+/// Records the announcements and attestations and sends them to the streams
+#[derive(Clone)]
+pub struct ActivityFeed {
+    storage: PostgresStorage,
+    live: broadcast::Sender<ActivityEvent>,
+}
+
+impl ActivityFeed {
+    pub fn new(storage: PostgresStorage) -> Self {
+        let (live, _) = broadcast::channel(LIVE_CAPACITY);
+        Self { storage, live }
+    }
+
+    async fn record(&self, event_id: &str, kind: ActivityKind) -> Result<(), Error> {
+        let id = self.storage.add_activity(event_id, kind.as_str()).await?;
+        let event = self
+            .storage
+            .get_event(event_id.to_string())
+            .await?
+            .ok_or_else(|| Error::NotFound {
+                event_id: event_id.to_string(),
+            })?;
+        // there may be no one listening
+        let _ = self.live.send(ActivityEvent {
+            id,
+            kind,
+            event: Arc::new(event),
+        });
+        Ok(())
+    }
+
+    /// Streams the activity matching the filter, starting with the activity
+    /// after `cursor` when resuming.
+    pub fn subscribe(
+        &self,
+        filter: ActivityFilter,
+        cursor: Option<i64>,
+    ) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+        // subscribe before replaying so nothing is missed in between
+        let mut live = self.live.subscribe();
+        let storage = self.storage.clone();
+        let (mut tx, rx) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            let _ = forward(&storage, &filter, cursor, &mut live, &mut tx).await;
+        });
+
+        rx.map(|activity| Ok(activity.to_sse()))
+    }
+}
+
+/// The recorded activity the streams replay from
+trait ActivityLog {
+    /// The id of the latest activity, 0 when there is none.
+    async fn latest_activity_id(&self) -> Result<i64, Error>;
+
+    /// The activity after the given id matching the filter, oldest first.
+    async fn list_activity(
+        &self,
+        after: i64,
+        filter: &ActivityFilter,
+        limit: i64,
+    ) -> Result<Vec<ActivityEvent>, Error>;
+}
+
+impl ActivityLog for PostgresStorage {
+    async fn latest_activity_id(&self) -> Result<i64, Error> {
+        PostgresStorage::latest_activity_id(self).await
+    }
+
+    async fn list_activity(
+        &self,
+        after: i64,
+        filter: &ActivityFilter,
+        limit: i64,
+    ) -> Result<Vec<ActivityEvent>, Error> {
+        PostgresStorage::list_activity(self, after, filter, limit)
+            .await?
+            .into_iter()
+            .map(|(activity, event)| {
+                Ok(ActivityEvent {
+                    id: activity.id,
+                    kind: activity.kind.parse().map_err(|_| Error::StorageFailure)?,
+                    event: Arc::new(event),
+                })
+            })
+            .collect()
+    }
+}
+
+/// The ids of the activity recently sent to a client, or skipped by its
+/// filter. Ids are not sent in order when concurrent requests commit out of
+/// order, so the last id alone does not tell what was sent.
+#[derive(Default)]
+struct SentWindow {
+    ids: HashSet<i64>,
+    order: VecDeque<i64>,
+}
+
+impl SentWindow {
+    /// Remembers the id, returns false when it was already sent.
+    fn insert(&mut self, id: i64) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > SENT_WINDOW {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    /// Where to replay from after missing live activity. Everything sent since
+    /// `cursor` is remembered until the window is full, then the activity
+    /// before the lowest id remembered is assumed to be sent.
+    fn resume_after(&self, cursor: i64) -> i64 {
+        match self.order.iter().min() {
+            Some(lowest) if self.order.len() == SENT_WINDOW => cursor.max(lowest - 1),
+            _ => cursor,
+        }
+    }
+}
+
+/// Sends the activity after `cursor` from the log, then the live activity.
+/// Fails when the client is gone or the log cannot be read.
+async fn forward(
+    log: &impl ActivityLog,
+    filter: &ActivityFilter,
+    cursor: Option<i64>,
+    live: &mut broadcast::Receiver<ActivityEvent>,
+    tx: &mut mpsc::Sender<ActivityEvent>,
+) -> Result<(), ()> {
+    let mut sent = SentWindow::default();
+    // everything up to the latest activity was committed before subscribing,
+    // anything after it is received live
+    let cursor = match cursor {
+        Some(after) => {
+            replay(log, filter, after, &mut sent, tx).await?;
+            after
+        }
+        None => log.latest_activity_id().await.map_err(|_| ())?,
+    };
+
+    loop {
+        let activity = match live.recv().await {
+            Ok(activity) => activity,
+            Err(broadcast::error::RecvError::Lagged(_)) => {
+                // the skipped activity is in the log
+                let after = sent.resume_after(cursor);
+                replay(log, filter, after, &mut sent, tx).await?;
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        };
+        // already sent while replaying
+        if !sent.insert(activity.id) {
+            continue;
+        }
+        if !filter.matches(&activity.event.event_id, activity.is_enum()) {
+            continue;
+        }
+        tx.send(activity).await.map_err(|_| ())?;
+    }
+}
+
+/// Sends the activity after `after` from the log that was not sent yet.
+async fn replay(
+    log: &impl ActivityLog,
+    filter: &ActivityFilter,
+    mut after: i64,
+    sent: &mut SentWindow,
+    tx: &mut mpsc::Sender<ActivityEvent>,
+) -> Result<(), ()> {
+    loop {
+        let page = log
+            .list_activity(after, filter, REPLAY_BATCH_SIZE)
+            .await
+            .map_err(|_| ())?;
+        let full = page.len() == REPLAY_BATCH_SIZE as usize;
+        for activity in page {
+            after = activity.id;
+            if sent.insert(activity.id) {
+                tx.send(activity).await.map_err(|_| ())?;
+            }
+        }
+        if !full {
+            return Ok(());
+        }
+    }
+}
+
+impl OracleObserver for ActivityFeed {
+    async fn on_announcement(&self, announcement: &OracleAnnouncement) -> Result<(), Error> {
+        self.record(
+            &announcement.oracle_event.event_id,
+            ActivityKind::Announcement,
+        )
+        .await
+    }
+
+    async fn on_attestation(&self, attestation: &OracleAttestation) -> Result<(), Error> {
+        self.record(&attestation.event_id, ActivityKind::Attestation)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::secp256k1::SecretKey;
+    use kormir::storage::MemoryStorage;
+    use kormir::Oracle;
+
+    /// Activity recorded in memory
+    struct MemoryLog {
+        activity: Vec<ActivityEvent>,
+        /// The latest id when the client subscribed
+        latest: i64,
+    }
+
+    impl ActivityLog for MemoryLog {
+        async fn latest_activity_id(&self) -> Result<i64, Error> {
+            Ok(self.latest)
+        }
+
+        async fn list_activity(
+            &self,
+            after: i64,
+            filter: &ActivityFilter,
+            limit: i64,
+        ) -> Result<Vec<ActivityEvent>, Error> {
+            let mut activity = self
+                .activity
+                .iter()
+                .filter(|a| a.id > after && filter.matches(&a.event.event_id, a.is_enum()))
+                .cloned()
+                .collect::<Vec<_>>();
+            activity.sort_by_key(|a| a.id);
+            activity.truncate(limit as usize);
+            Ok(activity)
+        }
+    }
+
+    async fn event() -> Arc<OracleEventData> {
+        let signing_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let oracle = Oracle::from_signing_key(MemoryStorage::default(), signing_key).unwrap();
+        oracle
+            .create_enum_event("btc".to_string(), vec!["a".to_string()], 0)
+            .await
+            .unwrap();
+        let event = oracle.storage.get_event("btc".to_string()).await.unwrap();
+        Arc::new(event.unwrap())
+    }
+
+    fn activity(event: &Arc<OracleEventData>, ids: &[i64]) -> Vec<ActivityEvent> {
+        ids.iter()
+            .map(|&id| ActivityEvent {
+                id,
+                kind: ActivityKind::Announcement,
+                event: event.clone(),
+            })
+            .collect()
+    }
+
+    /// Streams to a client in the background, returns its activity.
+    fn connect(
+        log: MemoryLog,
+        cursor: Option<i64>,
+        mut live: broadcast::Receiver<ActivityEvent>,
+    ) -> mpsc::Receiver<ActivityEvent> {
+        let (mut tx, rx) = mpsc::channel(LIVE_CAPACITY);
+        tokio::spawn(async move {
+            let filter = ActivityFilter::default();
+            let _ = forward(&log, &filter, cursor, &mut live, &mut tx).await;
+        });
+        rx
+    }
+
+    #[tokio::test]
+    async fn test_replay_then_live() {
+        let event = event().await;
+        let (live_tx, live) = broadcast::channel(LIVE_CAPACITY);
+        // 3 is committed after subscribing and is both replayed and live
+        let log = MemoryLog {
+            activity: activity(&event, &[1, 2, 3]),
+            latest: 3,
+        };
+        for activity in activity(&event, &[3, 4, 5]) {
+            live_tx.send(activity).unwrap();
+        }
+        drop(live_tx);
+
+        let sent = connect(log, Some(1), live)
+            .map(|a| a.id)
+            .collect::<Vec<_>>();
+        assert_eq!(sent.await, vec![2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_live_out_of_order() {
+        let event = event().await;
+        let (live_tx, live) = broadcast::channel(LIVE_CAPACITY);
+        let log = MemoryLog {
+            activity: activity(&event, &[1, 2]),
+            latest: 2,
+        };
+        // 3 is committed after 4
+        for activity in activity(&event, &[4, 3, 5]) {
+            live_tx.send(activity).unwrap();
+        }
+        drop(live_tx);
+
+        let sent = connect(log, None, live).map(|a| a.id).collect::<Vec<_>>();
+        assert_eq!(sent.await, vec![4, 3, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_lagged_replays_missed() {
+        let event = event().await;
+        let (live_tx, live) = broadcast::channel(2);
+        // 3 is committed after 4, and missed with 5 by the lagging client
+        let log = MemoryLog {
+            activity: activity(&event, &[1, 2, 4, 3, 5, 6, 7]),
+            latest: 2,
+        };
+        let mut activity = activity(&event, &[4, 3, 5, 6, 7]).into_iter();
+        live_tx.send(activity.next().unwrap()).unwrap();
+
+        let mut rx = connect(log, None, live);
+        assert_eq!(rx.next().await.unwrap().id, 4);
+        for activity in activity {
+            live_tx.send(activity).unwrap();
+        }
+        drop(live_tx);
+
+        let sent = rx.map(|a| a.id).collect::<Vec<_>>();
+        assert_eq!(sent.await, vec![3, 5, 6, 7]);
+    }
+
+    #[test]
+    fn test_resume_after() {
+        let mut sent = SentWindow::default();
+        assert_eq!(sent.resume_after(10), 10);
+        assert!(sent.insert(12));
+        assert!(!sent.insert(12));
+        assert_eq!(sent.resume_after(10), 10);
+
+        for id in 13..13 + SENT_WINDOW as i64 {
+            sent.insert(id);
+        }
+        assert_eq!(sent.ids.len(), SENT_WINDOW);
+        assert_eq!(sent.resume_after(10), 12);
+        // forgotten once out of the window
+        assert!(sent.insert(12));
+    }
+}
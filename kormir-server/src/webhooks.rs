@@ -0,0 +1,434 @@
+//! Webhooks notified when events are created, mature, are attested or are
+//! cancelled.
+//!
+//! Notifications are saved to the `webhook_deliveries` table when they
+//! happen, then POSTed by a background sender that retries failed deliveries
+//! with an exponential backoff. A webhook is only notified of the events
+//! maturing after it was first configured, not of the whole history.
+//!
+//! `X-Signature` is the hex HMAC-SHA256, keyed with `KORMIR_WEBHOOK_SECRET`,
+//! of the timestamp, the delivery id and the raw body. Unlike the
+//! authenticated routes there is no nonce, method or path, receivers can
+//! reject replays with the delivery id:
+//!
+//! ```text
+//! {X-Timestamp}\n{X-Delivery-Id}\n{body}
+//! ```
+
+use crate::models::webhook::{NewWebhookDelivery, WebhookDelivery};
+use crate::models::PostgresStorage;
+use chrono::{DateTime, NaiveDateTime};
+use dlc_messages::oracle_msgs::{OracleAnnouncement, OracleAttestation};
+use hmac::{Hmac, Mac};
+use kormir::error::Error;
+use kormir::lightning::util::ser::Writeable;
+use kormir::observer::OracleObserver;
+use kormir::retry::retry_delay;
+use kormir::storage::OracleEventData;
+use nostr::Url;
+use serde::Serialize;
+use sha2::Sha256;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Notify;
+
+/// Deliveries are given up on after this many failed attempts
+pub const MAX_ATTEMPTS: i32 = 20;
+/// Deliveries sent, and matured events looked up, at each run of the sender
+const BATCH_SIZE: i64 = 100;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What happened to the event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    Created,
+    /// The maturity time passed and the event has not been attested yet
+    Matured,
+    Attested,
+    Cancelled,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::Created => "event.created",
+            WebhookEvent::Matured => "event.matured",
+            WebhookEvent::Attested => "event.attested",
+            WebhookEvent::Cancelled => "event.cancelled",
+        }
+    }
+}
+
+impl Display for WebhookEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Body POSTed to the webhooks
+#[derive(Debug, Clone, Serialize)]
+struct WebhookPayload<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    event_id: &'a str,
+    /// Unix time the notification was created
+    created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    announcement: Option<&'a OracleAnnouncement>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    announcement_hex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attestation: Option<&'a OracleAttestation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attestation_hex: Option<String>,
+}
+
+impl<'a> WebhookPayload<'a> {
+    fn announcement(kind: WebhookEvent, announcement: &'a OracleAnnouncement) -> Self {
+        Self {
+            kind: kind.as_str(),
+            event_id: &announcement.oracle_event.event_id,
+            created_at: now(),
+            announcement: Some(announcement),
+            announcement_hex: Some(hex::encode(announcement.encode())),
+            attestation: None,
+            attestation_hex: None,
+        }
+    }
+
+    fn attestation(attestation: &'a OracleAttestation) -> Self {
+        Self {
+            kind: WebhookEvent::Attested.as_str(),
+            event_id: &attestation.event_id,
+            created_at: now(),
+            announcement: None,
+            announcement_hex: None,
+            attestation: Some(attestation),
+            attestation_hex: Some(hex::encode(attestation.encode())),
+        }
+    }
+}
+
+/// Queues and sends the webhook notifications
+#[derive(Clone)]
+pub struct Webhooks {
+    storage: PostgresStorage,
+    /// The webhooks and the unix time they were first configured
+    urls: Vec<(Url, u32)>,
+    secret: Vec<u8>,
+    client: reqwest::Client,
+    /// Wakes the sender when a notification is queued
+    queued: Arc<Notify>,
+}
+
+impl Webhooks {
+    pub async fn new(
+        storage: PostgresStorage,
+        urls: Vec<Url>,
+        secret: Vec<u8>,
+    ) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+
+        let mut webhooks = Vec::with_capacity(urls.len());
+        for url in urls {
+            let webhook = storage.register_webhook(url.as_str()).await?;
+            let since = webhook.created_at.and_utc().timestamp() as u32;
+            webhooks.push((url, since));
+        }
+
+        Ok(Self {
+            storage,
+            urls: webhooks,
+            secret,
+            client,
+            queued: Arc::new(Notify::new()),
+        })
+    }
+
+    /// Saves a delivery of the payload for every webhook.
+    async fn queue(&self, kind: WebhookEvent, payload: &WebhookPayload<'_>) -> Result<(), Error> {
+        let json = serde_json::to_string(payload).map_err(|_| Error::Internal)?;
+        for (url, _) in &self.urls {
+            let delivery = NewWebhookDelivery {
+                webhook_url: url.as_str(),
+                event_id: payload.event_id,
+                kind: kind.as_str(),
+                payload: &json,
+            };
+            self.storage.add_webhook_delivery(delivery).await?;
+        }
+        self.queued.notify_one();
+        Ok(())
+    }
+
+    /// Queues the notifications of the events that matured since the last run.
+    async fn queue_matured(&self) -> Result<(), Error> {
+        let kind = WebhookEvent::Matured;
+        for (url, since) in &self.urls {
+            let events = self
+                .storage
+                .list_matured_unnotified(
+                    *since,
+                    now() as u32,
+                    url.as_str(),
+                    kind.as_str(),
+                    BATCH_SIZE,
+                )
+                .await?;
+            for event in events {
+                let payload = WebhookPayload::announcement(kind, &event.announcement);
+                let payload = serde_json::to_string(&payload).map_err(|_| Error::Internal)?;
+                let delivery = NewWebhookDelivery {
+                    webhook_url: url.as_str(),
+                    event_id: &event.event_id,
+                    kind: kind.as_str(),
+                    payload: &payload,
+                };
+                self.storage.add_webhook_delivery(delivery).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the deliveries that are due, returns how many were delivered and
+    /// how many failed.
+    async fn send_due(&self) -> Result<(usize, usize), Error> {
+        let due = self
+            .storage
+            .list_due_webhook_deliveries(MAX_ATTEMPTS, BATCH_SIZE)
+            .await?;
+
+        let (mut delivered, mut failed) = (0, 0);
+        for delivery in due {
+            match self.send(&delivery).await {
+                Ok(()) => {
+                    self.storage.set_webhook_delivered(delivery.id).await?;
+                    delivered += 1;
+                }
+                Err(e) => {
+                    let attempts = delivery.attempts as u32 + 1;
+                    log::warn!(
+                        "Webhook delivery {} to {} failed ({attempts} attempts): {e}",
+                        delivery.id,
+                        delivery.webhook_url
+                    );
+                    let next_attempt = timestamp(now() + retry_delay(attempts));
+                    self.storage
+                        .set_webhook_failed(delivery.id, &e, next_attempt)
+                        .await?;
+                    failed += 1;
+                }
+            }
+        }
+        Ok((delivered, failed))
+    }
+
+    async fn send(&self, delivery: &WebhookDelivery) -> Result<(), String> {
+        let timestamp = now().to_string();
+        let id = delivery.id.to_string();
+        let signature = sign(&self.secret, &timestamp, &id, &delivery.payload);
+
+        let response = self
+            .client
+            .post(&delivery.webhook_url)
+            .header("Content-Type", "application/json")
+            .header("X-Webhook-Event", &delivery.kind)
+            .header("X-Delivery-Id", id)
+            .header("X-Timestamp", timestamp)
+            .header("X-Signature", signature)
+            .body(delivery.payload.clone())
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(format!("webhook responded with {}", response.status()))
+        }
+    }
+}
+
+impl OracleObserver for Webhooks {
+    async fn on_announcement(&self, announcement: &OracleAnnouncement) -> Result<(), Error> {
+        let kind = WebhookEvent::Created;
+        self.queue(kind, &WebhookPayload::announcement(kind, announcement))
+            .await
+    }
+
+    async fn on_attestation(&self, attestation: &OracleAttestation) -> Result<(), Error> {
+        self.queue(
+            WebhookEvent::Attested,
+            &WebhookPayload::attestation(attestation),
+        )
+        .await
+    }
+
+    async fn on_cancellation(&self, event: &OracleEventData) -> Result<(), Error> {
+        let kind = WebhookEvent::Cancelled;
+        self.queue(
+            kind,
+            &WebhookPayload::announcement(kind, &event.announcement),
+        )
+        .await
+    }
+}
+
+/// Queues the matured events and sends the due deliveries every `interval`,
+/// and as soon as a notification is queued.
+pub fn spawn_webhook_sender(webhooks: Webhooks, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = webhooks.queued.notified() => {}
+            }
+            if let Err(e) = webhooks.queue_matured().await {
+                log::error!("Failed to queue matured event notifications: {e}");
+            }
+            match webhooks.send_due().await {
+                Ok((0, 0)) => {}
+                Ok((delivered, failed)) => {
+                    log::info!("Sent webhooks: {delivered} delivered, {failed} failed")
+                }
+                Err(e) => log::error!("Failed to send webhooks: {e}"),
+            }
+        }
+    });
+}
+
+/// The hex HMAC-SHA256 of the delivery, see the module documentation.
+pub fn sign(secret: &[u8], timestamp: &str, delivery_id: &str, payload: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(format!("{timestamp}\n{delivery_id}\n{payload}").as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn timestamp(secs: u64) -> NaiveDateTime {
+    DateTime::from_timestamp(secs as i64, 0)
+        .expect("valid timestamp")
+        .naive_utc()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin::secp256k1::SecretKey;
+    use kormir::retry::MAX_RETRY_DELAY;
+    use kormir::storage::MemoryStorage;
+    use kormir::Oracle;
+    use serde_json::Value;
+
+    #[test]
+    fn test_sign() {
+        let payload = r#"{"type":"event.created"}"#;
+        assert_eq!(
+            sign(b"hooksecret", "1700000000", "42", payload),
+            "4ad3f6b1cc713bce472f0c6945e4f546357de1019010abae3cad91fa336bfcbb"
+        );
+        // every part is signed
+        assert_ne!(
+            sign(b"hooksecret", "1700000001", "42", payload),
+            sign(b"hooksecret", "1700000000", "42", payload)
+        );
+        assert_ne!(
+            sign(b"hooksecret", "1700000000", "43", payload),
+            sign(b"hooksecret", "1700000000", "42", payload)
+        );
+        assert_ne!(
+            sign(b"other", "1700000000", "42", payload),
+            sign(b"hooksecret", "1700000000", "42", payload)
+        );
+    }
+
+    #[test]
+    fn test_retries_are_capped() {
+        // deliveries are retried at most hourly before they are given up on
+        assert_eq!(retry_delay(MAX_ATTEMPTS as u32 - 1), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_payloads() {
+        let signing_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let oracle = Oracle::from_signing_key(MemoryStorage::default(), signing_key).unwrap();
+        let announcement = oracle
+            .create_enum_event("btc".to_string(), vec!["a".to_string()], 0)
+            .await
+            .unwrap();
+        let attestation = oracle
+            .sign_enum_event("btc".to_string(), "a".to_string())
+            .await
+            .unwrap();
+
+        let json = |payload: WebhookPayload| serde_json::to_value(payload).unwrap();
+        let keys = |value: &Value| {
+            let mut keys = value
+                .as_object()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>();
+            keys.sort();
+            keys
+        };
+
+        for kind in [
+            WebhookEvent::Created,
+            WebhookEvent::Matured,
+            WebhookEvent::Cancelled,
+        ] {
+            let value = json(WebhookPayload::announcement(kind, &announcement));
+            assert_eq!(
+                keys(&value),
+                [
+                    "announcement",
+                    "announcement_hex",
+                    "created_at",
+                    "event_id",
+                    "type"
+                ]
+            );
+            assert_eq!(value["type"], kind.as_str());
+            assert_eq!(value["event_id"], "btc");
+            assert_eq!(
+                value["announcement_hex"],
+                hex::encode(announcement.encode())
+            );
+            assert_eq!(
+                value["announcement"],
+                serde_json::to_value(&announcement).unwrap()
+            );
+        }
+
+        let value = json(WebhookPayload::attestation(&attestation));
+        assert_eq!(
+            keys(&value),
+            [
+                "attestation",
+                "attestation_hex",
+                "created_at",
+                "event_id",
+                "type"
+            ]
+        );
+        assert_eq!(value["type"], "event.attested");
+        assert_eq!(value["event_id"], "btc");
+        assert_eq!(value["attestation_hex"], hex::encode(attestation.encode()));
+        assert_eq!(
+            value["attestation"],
+            serde_json::to_value(&attestation).unwrap()
+        );
+        assert!(value["created_at"].as_u64().unwrap() >= now() - 60);
+    }
+}
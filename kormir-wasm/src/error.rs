@@ -9,6 +9,9 @@ pub enum JsError {
     /// Invalid argument given
     #[error("Invalid argument given")]
     InvalidArgument,
+    /// Attempted to create an event with an id that is already used
+    #[error("Attempted to create an event that already exists")]
+    EventAlreadyExists,
     /// Attempted to sign an event that was already signed
     #[error("Attempted to sign an event that was already signed")]
     EventAlreadySigned,
@@ -21,37 +24,64 @@ pub enum JsError {
     /// User gave an invalid outcome
     #[error("User gave an invalid outcome")]
     InvalidOutcome,
+    /// The event was withdrawn and can no longer be signed
+    #[error("The event was withdrawn")]
+    EventWithdrawn,
     /// An error that should never happen, if it does it's a bug
     #[error("Internal Error")]
     Internal,
     /// An error with creating or sending Nostr events
     #[error("Error sending nostr events")]
     Nostr,
+    /// The storage does not support the operation
+    #[error("Operation not supported by the storage")]
+    Unsupported,
 }
 
 impl From<Error> for JsError {
     fn from(value: Error) -> Self {
         match value {
-            Error::InvalidArgument => Self::InvalidArgument,
-            Error::EventAlreadySigned => Self::EventAlreadySigned,
-            Error::NotFound => Self::NotFound,
+            Error::InvalidArgument { .. } => Self::InvalidArgument,
+            Error::EventAlreadyExists { .. } => Self::EventAlreadyExists,
+            Error::EventAlreadySigned { .. } => Self::EventAlreadySigned,
+            Error::NotFound { .. } => Self::NotFound,
             Error::StorageFailure => Self::StorageFailure,
-            Error::InvalidOutcome => Self::InvalidOutcome,
+            Error::InvalidOutcome { .. } => Self::InvalidOutcome,
+            Error::EventWithdrawn { .. } => Self::EventWithdrawn,
             Error::Internal => Self::Internal,
+            Error::Nostr => Self::Nostr,
+            Error::Unsupported => Self::Unsupported,
         }
     }
 }
 
+/// The JS errors carry no context, so the event id and reason are left empty.
 impl From<JsError> for Error {
     fn from(value: JsError) -> Self {
         match value {
-            JsError::InvalidArgument => Self::InvalidArgument,
-            JsError::EventAlreadySigned => Self::EventAlreadySigned,
-            JsError::NotFound => Self::NotFound,
+            JsError::InvalidArgument => Self::InvalidArgument {
+                reason: String::new(),
+            },
+            JsError::EventAlreadyExists => Self::EventAlreadyExists {
+                event_id: String::new(),
+            },
+            JsError::EventAlreadySigned => Self::EventAlreadySigned {
+                event_id: String::new(),
+            },
+            JsError::NotFound => Self::NotFound {
+                event_id: String::new(),
+            },
             JsError::StorageFailure => Self::StorageFailure,
-            JsError::InvalidOutcome => Self::InvalidOutcome,
+            JsError::InvalidOutcome => Self::InvalidOutcome {
+                event_id: String::new(),
+                reason: String::new(),
+            },
+            JsError::EventWithdrawn => Self::EventWithdrawn {
+                event_id: String::new(),
+            },
             JsError::Internal => Self::Internal,
-            JsError::Nostr => Self::Internal,
+            JsError::Nostr => Self::Nostr,
+            JsError::Unsupported => Self::Unsupported,
         }
     }
 }
@@ -1,12 +1,16 @@
 use std::str::FromStr;
 
 use gloo_utils::format::JsValueSerdeExt;
-use nostr::{EventId, JsonUtil, Keys};
+use nostr::{Keys, Url};
 use nostr_sdk::Client;
 use wasm_bindgen::prelude::wasm_bindgen;
 use wasm_bindgen::JsValue;
 
+use kormir::audit::{AuditAction, AuditRecord, RESULT_OK};
 use kormir::bitcoin::secp256k1::SecretKey;
+use kormir::error::Error;
+use kormir::nostr_events::{DescriptorType, OracleProfile};
+use kormir::nostr_publisher::NostrPublisher;
 use kormir::storage::Storage;
 use kormir::{Oracle, OracleAnnouncement, OracleAttestation, Readable, Writeable};
 
@@ -22,13 +26,14 @@ mod utils;
 #[derive(Debug, Clone)]
 #[wasm_bindgen]
 pub struct Kormir {
-    oracle: Oracle<IndexedDb>,
+    oracle: Oracle<IndexedDb, Option<NostrPublisher<IndexedDb>>>,
     storage: IndexedDb,
-    client: Client,
 }
 
 #[wasm_bindgen]
 impl Kormir {
+    /// Opens the oracle stored in IndexedDB, publishing its events to the given
+    /// relays. Without relays nothing is sent to nostr.
     pub async fn new(relays: Vec<String>) -> Result<Kormir, JsError> {
         utils::set_panic_hook();
         let storage = IndexedDb::new().await?;
@@ -50,16 +55,24 @@ impl Kormir {
 
         let oracle = Oracle::from_signing_key(storage.clone(), nsec)?;
 
-        let client = Client::new(oracle.nostr_keys());
-        for relay in &relays {
-            client.add_relay(relay.as_str()).await?;
-        }
-        client.connect().await;
+        let publisher = if relays.is_empty() {
+            None
+        } else {
+            let client = Client::new(oracle.nostr_keys());
+            for relay in &relays {
+                client.add_relay(relay.as_str()).await?;
+            }
+            client.connect().await;
+            Some(NostrPublisher::new(
+                storage.clone(),
+                oracle.nostr_keys(),
+                client,
+            ))
+        };
 
         Ok(Kormir {
-            oracle,
+            oracle: oracle.with_observer(publisher),
             storage,
-            client,
         })
     }
 
@@ -88,31 +101,15 @@ impl Kormir {
         let ann = self
             .oracle
             .create_enum_event(event_id.clone(), outcomes, event_maturity_epoch)
-            .await?;
+            .await;
+        self.audit(AuditAction::CreateEnumEvent, event_id, None, &ann)
+            .await;
+        let ann = ann?;
 
         let hex = hex::encode(ann.encode());
 
         log::info!("Created enum event: {hex}");
 
-        let event =
-            kormir::nostr_events::create_announcement_event(&self.oracle.nostr_keys(), &ann)
-                .map_err(|_| JsError::Nostr)?;
-
-        log::debug!("Created nostr event: {}", event.as_json());
-
-        self.storage
-            .add_announcement_event_id(event_id, event.id.to_hex())
-            .await?;
-
-        log::debug!(
-            "Added announcement event id to storage: {}",
-            event.id.to_hex()
-        );
-
-        self.client.send_event(&event).await?;
-
-        log::trace!("Sent event to nostr");
-
         Ok(hex)
     }
 
@@ -123,28 +120,16 @@ impl Kormir {
     ) -> Result<String, JsError> {
         let attestation = self
             .oracle
-            .sign_enum_event(event_id.clone(), outcome)
-            .await?;
-
-        let event = self
-            .storage
-            .get_event(event_id.clone())
-            .await?
-            .ok_or(JsError::NotFound)?;
-        let nostr_event_id = EventId::from_hex(&event.announcement_event_id.unwrap()).unwrap();
-
-        let event = kormir::nostr_events::create_attestation_event(
-            &self.oracle.nostr_keys(),
+            .sign_enum_event(event_id.clone(), outcome.clone())
+            .await;
+        self.audit(
+            AuditAction::SignEnumEvent,
+            event_id,
+            Some(outcome),
             &attestation,
-            nostr_event_id,
         )
-        .map_err(|_| JsError::Nostr)?;
-
-        self.storage
-            .add_attestation_event_id(event_id, event.id.to_hex())
-            .await?;
-
-        self.client.send_event(&event).await?;
+        .await;
+        let attestation = attestation?;
 
         Ok(hex::encode(attestation.encode()))
     }
@@ -168,31 +153,15 @@ impl Kormir {
                 unit,
                 event_maturity_epoch,
             )
-            .await?;
+            .await;
+        self.audit(AuditAction::CreateNumericEvent, event_id, None, &ann)
+            .await;
+        let ann = ann?;
 
         let hex = hex::encode(ann.encode());
 
         log::info!("Created numeric event: {hex}");
 
-        let event =
-            kormir::nostr_events::create_announcement_event(&self.oracle.nostr_keys(), &ann)
-                .map_err(|_| JsError::Nostr)?;
-
-        log::debug!("Created nostr event: {}", event.as_json());
-
-        self.storage
-            .add_announcement_event_id(event_id, event.id.to_hex())
-            .await?;
-
-        log::debug!(
-            "Added announcement event id to storage: {}",
-            event.id.to_hex()
-        );
-
-        self.client.send_event(&event).await?;
-
-        log::trace!("Sent event to nostr");
-
         Ok(hex)
     }
 
@@ -204,29 +173,91 @@ impl Kormir {
         let attestation = self
             .oracle
             .sign_numeric_event(event_id.clone(), outcome)
-            .await?;
-
-        let event = self
-            .storage
-            .get_event(event_id.clone())
-            .await?
-            .ok_or(JsError::NotFound)?;
-        let nostr_event_id = EventId::from_hex(&event.announcement_event_id.unwrap()).unwrap();
-
-        let event = kormir::nostr_events::create_attestation_event(
-            &self.oracle.nostr_keys(),
+            .await;
+        self.audit(
+            AuditAction::SignNumericEvent,
+            event_id,
+            Some(outcome.to_string()),
             &attestation,
-            nostr_event_id,
         )
-        .map_err(|_| JsError::Nostr)?;
+        .await;
+        let attestation = attestation?;
+
+        Ok(hex::encode(attestation.encode()))
+    }
 
-        self.storage
-            .add_attestation_event_id(event_id, event.id.to_hex())
+    /// Withdraws an announcement that will never be signed and asks relays to
+    /// delete it.
+    pub async fn withdraw_event(&self, event_id: String) -> Result<EventData, JsError> {
+        let data = self.oracle.withdraw_event(event_id.clone()).await;
+        self.audit(AuditAction::WithdrawEvent, event_id, None, &data)
+            .await;
+        Ok(data?.into())
+    }
+
+    /// Returns at most `limit` entries of the audit log, starting at the given
+    /// sequence number.
+    pub async fn list_audit_log(
+        &self,
+        from: u64,
+        limit: u32,
+    ) -> Result<JsValue /* Vec<AuditEntry> */, JsError> {
+        let entries = self
+            .storage
+            .list_audit_entries(from, limit as usize)
             .await?;
 
-        self.client.send_event(&event).await?;
+        Ok(JsValue::from_serde(&entries)?)
+    }
 
-        Ok(hex::encode(attestation.encode()))
+    /// Returns the ids of the nostr events that have not been published yet.
+    pub async fn list_outbox(&self) -> Result<JsValue /* Vec<String> */, JsError> {
+        let pending = match self.oracle.observer() {
+            Some(publisher) => publisher.pending().await?,
+            None => Vec::new(),
+        };
+        let ids = pending
+            .into_iter()
+            .map(|e| e.nostr_event.id.to_hex())
+            .collect::<Vec<_>>();
+
+        Ok(JsValue::from_serde(&ids)?)
+    }
+
+    /// Retries publishing the nostr events that could not be sent yet,
+    /// returning the number of events still waiting to be published.
+    pub async fn flush_outbox(&self) -> Result<u32, JsError> {
+        let Some(publisher) = self.oracle.observer() else {
+            return Ok(0);
+        };
+        let result = publisher.flush(true).await;
+        self.audit(AuditAction::FlushOutbox, String::new(), None, &result)
+            .await;
+        Ok(result?.failed.len() as u32)
+    }
+
+    /// Publishes the oracle's nostr profile and the relay list of the relays it
+    /// is connected to.
+    pub async fn publish_profile(
+        &self,
+        name: String,
+        description: Option<String>,
+        http_endpoint: Option<String>,
+    ) -> Result<(), JsError> {
+        let http_endpoint = http_endpoint
+            .map(|url| Url::parse(&url))
+            .transpose()
+            .map_err(|_| JsError::InvalidArgument)?;
+        let profile = OracleProfile {
+            name,
+            description,
+            http_endpoint,
+            descriptor_types: vec![DescriptorType::Enum, DescriptorType::DigitDecomposition],
+        };
+
+        let publisher = self.oracle.observer().as_ref().ok_or(JsError::Nostr)?;
+        publisher.publish_profile(&profile).await?;
+        Ok(())
     }
 
     pub async fn list_events(&self) -> Result<JsValue /* Vec<EventData> */, JsError> {
@@ -250,3 +281,31 @@ impl Kormir {
         Ok(attestation.into())
     }
 }
+
+impl Kormir {
+    /// Records the action in the audit log. The action already happened, so a
+    /// failure to record it is only logged.
+    async fn audit<T>(
+        &self,
+        action: AuditAction,
+        event_id: String,
+        outcome: Option<String>,
+        result: &Result<T, Error>,
+    ) {
+        let record = AuditRecord {
+            action: action.into(),
+            event_id,
+            outcome,
+            key_id: None,
+            source: None,
+            timestamp: nostr::Timestamp::now().as_u64(),
+            result: match result {
+                Ok(_) => RESULT_OK.to_string(),
+                Err(e) => e.code().to_string(),
+            },
+        };
+        if let Err(e) = self.storage.append_audit_record(record).await {
+            log::error!("Failed to append to the audit log: {e}");
+        }
+    }
+}
@@ -131,6 +131,7 @@ pub struct EventData {
     announcement_event_id: Option<String>,
     attestation_event_id: Option<String>,
     observed_outcome: Option<String>,
+    pub withdrawn: bool,
 }
 
 #[wasm_bindgen]
@@ -176,8 +177,8 @@ impl EventData {
     }
 }
 
-impl From<(String, OracleEventData)> for EventData {
-    fn from((id, value): (String, OracleEventData)) -> Self {
+impl From<OracleEventData> for EventData {
+    fn from(value: OracleEventData) -> Self {
         let outcomes = match &value.announcement.oracle_event.event_descriptor {
             EventDescriptor::EnumEvent(e) => e.outcomes.clone(),
             EventDescriptor::DigitDecompositionEvent(_) => {
@@ -213,7 +214,7 @@ impl From<(String, OracleEventData)> for EventData {
         };
 
         EventData {
-            event_id: id,
+            event_id: value.event_id,
             announcement: hex::encode(value.announcement.encode()),
             attestation,
             event_maturity_epoch: value.announcement.oracle_event.event_maturity_epoch,
@@ -222,6 +223,7 @@ impl From<(String, OracleEventData)> for EventData {
             announcement_event_id: value.announcement_event_id,
             attestation_event_id: value.attestation_event_id,
             observed_outcome,
+            withdrawn: value.withdrawn,
         }
     }
 }
@@ -1,8 +1,10 @@
 use crate::error::JsError;
 use gloo_utils::format::JsValueSerdeExt;
+use kormir::audit::{AuditEntry, AuditRecord};
 use kormir::error::Error;
-use kormir::storage::{OracleEventData, Storage};
+use kormir::storage::{OracleEventData, OutboxEvent, Storage};
 use kormir::{OracleAnnouncement, Signature};
+use nostr::EventId;
 use rexie::{ObjectStore, Rexie, TransactionMode};
 use serde::Serialize;
 use std::sync::atomic::{AtomicU32, Ordering};
@@ -14,11 +16,23 @@ const OBJECT_STORE_NAME: &str = "oracle";
 pub const NSEC_KEY: &str = "nsec";
 const NONCE_INDEX_KEY: &str = "nonce_index";
 const ORACLE_DATA_PREFIX: &str = "oracle_data/";
+const OUTBOX_PREFIX: &str = "outbox/";
+const AUDIT_HEAD_KEY: &str = "audit_head";
+const AUDIT_PREFIX: &str = "audit/";
 
 fn get_oracle_data_key(event_id: String) -> String {
     format!("{ORACLE_DATA_PREFIX}{event_id}")
 }
 
+/// Zero padded so the keys sort in sequence order
+fn get_audit_key(sequence: u64) -> String {
+    format!("{AUDIT_PREFIX}{sequence:020}")
+}
+
+fn get_outbox_key(nostr_event_id: EventId) -> String {
+    format!("{OUTBOX_PREFIX}{}", nostr_event_id.to_hex())
+}
+
 #[derive(Debug, Clone)]
 pub struct IndexedDb {
     current_index: Arc<AtomicU32>,
@@ -86,10 +100,10 @@ impl IndexedDb {
         Ok(value)
     }
 
-    pub async fn add_announcement_event_id(
+    async fn update_event(
         &self,
         event_id: String,
-        nostr_event_id: String,
+        update: impl FnOnce(&mut OracleEventData),
     ) -> Result<(), JsError> {
         let tx = self
             .rexie
@@ -98,31 +112,52 @@ impl IndexedDb {
         let key = JsValue::from_serde(&get_oracle_data_key(event_id))?;
         let js = store.get(&key).await?;
         let mut event: OracleEventData = js.into_serde()?;
-        event.announcement_event_id = Some(nostr_event_id);
+        update(&mut event);
         store.put(&JsValue::from_serde(&event)?, Some(&key)).await?;
         tx.done().await?;
         Ok(())
     }
 
-    pub async fn add_attestation_event_id(
-        &self,
-        event_id: String,
-        nostr_event_id: String,
-    ) -> Result<(), JsError> {
+    async fn list_oracle_data(&self) -> Result<Vec<OracleEventData>, JsError> {
+        let tx = self
+            .rexie
+            .transaction(&[OBJECT_STORE_NAME], TransactionMode::ReadOnly)?;
+        let store = tx.store(OBJECT_STORE_NAME)?;
+        let all = store.get_all(None, None, None, None).await?;
+        tx.done().await?;
+
+        let mut vec = Vec::with_capacity(all.len());
+        for (key, value) in all {
+            let key: String = key.into_serde()?;
+            if key.starts_with(ORACLE_DATA_PREFIX) {
+                vec.push(value.into_serde()?);
+            }
+        }
+
+        Ok(vec)
+    }
+
+    async fn append_audit(&self, record: AuditRecord) -> Result<AuditEntry, JsError> {
         let tx = self
             .rexie
             .transaction(&[OBJECT_STORE_NAME], TransactionMode::ReadWrite)?;
         let store = tx.store(OBJECT_STORE_NAME)?;
-        let key = JsValue::from_serde(&get_oracle_data_key(event_id))?;
-        let js = store.get(&key).await?;
-        let mut event: OracleEventData = js.into_serde()?;
-        event.attestation_event_id = Some(nostr_event_id);
-        store.put(&JsValue::from_serde(&event)?, Some(&key)).await?;
+        let head_key = JsValue::from_serde(AUDIT_HEAD_KEY)?;
+        let head: Option<AuditEntry> = store.get(&head_key).await?.into_serde()?;
+        let entry = AuditEntry::new(head.as_ref(), record);
+        let value = JsValue::from_serde(&entry)?;
+        store
+            .put(
+                &value,
+                Some(&JsValue::from_serde(&get_audit_key(entry.sequence))?),
+            )
+            .await?;
+        store.put(&value, Some(&head_key)).await?;
         tx.done().await?;
-        Ok(())
+        Ok(entry)
     }
 
-    pub async fn list_events(&self) -> Result<Vec<(String, OracleEventData)>, JsError> {
+    async fn list_audit(&self, from: u64, limit: usize) -> Result<Vec<AuditEntry>, JsError> {
         let tx = self
             .rexie
             .transaction(&[OBJECT_STORE_NAME], TransactionMode::ReadOnly)?;
@@ -130,22 +165,49 @@ impl IndexedDb {
         let all = store.get_all(None, None, None, None).await?;
         tx.done().await?;
 
-        let mut vec = Vec::with_capacity(all.len());
+        let start = get_audit_key(from);
+        let mut vec = Vec::new();
         for (key, value) in all {
             let key: String = key.into_serde()?;
-            if key.starts_with(ORACLE_DATA_PREFIX) {
-                let data: OracleEventData = value.into_serde()?;
-                let id = key
-                    .strip_prefix(ORACLE_DATA_PREFIX)
-                    .expect("just checked")
-                    .to_string();
-                vec.push((id, data))
+            if key.starts_with(AUDIT_PREFIX) && key >= start {
+                vec.push(value.into_serde::<AuditEntry>()?);
             }
         }
+        vec.sort_by_key(|entry| entry.sequence);
+        vec.truncate(limit);
 
         Ok(vec)
     }
 
+    async fn list_outbox(&self) -> Result<Vec<OutboxEvent>, JsError> {
+        let tx = self
+            .rexie
+            .transaction(&[OBJECT_STORE_NAME], TransactionMode::ReadOnly)?;
+        let store = tx.store(OBJECT_STORE_NAME)?;
+        let all = store.get_all(None, None, None, None).await?;
+        tx.done().await?;
+
+        let mut vec = Vec::new();
+        for (key, value) in all {
+            let key: String = key.into_serde()?;
+            if key.starts_with(OUTBOX_PREFIX) {
+                vec.push(value.into_serde()?);
+            }
+        }
+
+        Ok(vec)
+    }
+
+    async fn delete_from_indexed_db<K: Serialize>(&self, key: K) -> Result<(), JsError> {
+        let tx = self
+            .rexie
+            .transaction(&[OBJECT_STORE_NAME], TransactionMode::ReadWrite)?;
+        let store = tx.store(OBJECT_STORE_NAME)?;
+        store.delete(&JsValue::from_serde(&key)?).await?;
+        tx.done().await?;
+        Ok(())
+    }
+
     pub async fn clear() -> Result<(), JsError> {
         let rexie = Self::build_indexed_db().await?;
         let tx = rexie.transaction(&[OBJECT_STORE_NAME], TransactionMode::ReadWrite)?;
@@ -160,15 +222,10 @@ impl IndexedDb {
 
 impl Storage for IndexedDb {
     async fn get_next_nonce_indexes(&self, num: usize) -> Result<Vec<u32>, Error> {
-        let mut current_index = self.current_index.fetch_add(num as u32, Ordering::SeqCst);
-        let mut indexes = Vec::with_capacity(num);
-        for _ in 0..num {
-            indexes.push(current_index);
-            current_index += 1;
-        }
-        self.save_to_indexed_db(NONCE_INDEX_KEY, current_index)
-            .await?;
-        Ok(indexes)
+        let current_index = self.current_index.fetch_add(num as u32, Ordering::SeqCst);
+        let next_index = current_index + num as u32;
+        self.save_to_indexed_db(NONCE_INDEX_KEY, next_index).await?;
+        Ok((current_index..next_index).collect())
     }
 
     async fn save_announcement(
@@ -181,6 +238,7 @@ impl Storage for IndexedDb {
             announcement: announcement.clone(),
             indexes,
             signatures: Default::default(),
+            withdrawn: false,
             announcement_event_id: None,
             attestation_event_id: None,
         };
@@ -199,9 +257,14 @@ impl Storage for IndexedDb {
         let mut event = self
             .get_event(event_id.clone())
             .await?
-            .ok_or(Error::NotFound)?;
+            .ok_or_else(|| Error::NotFound {
+                event_id: event_id.clone(),
+            })?;
+        if event.withdrawn {
+            return Err(Error::EventWithdrawn { event_id });
+        }
         if !event.signatures.is_empty() {
-            return Err(Error::EventAlreadySigned);
+            return Err(Error::EventAlreadySigned { event_id });
         }
 
         event.signatures = sigs;
@@ -217,4 +280,74 @@ impl Storage for IndexedDb {
             .await?;
         Ok(event)
     }
+
+    async fn list_events(&self) -> Result<Vec<OracleEventData>, Error> {
+        Ok(self.list_oracle_data().await?)
+    }
+
+    async fn withdraw_event(&self, event_id: String) -> Result<OracleEventData, Error> {
+        let mut event = self
+            .get_event(event_id.clone())
+            .await?
+            .ok_or_else(|| Error::NotFound {
+                event_id: event_id.clone(),
+            })?;
+        if !event.signatures.is_empty() {
+            return Err(Error::EventAlreadySigned { event_id });
+        }
+
+        event.withdrawn = true;
+        self.save_to_indexed_db(get_oracle_data_key(event_id), &event)
+            .await?;
+
+        Ok(event)
+    }
+
+    async fn append_audit_record(&self, record: AuditRecord) -> Result<AuditEntry, Error> {
+        Ok(self.append_audit(record).await?)
+    }
+
+    async fn list_audit_entries(&self, from: u64, limit: usize) -> Result<Vec<AuditEntry>, Error> {
+        Ok(self.list_audit(from, limit).await?)
+    }
+
+    async fn add_announcement_event_id(
+        &self,
+        event_id: String,
+        nostr_event_id: EventId,
+    ) -> Result<(), Error> {
+        self.update_event(event_id, |event| {
+            event.announcement_event_id = Some(nostr_event_id.to_hex())
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn add_attestation_event_id(
+        &self,
+        event_id: String,
+        nostr_event_id: EventId,
+    ) -> Result<(), Error> {
+        self.update_event(event_id, |event| {
+            event.attestation_event_id = Some(nostr_event_id.to_hex())
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn save_outbox_event(&self, event: OutboxEvent) -> Result<(), Error> {
+        self.save_to_indexed_db(get_outbox_key(event.nostr_event.id), event)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_outbox_events(&self) -> Result<Vec<OutboxEvent>, Error> {
+        Ok(self.list_outbox().await?)
+    }
+
+    async fn remove_outbox_event(&self, nostr_event_id: EventId) -> Result<(), Error> {
+        self.delete_from_indexed_db(get_outbox_key(nostr_event_id))
+            .await?;
+        Ok(())
+    }
 }
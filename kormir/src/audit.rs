@@ -0,0 +1,241 @@
+//! Append-only, hash chained log of the actions performed on an oracle.
+//!
+//! Each [`AuditEntry`] commits to the previous one, so editing or removing an
+//! entry breaks the chain from that point on, see [`verify_chain`].
+//!
+//! The hash of an entry is the SHA256 of:
+//!
+//! - the hash of the previous entry, 32 zero bytes for the first entry
+//! - the sequence number as a big endian u64
+//! - the action, the event id, the outcome, the key id, the source and the
+//!   result, each as a big endian u32 length followed by its UTF-8 bytes.
+//!   A missing value is encoded as the length `0xffffffff`.
+//! - the timestamp as a big endian u64
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Result of a successful action
+pub const RESULT_OK: &str = "ok";
+
+/// An action performed on the oracle. Embedders record their own actions in
+/// the same log by name, see [`AuditRecord::action`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    CreateEnumEvent,
+    CreateNumericEvent,
+    SignEnumEvent,
+    SignNumericEvent,
+    WithdrawEvent,
+    FlushOutbox,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::CreateEnumEvent => "create_enum_event",
+            AuditAction::CreateNumericEvent => "create_numeric_event",
+            AuditAction::SignEnumEvent => "sign_enum_event",
+            AuditAction::SignNumericEvent => "sign_numeric_event",
+            AuditAction::WithdrawEvent => "withdraw_event",
+            AuditAction::FlushOutbox => "flush_outbox",
+        }
+    }
+}
+
+impl Display for AuditAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "create_enum_event" => Ok(AuditAction::CreateEnumEvent),
+            "create_numeric_event" => Ok(AuditAction::CreateNumericEvent),
+            "sign_enum_event" => Ok(AuditAction::SignEnumEvent),
+            "sign_numeric_event" => Ok(AuditAction::SignNumericEvent),
+            "withdraw_event" => Ok(AuditAction::WithdrawEvent),
+            "flush_outbox" => Ok(AuditAction::FlushOutbox),
+            _ => Err(format!("unknown audit action: {s}")),
+        }
+    }
+}
+
+impl From<AuditAction> for String {
+    fn from(action: AuditAction) -> Self {
+        action.as_str().to_string()
+    }
+}
+
+/// An action to append to the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct AuditRecord {
+    /// Name of the action, an [`AuditAction`] or one of the embedder's own
+    #[cfg_attr(feature = "utoipa", schema(example = "sign_enum_event"))]
+    pub action: String,
+    /// What the action was performed on: the event for the event actions,
+    /// empty when flushing the outbox
+    pub event_id: String,
+    /// The requested outcome, for attestations
+    pub outcome: Option<String>,
+    /// The credential of the caller
+    pub key_id: Option<String>,
+    /// Where the request came from
+    pub source: Option<String>,
+    /// Unix time of the action
+    pub timestamp: u64,
+    /// [`RESULT_OK`] (`ok`) or the code of the error the action failed with
+    pub result: String,
+}
+
+/// An entry of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct AuditEntry {
+    /// Position in the log, starting at 0
+    pub sequence: u64,
+    #[serde(flatten)]
+    pub record: AuditRecord,
+    /// Hex hash of the previous entry
+    #[cfg_attr(feature = "utoipa", schema(value_type = String))]
+    pub prev_hash: sha256::Hash,
+    /// Hex hash of the entry
+    #[cfg_attr(feature = "utoipa", schema(value_type = String))]
+    pub hash: sha256::Hash,
+}
+
+impl AuditEntry {
+    /// Chains the record after the last entry of the log, if any.
+    pub fn new(last: Option<&AuditEntry>, record: AuditRecord) -> Self {
+        let (sequence, prev_hash) = match last {
+            Some(last) => (last.sequence + 1, last.hash),
+            None => (0, sha256::Hash::all_zeros()),
+        };
+        let hash = Self::compute_hash(sequence, &prev_hash, &record);
+
+        Self {
+            sequence,
+            record,
+            prev_hash,
+            hash,
+        }
+    }
+
+    pub fn compute_hash(
+        sequence: u64,
+        prev_hash: &sha256::Hash,
+        record: &AuditRecord,
+    ) -> sha256::Hash {
+        fn write_str(engine: &mut sha256::HashEngine, value: Option<&str>) {
+            match value {
+                Some(value) => {
+                    engine.input(&(value.len() as u32).to_be_bytes());
+                    engine.input(value.as_bytes());
+                }
+                None => engine.input(&u32::MAX.to_be_bytes()),
+            }
+        }
+
+        let mut engine = sha256::Hash::engine();
+        engine.input(prev_hash.as_byte_array());
+        engine.input(&sequence.to_be_bytes());
+        write_str(&mut engine, Some(&record.action));
+        write_str(&mut engine, Some(&record.event_id));
+        write_str(&mut engine, record.outcome.as_deref());
+        write_str(&mut engine, record.key_id.as_deref());
+        write_str(&mut engine, record.source.as_deref());
+        write_str(&mut engine, Some(&record.result));
+        engine.input(&record.timestamp.to_be_bytes());
+        sha256::Hash::from_engine(engine)
+    }
+
+    /// Whether the hash of the entry matches its content
+    pub fn is_valid(&self) -> bool {
+        self.hash == Self::compute_hash(self.sequence, &self.prev_hash, &self.record)
+    }
+}
+
+/// Checks that the entries form an unbroken chain, returns the sequence number
+/// of the first entry that does not. The entries may start after the first
+/// entry of the log, so a log can be verified a page at a time.
+pub fn verify_chain(entries: &[AuditEntry]) -> Result<(), u64> {
+    let mut prev: Option<&AuditEntry> = None;
+    for entry in entries {
+        let linked = match prev {
+            Some(prev) => entry.sequence == prev.sequence + 1 && entry.prev_hash == prev.hash,
+            None => entry.sequence != 0 || entry.prev_hash == sha256::Hash::all_zeros(),
+        };
+        if !linked || !entry.is_valid() {
+            return Err(entry.sequence);
+        }
+        prev = Some(entry);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(event_id: &str, outcome: Option<&str>) -> AuditRecord {
+        AuditRecord {
+            action: AuditAction::SignEnumEvent.into(),
+            event_id: event_id.to_string(),
+            outcome: outcome.map(|o| o.to_string()),
+            key_id: Some("alice".to_string()),
+            source: None,
+            timestamp: 1_700_000_000,
+            result: RESULT_OK.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_audit_chain() {
+        let first = AuditEntry::new(None, record("a", Some("yes")));
+        let second = AuditEntry::new(Some(&first), record("b", None));
+        let third = AuditEntry::new(Some(&second), record("c", Some("")));
+        assert_eq!(first.sequence, 0);
+        assert_eq!(third.sequence, 2);
+        assert_eq!(third.prev_hash, second.hash);
+
+        let mut entries = vec![first, second, third];
+        assert_eq!(verify_chain(&entries), Ok(()));
+        assert_eq!(verify_chain(&entries[1..]), Ok(()));
+
+        // a missing value and an empty one hash differently
+        let mut tampered = entries.clone();
+        tampered[2].record.outcome = None;
+        assert_eq!(verify_chain(&tampered), Err(2));
+
+        // rehashing an edited entry breaks the link to the next one
+        entries[1].record.outcome = Some("no".to_string());
+        entries[1].hash = AuditEntry::compute_hash(1, &entries[1].prev_hash, &entries[1].record);
+        assert_eq!(verify_chain(&entries), Err(2));
+
+        entries.remove(1);
+        assert_eq!(verify_chain(&entries), Err(2));
+    }
+
+    #[test]
+    fn test_audit_action_names() {
+        let actions = [
+            AuditAction::CreateEnumEvent,
+            AuditAction::CreateNumericEvent,
+            AuditAction::SignEnumEvent,
+            AuditAction::SignNumericEvent,
+            AuditAction::WithdrawEvent,
+            AuditAction::FlushOutbox,
+        ];
+        for action in actions {
+            assert_eq!(action.as_str().parse(), Ok(action));
+        }
+    }
+}
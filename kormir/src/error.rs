@@ -1,30 +1,91 @@
 use std::fmt::{Display, Formatter};
 
 /// Kormir error type
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     /// Invalid argument given
-    InvalidArgument,
+    InvalidArgument { reason: String },
+    /// Attempted to create an event with an id that is already used
+    EventAlreadyExists { event_id: String },
     /// Attempted to sign an event that was already signed
-    EventAlreadySigned,
+    EventAlreadySigned { event_id: String },
     /// Event data was not found
-    NotFound,
+    NotFound { event_id: String },
     /// The storage failed to read/save the data
     StorageFailure,
     /// User gave an invalid outcome
-    InvalidOutcome,
+    InvalidOutcome { event_id: String, reason: String },
+    /// The event was withdrawn and can no longer be signed
+    EventWithdrawn { event_id: String },
+    /// Failed to create or publish a nostr event
+    Nostr,
+    /// The storage does not support the operation
+    Unsupported,
     /// An error that should never happen, if it does it's a bug
     Internal,
 }
 
+impl Error {
+    pub(crate) fn invalid_argument(reason: impl Into<String>) -> Self {
+        Error::InvalidArgument {
+            reason: reason.into(),
+        }
+    }
+
+    pub(crate) fn invalid_outcome(event_id: impl Into<String>, reason: impl Into<String>) -> Self {
+        Error::InvalidOutcome {
+            event_id: event_id.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Stable identifier of the kind of error, for clients to match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidArgument { .. } => "invalid_argument",
+            Error::EventAlreadyExists { .. } => "event_already_exists",
+            Error::EventAlreadySigned { .. } => "event_already_signed",
+            Error::NotFound { .. } => "not_found",
+            Error::StorageFailure => "storage_failure",
+            Error::InvalidOutcome { .. } => "invalid_outcome",
+            Error::EventWithdrawn { .. } => "event_withdrawn",
+            Error::Nostr => "nostr",
+            Error::Unsupported => "unsupported",
+            Error::Internal => "internal",
+        }
+    }
+
+    /// The oracle event the error is about, if any
+    pub fn event_id(&self) -> Option<&str> {
+        match self {
+            Error::EventAlreadyExists { event_id }
+            | Error::EventAlreadySigned { event_id }
+            | Error::NotFound { event_id }
+            | Error::InvalidOutcome { event_id, .. }
+            | Error::EventWithdrawn { event_id } => Some(event_id),
+            Error::InvalidArgument { .. }
+            | Error::StorageFailure
+            | Error::Nostr
+            | Error::Unsupported
+            | Error::Internal => None,
+        }
+    }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Error::InvalidArgument => write!(f, "Invalid argument given"),
-            Error::EventAlreadySigned => write!(f, "Event already signed"),
-            Error::NotFound => write!(f, "Event data not found"),
+            Error::InvalidArgument { reason } => write!(f, "Invalid argument given: {reason}"),
+            Error::EventAlreadyExists { event_id } => write!(f, "Event {event_id} already exists"),
+            Error::EventAlreadySigned { event_id } => write!(f, "Event {event_id} already signed"),
+            Error::NotFound { event_id } => write!(f, "Event {event_id} not found"),
             Error::StorageFailure => write!(f, "Storage failure"),
-            Error::InvalidOutcome => write!(f, "Invalid outcome"),
+            Error::InvalidOutcome { event_id, reason } => {
+                write!(f, "Invalid outcome for event {event_id}: {reason}")
+            }
+            Error::EventWithdrawn { event_id } => write!(f, "Event {event_id} withdrawn"),
+            Error::Nostr => write!(f, "Nostr error"),
+            Error::Unsupported => write!(f, "Operation not supported by the storage"),
             Error::Internal => write!(f, "Internal error"),
         }
     }
@@ -1,12 +1,22 @@
 #![allow(async_fn_in_trait)]
 
+pub mod audit;
 pub mod error;
 #[cfg(feature = "nostr")]
+pub mod nostr_directory;
+#[cfg(feature = "nostr")]
 pub mod nostr_events;
+#[cfg(feature = "nostr")]
+pub mod nostr_publisher;
+pub mod observer;
+pub mod retry;
+#[cfg(feature = "utoipa")]
+pub mod schema;
 pub mod storage;
 
 use crate::error::Error;
-use crate::storage::Storage;
+use crate::observer::OracleObserver;
+use crate::storage::{OracleEventData, Storage};
 use bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv};
 use bitcoin::hashes::{sha256, Hash};
 use bitcoin::key::XOnlyPublicKey;
@@ -25,15 +35,24 @@ pub use lightning;
 pub use lightning::util::ser::{Readable, Writeable};
 #[cfg(feature = "nostr")]
 pub use nostr;
+#[cfg(feature = "nostr")]
+pub use nostr_sdk;
 
 // first key for taproot address
 const SIGNING_KEY_PATH: &str = "m/86'/0'/0'/0/0";
+// first nostr key, see NIP-06
+#[cfg(feature = "nostr")]
+const NOSTR_KEY_PATH: &str = "m/44'/1237'/0'/0/0";
 
 #[derive(Debug, Clone)]
-pub struct Oracle<S: Storage> {
+pub struct Oracle<S: Storage, O: OracleObserver = ()> {
     pub storage: S,
+    observer: O,
     key_pair: Keypair,
     nonce_xpriv: Xpriv,
+    /// Separate key used for nostr, the signing key is used when unset
+    #[cfg(feature = "nostr")]
+    nostr_key: Option<SecretKey>,
     secp: Secp256k1<All>,
 }
 
@@ -42,8 +61,11 @@ impl<S: Storage> Oracle<S> {
         let secp = Secp256k1::new();
         Self {
             storage,
+            observer: (),
             key_pair: Keypair::from_secret_key(&secp, &signing_key),
             nonce_xpriv,
+            #[cfg(feature = "nostr")]
+            nostr_key: None,
             secp,
         }
     }
@@ -64,24 +86,78 @@ impl<S: Storage> Oracle<S> {
 
         Ok(Self {
             storage,
+            observer: (),
             key_pair: Keypair::from_secret_key(&secp, &signing_key),
             nonce_xpriv,
+            #[cfg(feature = "nostr")]
+            nostr_key: None,
             secp,
         })
     }
+}
+
+impl<S: Storage, O: OracleObserver> Oracle<S, O> {
+    /// Registers an observer that is notified when events are announced,
+    /// attested or cancelled, replacing the current one.
+    ///
+    /// Use a tuple to register multiple observers.
+    pub fn with_observer<T: OracleObserver>(self, observer: T) -> Oracle<S, T> {
+        Oracle {
+            storage: self.storage,
+            observer,
+            key_pair: self.key_pair,
+            nonce_xpriv: self.nonce_xpriv,
+            #[cfg(feature = "nostr")]
+            nostr_key: self.nostr_key,
+            secp: self.secp,
+        }
+    }
+
+    /// Publishes to nostr with `nostr_key` instead of the oracle's signing key.
+    ///
+    /// Clients link the two keys through [`Oracle::nostr_key_binding`].
+    #[cfg(feature = "nostr")]
+    pub fn with_nostr_key(mut self, nostr_key: SecretKey) -> Self {
+        self.nostr_key = Some(nostr_key);
+        self
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
 
     pub fn public_key(&self) -> XOnlyPublicKey {
         self.key_pair.x_only_public_key().0
     }
 
     /// Returns the keys for the oracle, used for Nostr.
+    ///
+    /// These are the oracle's signing keys unless a separate nostr key was set
+    /// with [`Oracle::with_nostr_key`].
     #[cfg(feature = "nostr")]
     pub fn nostr_keys(&self) -> nostr::Keys {
-        let sec = nostr::key::SecretKey::from_slice(&self.key_pair.secret_key().secret_bytes()[..])
-            .expect("just converting types");
+        let secret = self
+            .nostr_key
+            .unwrap_or_else(|| self.key_pair.secret_key())
+            .secret_bytes();
+        let sec = nostr::key::SecretKey::from_slice(&secret).expect("just converting types");
         nostr::Keys::new(sec)
     }
 
+    /// Signs the binding between the nostr key and the oracle key.
+    ///
+    /// Returns `None` when the oracle publishes with its own signing key, as
+    /// there is nothing to bind.
+    #[cfg(feature = "nostr")]
+    pub fn nostr_key_binding(&self) -> Option<nostr_events::KeyBinding> {
+        self.nostr_key?;
+        let msg = nostr_events::KeyBinding::message(&self.nostr_keys().public_key);
+        Some(nostr_events::KeyBinding {
+            oracle_public_key: self.public_key(),
+            signature: self.secp.sign_schnorr_no_aux_rand(&msg, &self.key_pair),
+        })
+    }
+
     fn get_nonce_key(&self, index: u32) -> SecretKey {
         self.nonce_xpriv
             .derive_priv(
@@ -98,6 +174,11 @@ impl<S: Storage> Oracle<S> {
         outcomes: Vec<String>,
         event_maturity_epoch: u32,
     ) -> Result<OracleAnnouncement, Error> {
+        if outcomes.is_empty() {
+            return Err(Error::invalid_argument("must have at least one outcome"));
+        }
+        self.check_new_event_id(&event_id).await?;
+
         let indexes = self.storage.get_next_nonce_indexes(1).await?;
         let oracle_nonces = indexes
             .iter()
@@ -131,6 +212,8 @@ impl<S: Storage> Oracle<S> {
 
         let _ = self.storage.save_announcement(ann.clone(), indexes).await?;
 
+        self.notify_announcement(&ann).await;
+
         Ok(ann)
     }
 
@@ -140,21 +223,12 @@ impl<S: Storage> Oracle<S> {
         outcome: String,
     ) -> Result<OracleAttestation, Error> {
         let Some(data) = self.storage.get_event(event_id.clone()).await? else {
-            return Err(Error::NotFound);
+            return Err(Error::NotFound { event_id });
         };
-        if !data.signatures.is_empty() {
-            return Err(Error::EventAlreadySigned);
-        }
+        data.check_enum_outcome(&outcome)?;
         if data.indexes.len() != 1 {
             return Err(Error::Internal);
         }
-        let descriptor = match &data.announcement.oracle_event.event_descriptor {
-            EventDescriptor::EnumEvent(desc) => desc,
-            _ => return Err(Error::Internal),
-        };
-        if !descriptor.outcomes.contains(&outcome) {
-            return Err(Error::InvalidOutcome);
-        }
 
         let nonce_index = data.indexes.first().expect("Already checked length");
         let nonce_key = self.get_nonce_key(*nonce_index);
@@ -196,6 +270,8 @@ impl<S: Storage> Oracle<S> {
             outcomes: vec![outcome],
         };
 
+        self.notify_attestation(&attestation).await;
+
         Ok(attestation)
     }
 
@@ -209,8 +285,11 @@ impl<S: Storage> Oracle<S> {
         event_maturity_epoch: u32,
     ) -> Result<OracleAnnouncement, Error> {
         if num_digits == 0 {
-            return Err(Error::InvalidArgument);
+            return Err(Error::invalid_argument(
+                "number of digits must be greater than 0",
+            ));
         }
+        self.check_new_event_id(&event_id).await?;
 
         let num_nonces = if is_signed {
             num_digits as usize + 1
@@ -258,6 +337,8 @@ impl<S: Storage> Oracle<S> {
 
         let _ = self.storage.save_announcement(ann.clone(), indexes).await?;
 
+        self.notify_announcement(&ann).await;
+
         Ok(ann)
     }
 
@@ -267,23 +348,14 @@ impl<S: Storage> Oracle<S> {
         outcome: i64,
     ) -> Result<OracleAttestation, Error> {
         let Some(data) = self.storage.get_event(event_id.clone()).await? else {
-            return Err(Error::NotFound);
+            return Err(Error::NotFound { event_id });
         };
-        if !data.signatures.is_empty() {
-            return Err(Error::EventAlreadySigned);
-        }
-        let descriptor = match &data.announcement.oracle_event.event_descriptor {
-            EventDescriptor::DigitDecompositionEvent(desc) => desc,
-            _ => return Err(Error::Internal),
-        };
-        if descriptor.base != 2 {
+        data.check_numeric_outcome(outcome)?;
+        let EventDescriptor::DigitDecompositionEvent(descriptor) =
+            &data.announcement.oracle_event.event_descriptor
+        else {
             return Err(Error::Internal);
-        }
-        let max_value = (descriptor.base as i64).pow(descriptor.nb_digits as u32) - 1;
-        let min_value = if descriptor.is_signed { -max_value } else { 0 };
-        if outcome < min_value || outcome > max_value {
-            return Err(Error::InvalidOutcome);
-        }
+        };
 
         let digits = format!(
             "{:0width$b}",
@@ -354,8 +426,74 @@ impl<S: Storage> Oracle<S> {
             outcomes,
         };
 
+        self.notify_attestation(&attestation).await;
+
         Ok(attestation)
     }
+
+    /// Withdraws an announcement that was cancelled or created by mistake.
+    ///
+    /// The event is marked as withdrawn so it can never be signed and observers
+    /// are notified so they can retract the announcement. Events that are
+    /// already signed cannot be withdrawn.
+    pub async fn withdraw_event(&self, event_id: String) -> Result<OracleEventData, Error> {
+        let Some(data) = self.storage.get_event(event_id.clone()).await? else {
+            return Err(Error::NotFound { event_id });
+        };
+        if data.withdrawn {
+            return Err(Error::EventWithdrawn { event_id });
+        }
+        if !data.signatures.is_empty() {
+            return Err(Error::EventAlreadySigned { event_id });
+        }
+
+        let data = self.storage.withdraw_event(event_id).await?;
+
+        self.notify_cancellation(&data).await;
+
+        Ok(data)
+    }
+
+    async fn check_new_event_id(&self, event_id: &str) -> Result<(), Error> {
+        if self
+            .storage
+            .get_event(event_id.to_string())
+            .await?
+            .is_some()
+        {
+            return Err(Error::EventAlreadyExists {
+                event_id: event_id.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    async fn notify_announcement(&self, announcement: &OracleAnnouncement) {
+        if let Err(e) = self.observer.on_announcement(announcement).await {
+            log::error!(
+                "Observer failed to handle announcement for {}: {e}",
+                announcement.oracle_event.event_id
+            );
+        }
+    }
+
+    async fn notify_attestation(&self, attestation: &OracleAttestation) {
+        if let Err(e) = self.observer.on_attestation(attestation).await {
+            log::error!(
+                "Observer failed to handle attestation for {}: {e}",
+                attestation.event_id
+            );
+        }
+    }
+
+    async fn notify_cancellation(&self, event: &OracleEventData) {
+        if let Err(e) = self.observer.on_cancellation(event).await {
+            log::error!(
+                "Observer failed to handle cancellation for {}: {e}",
+                event.event_id
+            );
+        }
+    }
 }
 
 pub fn derive_signing_key(secp: &Secp256k1<All>, xpriv: Xpriv) -> Result<SecretKey, Error> {
@@ -369,11 +507,26 @@ pub fn derive_signing_key(secp: &Secp256k1<All>, xpriv: Xpriv) -> Result<SecretK
     Ok(signing_key)
 }
 
+/// Derives a nostr key from the oracle's master key on the NIP-06 path, to be
+/// used with [`Oracle::with_nostr_key`].
+#[cfg(feature = "nostr")]
+pub fn derive_nostr_key(secp: &Secp256k1<All>, xpriv: Xpriv) -> Result<SecretKey, Error> {
+    let nostr_key = xpriv
+        .derive_priv(
+            secp,
+            &DerivationPath::from_str(NOSTR_KEY_PATH).map_err(|_| Error::Internal)?,
+        )
+        .map_err(|_| Error::Internal)?
+        .private_key;
+    Ok(nostr_key)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::storage::MemoryStorage;
     use bitcoin::secp256k1::rand::{thread_rng, Rng};
+    use std::sync::{Arc, Mutex};
 
     fn create_oracle() -> Oracle<MemoryStorage> {
         let mut seed: [u8; 64] = [0; 64];
@@ -664,4 +817,250 @@ mod test {
 
         println!("{}", hex::encode(attestation.encode()));
     }
+
+    #[derive(Debug, Clone, Default)]
+    struct RecordingObserver {
+        announced: Arc<Mutex<Vec<String>>>,
+        attested: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl OracleObserver for RecordingObserver {
+        async fn on_announcement(&self, announcement: &OracleAnnouncement) -> Result<(), Error> {
+            self.announced
+                .lock()
+                .unwrap()
+                .push(announcement.oracle_event.event_id.clone());
+            Ok(())
+        }
+
+        async fn on_attestation(&self, attestation: &OracleAttestation) -> Result<(), Error> {
+            self.attested
+                .lock()
+                .unwrap()
+                .push(attestation.event_id.clone());
+            Err(Error::Internal)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_observer_notified() {
+        let observer = RecordingObserver::default();
+        let oracle = create_oracle().with_observer(observer.clone());
+
+        let event_id = "test_observer".to_string();
+        oracle
+            .create_enum_event(event_id.clone(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        assert_eq!(*observer.announced.lock().unwrap(), vec![event_id.clone()]);
+        assert!(observer.attested.lock().unwrap().is_empty());
+
+        // observer errors do not fail the call
+        oracle
+            .sign_enum_event(event_id.clone(), "a".to_string())
+            .await
+            .unwrap();
+        assert_eq!(*observer.attested.lock().unwrap(), vec![event_id]);
+    }
+
+    #[tokio::test]
+    async fn test_optional_observer() {
+        let oracle = create_oracle().with_observer(None::<RecordingObserver>);
+        oracle
+            .create_enum_event("test_none".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        oracle
+            .sign_enum_event("test_none".into(), "a".into())
+            .await
+            .unwrap();
+
+        let observer = RecordingObserver::default();
+        let oracle = create_oracle().with_observer(Some(observer.clone()));
+        oracle
+            .create_enum_event("test_some".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        assert_eq!(*observer.announced.lock().unwrap(), vec!["test_some"]);
+    }
+
+    #[tokio::test]
+    async fn test_storage_rejects_signatures_of_withdrawn_event() {
+        let oracle = create_oracle();
+
+        let event_id = "test_withdrawn_signatures".to_string();
+        oracle
+            .create_enum_event(event_id.clone(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        oracle.withdraw_event(event_id.clone()).await.unwrap();
+
+        // bypasses the check of the oracle, like a sign racing the withdrawal
+        let err = oracle
+            .storage
+            .save_signatures(event_id.clone(), vec![])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            Error::EventWithdrawn {
+                event_id: event_id.clone()
+            }
+        );
+        let data = oracle.storage.get_event(event_id).await.unwrap().unwrap();
+        assert!(data.signatures.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_event() {
+        let oracle = create_oracle();
+
+        let event_id = "test_withdraw".to_string();
+        oracle
+            .create_enum_event(event_id.clone(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+
+        let data = oracle.withdraw_event(event_id.clone()).await.unwrap();
+        assert!(data.withdrawn);
+        assert_eq!(
+            oracle.sign_enum_event(event_id.clone(), "a".into()).await,
+            Err(Error::EventWithdrawn {
+                event_id: event_id.clone()
+            })
+        );
+        assert_eq!(
+            oracle.withdraw_event(event_id.clone()).await.unwrap_err(),
+            Error::EventWithdrawn { event_id }
+        );
+
+        // signed events cannot be withdrawn
+        let event_id = "test_withdraw_signed".to_string();
+        oracle
+            .create_numeric_event(event_id.clone(), 4, false, 0, "m/s".into(), 100)
+            .await
+            .unwrap();
+        oracle
+            .sign_numeric_event(event_id.clone(), 3)
+            .await
+            .unwrap();
+        assert_eq!(
+            oracle.withdraw_event(event_id.clone()).await.unwrap_err(),
+            Error::EventAlreadySigned { event_id }
+        );
+    }
+
+    /// A storage implementing only the required methods
+    struct MinimalStorage(MemoryStorage);
+
+    impl Storage for MinimalStorage {
+        async fn get_next_nonce_indexes(&self, num: usize) -> Result<Vec<u32>, Error> {
+            self.0.get_next_nonce_indexes(num).await
+        }
+
+        async fn save_announcement(
+            &self,
+            announcement: OracleAnnouncement,
+            indexes: Vec<u32>,
+        ) -> Result<String, Error> {
+            self.0.save_announcement(announcement, indexes).await
+        }
+
+        async fn save_signatures(
+            &self,
+            event_id: String,
+            sigs: Vec<(String, Signature)>,
+        ) -> Result<OracleEventData, Error> {
+            self.0.save_signatures(event_id, sigs).await
+        }
+
+        async fn get_event(&self, event_id: String) -> Result<Option<OracleEventData>, Error> {
+            self.0.get_event(event_id).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_minimal_storage() {
+        let signing_key = SecretKey::from_slice(&[1; 32]).unwrap();
+        let oracle =
+            Oracle::from_signing_key(MinimalStorage(MemoryStorage::default()), signing_key)
+                .unwrap();
+
+        let event_id = "test_minimal".to_string();
+        oracle
+            .create_enum_event(event_id.clone(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        oracle
+            .sign_enum_event(event_id.clone(), "a".into())
+            .await
+            .unwrap();
+
+        let event_id = "test_minimal_withdraw".to_string();
+        oracle
+            .create_enum_event(event_id.clone(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        assert_eq!(
+            oracle.withdraw_event(event_id).await.unwrap_err(),
+            Error::Unsupported
+        );
+        assert_eq!(
+            oracle.storage.list_events().await.unwrap_err(),
+            Error::Unsupported
+        );
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let oracle = create_oracle();
+
+        let event_id = "test_errors".to_string();
+        oracle
+            .create_enum_event(event_id.clone(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+
+        let err = oracle
+            .create_numeric_event(event_id.clone(), 4, false, 0, "m/s".into(), 100)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err,
+            Error::EventAlreadyExists {
+                event_id: event_id.clone()
+            }
+        );
+        assert_eq!(err.code(), "event_already_exists");
+
+        let err = oracle
+            .sign_enum_event(event_id.clone(), "c".into())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "invalid_outcome");
+        assert_eq!(err.event_id(), Some(event_id.as_str()));
+        assert_eq!(
+            oracle
+                .sign_numeric_event(event_id.clone(), 1)
+                .await
+                .unwrap_err(),
+            Error::InvalidOutcome {
+                event_id,
+                reason: "not a numeric event".to_string()
+            }
+        );
+
+        let err = oracle
+            .sign_enum_event("missing".into(), "a".into())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Event missing not found");
+        assert!(matches!(
+            oracle
+                .create_enum_event("empty".into(), vec![], 100)
+                .await
+                .unwrap_err(),
+            Error::InvalidArgument { .. }
+        ));
+    }
 }
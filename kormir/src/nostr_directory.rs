@@ -0,0 +1,824 @@
+//! Discovery of oracle announcements and attestations published on nostr.
+//!
+//! [`OracleDirectory`] reads the kind 88 and 89 events of a set of oracles, or
+//! of every oracle, verifies and decodes them and keeps an index of the
+//! announced events that can be queried by maturity, oracle or descriptor.
+//! Announcements retracted with a deletion request are dropped from the index.
+//!
+//! Attestations and deletion requests can arrive before their announcement,
+//! they are kept until it does, up to a limit since anyone can publish them.
+
+use crate::error::Error;
+use crate::nostr_events::{
+    parse_announcement_event, parse_announcement_tags, parse_attestation_event,
+    parse_attestation_tags, DescriptorType, ParseError, ANNOUNCEMENT_KIND, ATTESTATION_KIND,
+};
+use dlc_messages::oracle_msgs::{OracleAnnouncement, OracleAttestation};
+use nostr::{Alphabet, Event, EventId, Filter, Kind, PublicKey, RelayUrl, SingleLetterTag};
+use nostr_sdk::{Client, RelayPoolNotification};
+use secp256k1_zkp::{Secp256k1, XOnlyPublicKey};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Most announcements the index keeps orphaned attestations for
+const MAX_ORPHANED_ANNOUNCEMENTS: usize = 1_000;
+
+/// Most attestations kept for an announcement that has not arrived yet
+const MAX_ORPHANS_PER_ANNOUNCEMENT: usize = 10;
+
+/// Most deletion requests kept for announcements that have not arrived yet
+const MAX_PENDING_DELETIONS: usize = 10_000;
+
+/// An oracle event found on nostr
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectoryEntry {
+    /// The nostr key that published the announcement
+    pub nostr_public_key: PublicKey,
+    pub announcement_event_id: EventId,
+    pub announcement: OracleAnnouncement,
+    /// The attestation, once the oracle has published it
+    pub attestation: Option<OracleAttestation>,
+    /// The relays the oracle publishes its attestation to
+    pub relays: Vec<RelayUrl>,
+}
+
+impl DirectoryEntry {
+    pub fn oracle_public_key(&self) -> XOnlyPublicKey {
+        self.announcement.oracle_public_key
+    }
+
+    pub fn event_id(&self) -> &str {
+        &self.announcement.oracle_event.event_id
+    }
+
+    pub fn event_maturity_epoch(&self) -> u32 {
+        self.announcement.oracle_event.event_maturity_epoch
+    }
+
+    pub fn descriptor(&self) -> DescriptorType {
+        DescriptorType::from_descriptor(&self.announcement.oracle_event.event_descriptor)
+    }
+}
+
+/// Filters for [`OracleDirectory::query`], unset fields match every entry
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirectoryQuery {
+    pub oracle_public_key: Option<XOnlyPublicKey>,
+    pub descriptor: Option<DescriptorType>,
+    /// Only events maturing at or after this time
+    pub maturity_from: Option<u32>,
+    /// Only events maturing before this time
+    pub maturity_until: Option<u32>,
+    /// Only attested, or only unattested, events
+    pub attested: Option<bool>,
+}
+
+impl DirectoryQuery {
+    fn matches(&self, entry: &DirectoryEntry) -> bool {
+        let maturity = entry.event_maturity_epoch();
+        self.oracle_public_key
+            .is_none_or(|pk| pk == entry.oracle_public_key())
+            && self.descriptor.is_none_or(|d| d == entry.descriptor())
+            && self.maturity_from.is_none_or(|from| maturity >= from)
+            && self.maturity_until.is_none_or(|until| maturity < until)
+            && self
+                .attested
+                .is_none_or(|attested| attested == entry.attestation.is_some())
+    }
+}
+
+/// Map that forgets its oldest keys once it holds more than its capacity
+#[derive(Debug)]
+struct FifoMap<K, V> {
+    map: HashMap<K, V>,
+    /// Keys of the map, oldest first
+    order: VecDeque<K>,
+    capacity: usize,
+}
+
+impl<K: Hash + Eq + Copy, V> FifoMap<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            map: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        self.map.get(key)
+    }
+
+    /// Returns the value of the key, inserting it first if missing, and the
+    /// entry it evicted to make room, if any.
+    fn get_or_insert_with(&mut self, key: K, f: impl FnOnce() -> V) -> (&mut V, Option<V>) {
+        let mut evicted = None;
+        if !self.map.contains_key(&key) {
+            if self.map.len() >= self.capacity {
+                evicted = self.order.pop_front().and_then(|old| self.map.remove(&old));
+            }
+            self.map.insert(key, f());
+            self.order.push_back(key);
+        }
+        (self.map.get_mut(&key).expect("just inserted"), evicted)
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let value = self.map.remove(key)?;
+        self.order.retain(|k| k != key);
+        Some(value)
+    }
+}
+
+#[derive(Debug)]
+struct Index {
+    /// Entries by oracle and oracle event id
+    entries: HashMap<(XOnlyPublicKey, String), DirectoryEntry>,
+    /// Every verified announcement event, an oracle may have republished an
+    /// announcement under a new nostr event id
+    announcement_events: HashMap<EventId, Event>,
+    /// Attestations received before the announcement they reference, by
+    /// announcement event id
+    orphans: FifoMap<EventId, Vec<Event>>,
+    /// Ids of the attestation events in `orphans`
+    orphan_ids: HashSet<EventId>,
+    /// Deleted announcement events and the key that requested the deletion
+    deleted: HashMap<EventId, PublicKey>,
+    /// Deletion requests for announcement events not received yet, with the
+    /// key that requested them. Anyone can send them, so only the latest are
+    /// kept.
+    pending_deletions: FifoMap<(EventId, PublicKey), ()>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            announcement_events: HashMap::new(),
+            orphans: FifoMap::new(MAX_ORPHANED_ANNOUNCEMENTS),
+            orphan_ids: HashSet::new(),
+            deleted: HashMap::new(),
+            pending_deletions: FifoMap::new(MAX_PENDING_DELETIONS),
+        }
+    }
+}
+
+impl Index {
+    fn insert(&mut self, event: &Event) -> Result<bool, ParseError> {
+        if event.kind == ANNOUNCEMENT_KIND {
+            self.insert_announcement(event)
+        } else if event.kind == ATTESTATION_KIND {
+            self.insert_attestation(event)
+        } else if event.kind == Kind::EventDeletion {
+            self.insert_deletion(event)
+        } else {
+            Err(ParseError::WrongKind)
+        }
+    }
+
+    /// Adds an announcement event. Only the oracle key, or a nostr key the
+    /// oracle bound, can publish it, see [`parse_announcement_event`], so an
+    /// entry cannot be taken over by someone republishing the announcement.
+    /// When the oracle publishes it more than once, the first event is the
+    /// source of the entry.
+    fn insert_announcement(&mut self, event: &Event) -> Result<bool, ParseError> {
+        if self.announcement_events.contains_key(&event.id)
+            || self.deleted.get(&event.id) == Some(&event.pubkey)
+            || self
+                .pending_deletions
+                .get(&(event.id, event.pubkey))
+                .is_some()
+        {
+            return Ok(false);
+        }
+
+        let announcement = parse_announcement_event(event)?;
+        let tags = parse_announcement_tags(event)?;
+        self.announcement_events.insert(event.id, event.clone());
+
+        let key = (
+            announcement.oracle_public_key,
+            announcement.oracle_event.event_id.clone(),
+        );
+        self.entries.entry(key).or_insert_with(|| DirectoryEntry {
+            nostr_public_key: event.pubkey,
+            announcement_event_id: event.id,
+            announcement,
+            attestation: None,
+            relays: tags.relays,
+        });
+
+        for orphan in self.orphans.remove(&event.id).unwrap_or_default() {
+            self.orphan_ids.remove(&orphan.id);
+            if let Err(e) = self.insert_attestation(&orphan) {
+                log::debug!("Ignoring attestation event {}: {e}", orphan.id);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn insert_attestation(&mut self, event: &Event) -> Result<bool, ParseError> {
+        let tags = parse_attestation_tags(event)?;
+        let Some(announcement_event) = self.announcement_events.get(&tags.announcement_event_id)
+        else {
+            if !self.orphan_ids.contains(&event.id) {
+                event.verify().map_err(|_| ParseError::InvalidSignature)?;
+                self.insert_orphan(tags.announcement_event_id, event);
+            }
+            return Ok(false);
+        };
+
+        let attestation = parse_attestation_event(event, announcement_event)?;
+        let key = (attestation.oracle_public_key, attestation.event_id.clone());
+        match self.entries.get_mut(&key) {
+            Some(entry) if entry.attestation.is_none() => {
+                entry.attestation = Some(attestation);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Keeps an attestation until its announcement arrives, within the limits
+    /// of the orphans.
+    fn insert_orphan(&mut self, announcement_event_id: EventId, event: &Event) {
+        let (orphans, evicted) = self
+            .orphans
+            .get_or_insert_with(announcement_event_id, Vec::new);
+        if orphans.len() >= MAX_ORPHANS_PER_ANNOUNCEMENT {
+            log::debug!(
+                "Dropping attestation event {}, too many for announcement {announcement_event_id}",
+                event.id
+            );
+        } else {
+            orphans.push(event.clone());
+            self.orphan_ids.insert(event.id);
+        }
+        for orphan in evicted.unwrap_or_default() {
+            self.orphan_ids.remove(&orphan.id);
+        }
+    }
+
+    fn insert_deletion(&mut self, event: &Event) -> Result<bool, ParseError> {
+        event.verify().map_err(|_| ParseError::InvalidSignature)?;
+
+        let mut changed = false;
+        for id in event.tags.event_ids() {
+            // only the author of an event can delete it
+            match self.announcement_events.get(id) {
+                Some(announcement) if announcement.pubkey == event.pubkey => {
+                    self.remove_announcement(id);
+                    changed = true;
+                }
+                Some(_) => continue,
+                None => {
+                    self.pending_deletions
+                        .get_or_insert_with((*id, event.pubkey), || ());
+                    continue;
+                }
+            }
+            self.deleted.insert(*id, event.pubkey);
+        }
+
+        Ok(changed)
+    }
+
+    /// Drops a deleted announcement event. The entry it is the source of is
+    /// taken over by another announcement event of the oracle for the same
+    /// oracle event, if any, or dropped.
+    fn remove_announcement(&mut self, id: &EventId) {
+        if self.announcement_events.remove(id).is_none() {
+            return;
+        }
+        let Some(key) = self
+            .entries
+            .iter()
+            .find(|(_, entry)| entry.announcement_event_id == *id)
+            .map(|(key, _)| key.clone())
+        else {
+            return;
+        };
+        let entry = self.entries.remove(&key).expect("just found");
+
+        let replacement = self
+            .announcement_events
+            .values()
+            .filter(|e| parse_announcement_tags(e).is_ok_and(|tags| tags.event_id == key.1))
+            .find_map(|e| {
+                let announcement = parse_announcement_event(e).ok()?;
+                let tags = parse_announcement_tags(e).ok()?;
+                (announcement.oracle_public_key == key.0).then_some((e, announcement, tags))
+            });
+        if let Some((event, announcement, tags)) = replacement {
+            // the attestation still holds if the oracle announced the same nonces
+            let attestation = entry.attestation.filter(|attestation| {
+                attestation
+                    .validate(&Secp256k1::verification_only(), &announcement)
+                    .is_ok()
+            });
+            let entry = DirectoryEntry {
+                nostr_public_key: event.pubkey,
+                announcement_event_id: event.id,
+                announcement,
+                attestation,
+                relays: tags.relays,
+            };
+            self.entries.insert(key, entry);
+        }
+    }
+}
+
+/// Index of the events announced by oracles on nostr.
+///
+/// The directory is filled with [`OracleDirectory::sync`], which fetches the
+/// events already on the relays, and kept up to date with
+/// [`OracleDirectory::run`]. Events can also be added directly with
+/// [`OracleDirectory::insert`].
+#[derive(Debug, Clone)]
+pub struct OracleDirectory {
+    client: Client,
+    /// Nostr keys to follow, every oracle when `None`
+    authors: Option<HashSet<PublicKey>>,
+    index: Arc<RwLock<Index>>,
+}
+
+impl OracleDirectory {
+    /// Creates a directory of every oracle publishing to the client's relays.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            authors: None,
+            index: Arc::new(RwLock::new(Index::default())),
+        }
+    }
+
+    /// Only follows the oracles publishing with the given nostr keys.
+    pub fn with_oracles(mut self, authors: impl IntoIterator<Item = PublicKey>) -> Self {
+        self.authors = Some(authors.into_iter().collect());
+        self
+    }
+
+    /// The nostr client used to read events.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// The filters for the announcements, attestations and deletion requests
+    /// of the followed oracles.
+    pub fn filters(&self) -> Vec<Filter> {
+        let filters = vec![
+            Filter::new().kinds([ANNOUNCEMENT_KIND, ATTESTATION_KIND]),
+            Filter::new().kind(Kind::EventDeletion).custom_tag(
+                SingleLetterTag::lowercase(Alphabet::K),
+                ANNOUNCEMENT_KIND.as_u16().to_string(),
+            ),
+        ];
+        match &self.authors {
+            Some(authors) => filters
+                .into_iter()
+                .map(|f| f.authors(authors.iter().copied()))
+                .collect(),
+            None => filters,
+        }
+    }
+
+    /// Fetches the events stored on the relays and adds them to the index.
+    ///
+    /// Returns the number of events that changed the index.
+    pub async fn sync(&self, timeout: Duration) -> Result<usize, Error> {
+        let mut events = Vec::new();
+        for filter in self.filters() {
+            let fetched = self
+                .client
+                .fetch_events(filter, timeout)
+                .await
+                .map_err(|e| {
+                    log::warn!("Failed to fetch oracle events: {e}");
+                    Error::Nostr
+                })?;
+            events.extend(fetched);
+        }
+
+        // announcements first so attestations and deletions can be matched
+        events.sort_by_key(|e| e.kind != ANNOUNCEMENT_KIND);
+        Ok(events.iter().filter(|e| self.handle_event(e)).count())
+    }
+
+    /// Subscribes to the followed oracles and adds their events to the index
+    /// as they arrive, until the client shuts down.
+    pub async fn run(&self) -> Result<(), Error> {
+        for filter in self.filters() {
+            self.client.subscribe(filter, None).await.map_err(|e| {
+                log::warn!("Failed to subscribe to oracle events: {e}");
+                Error::Nostr
+            })?;
+        }
+
+        self.client
+            .handle_notifications(|notification| async move {
+                if let RelayPoolNotification::Event { event, .. } = notification {
+                    self.handle_event(&event);
+                }
+                Ok(false)
+            })
+            .await
+            .map_err(|_| Error::Nostr)
+    }
+
+    /// Verifies an oracle event and adds it to the index.
+    ///
+    /// Returns whether the index changed. Events from oracles that are not
+    /// followed are ignored.
+    pub fn insert(&self, event: &Event) -> Result<bool, ParseError> {
+        if self
+            .authors
+            .as_ref()
+            .is_some_and(|authors| !authors.contains(&event.pubkey))
+        {
+            return Ok(false);
+        }
+        self.index.write().unwrap().insert(event)
+    }
+
+    fn handle_event(&self, event: &Event) -> bool {
+        match self.insert(event) {
+            Ok(changed) => changed,
+            Err(e) => {
+                log::debug!("Ignoring oracle event {}: {e}", event.id);
+                false
+            }
+        }
+    }
+
+    /// Returns the entry for an oracle event.
+    pub fn get(&self, oracle_public_key: XOnlyPublicKey, event_id: &str) -> Option<DirectoryEntry> {
+        self.index
+            .read()
+            .unwrap()
+            .entries
+            .get(&(oracle_public_key, event_id.to_string()))
+            .cloned()
+    }
+
+    /// Returns the entries matching the query, ordered by maturity.
+    pub fn query(&self, query: &DirectoryQuery) -> Vec<DirectoryEntry> {
+        let mut entries: Vec<DirectoryEntry> = self
+            .index
+            .read()
+            .unwrap()
+            .entries
+            .values()
+            .filter(|entry| query.matches(entry))
+            .cloned()
+            .collect();
+        entries.sort_by(|a, b| {
+            a.event_maturity_epoch()
+                .cmp(&b.event_maturity_epoch())
+                .then_with(|| a.event_id().cmp(b.event_id()))
+        });
+        entries
+    }
+
+    /// Returns every entry, ordered by maturity.
+    pub fn entries(&self) -> Vec<DirectoryEntry> {
+        self.query(&DirectoryQuery::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::nostr_events::{
+        create_announcement_event, create_attestation_event, create_deletion_event,
+    };
+    use crate::storage::MemoryStorage;
+    use crate::Oracle;
+    use bitcoin::bip32::Xpriv;
+    use bitcoin::secp256k1::rand::{thread_rng, Rng};
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::Network;
+    use nostr::Keys;
+
+    fn create_oracle() -> Oracle<MemoryStorage> {
+        let mut seed: [u8; 64] = [0; 64];
+        thread_rng().fill(&mut seed);
+        let xpriv = Xpriv::new_master(Network::Regtest, &seed).unwrap();
+        Oracle::from_xpriv(MemoryStorage::default(), xpriv).unwrap()
+    }
+
+    fn create_directory() -> OracleDirectory {
+        OracleDirectory::new(Client::new(Keys::generate()))
+    }
+
+    #[tokio::test]
+    async fn test_directory_query() {
+        let oracle = create_oracle();
+        let other = create_oracle();
+        let directory = create_directory();
+
+        let enum_ann = oracle
+            .create_enum_event("enum".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let numeric_ann = oracle
+            .create_numeric_event("numeric".into(), 8, false, 0, "m/s".into(), 200)
+            .await
+            .unwrap();
+        let other_ann = other
+            .create_enum_event("enum".into(), vec!["a".into(), "b".into()], 300)
+            .await
+            .unwrap();
+
+        for (oracle, ann) in [
+            (&oracle, &enum_ann),
+            (&oracle, &numeric_ann),
+            (&other, &other_ann),
+        ] {
+            let event = create_announcement_event(&oracle.nostr_keys(), ann, &[], None).unwrap();
+            assert!(directory.insert(&event).unwrap());
+            assert!(!directory.insert(&event).unwrap());
+        }
+
+        let event_ids = |query: DirectoryQuery| -> Vec<(XOnlyPublicKey, String)> {
+            directory
+                .query(&query)
+                .into_iter()
+                .map(|e| (e.oracle_public_key(), e.event_id().to_string()))
+                .collect()
+        };
+        assert_eq!(
+            event_ids(DirectoryQuery::default()),
+            vec![
+                (oracle.public_key(), "enum".to_string()),
+                (oracle.public_key(), "numeric".to_string()),
+                (other.public_key(), "enum".to_string()),
+            ]
+        );
+        assert_eq!(
+            event_ids(DirectoryQuery {
+                oracle_public_key: Some(other.public_key()),
+                ..Default::default()
+            }),
+            vec![(other.public_key(), "enum".to_string())]
+        );
+        assert_eq!(
+            event_ids(DirectoryQuery {
+                descriptor: Some(DescriptorType::DigitDecomposition),
+                ..Default::default()
+            }),
+            vec![(oracle.public_key(), "numeric".to_string())]
+        );
+        assert_eq!(
+            event_ids(DirectoryQuery {
+                maturity_from: Some(100),
+                maturity_until: Some(300),
+                ..Default::default()
+            }),
+            vec![
+                (oracle.public_key(), "enum".to_string()),
+                (oracle.public_key(), "numeric".to_string()),
+            ]
+        );
+
+        // invalid events are rejected
+        let event = create_announcement_event(&Keys::generate(), &enum_ann, &[], None).unwrap();
+        assert_eq!(
+            directory.insert(&event).unwrap_err(),
+            ParseError::WrongAuthor
+        );
+    }
+
+    #[tokio::test]
+    async fn test_directory_attestations() {
+        let oracle = create_oracle();
+        let keys = oracle.nostr_keys();
+        let directory = create_directory().with_oracles([keys.public_key]);
+
+        let ann = oracle
+            .create_enum_event("test".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let att = oracle
+            .sign_enum_event("test".into(), "a".into())
+            .await
+            .unwrap();
+        let ann_event = create_announcement_event(&keys, &ann, &[], None).unwrap();
+        let att_event = create_attestation_event(&keys, &att, ann_event.id, None).unwrap();
+
+        // the attestation is kept until its announcement arrives
+        assert!(!directory.insert(&att_event).unwrap());
+        assert!(directory.insert(&ann_event).unwrap());
+        let entry = directory.get(oracle.public_key(), "test").unwrap();
+        assert_eq!(entry.announcement, ann);
+        assert_eq!(entry.attestation, Some(att));
+        assert_eq!(
+            directory
+                .query(&DirectoryQuery {
+                    attested: Some(false),
+                    ..Default::default()
+                })
+                .len(),
+            0
+        );
+
+        // events of oracles that are not followed are ignored
+        let other = create_oracle();
+        let ann = other
+            .create_enum_event("test".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let event = create_announcement_event(&other.nostr_keys(), &ann, &[], None).unwrap();
+        assert!(!directory.insert(&event).unwrap());
+        assert_eq!(directory.entries().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_directory_deletion() {
+        let oracle = create_oracle();
+        let keys = oracle.nostr_keys();
+        let directory = create_directory();
+
+        let ann = oracle
+            .create_enum_event("test".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let ann_event = create_announcement_event(&keys, &ann, &[], None).unwrap();
+        directory.insert(&ann_event).unwrap();
+
+        // only the oracle can delete its announcement
+        let deletion =
+            create_deletion_event(&Keys::generate(), "test", ann_event.id, "spam").unwrap();
+        assert!(!directory.insert(&deletion).unwrap());
+        assert_eq!(directory.entries().len(), 1);
+
+        let deletion = create_deletion_event(&keys, "test", ann_event.id, "withdrawn").unwrap();
+        assert!(directory.insert(&deletion).unwrap());
+        assert!(directory.entries().is_empty());
+
+        // the deleted announcement is not added back
+        assert!(!directory.insert(&ann_event).unwrap());
+        assert!(directory.entries().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_directory_republished_announcement() {
+        let nostr_key = SecretKey::from_slice(&[7; 32]).unwrap();
+        let oracle = create_oracle().with_nostr_key(nostr_key);
+        let keys = oracle.nostr_keys();
+        let binding = oracle.nostr_key_binding().unwrap();
+        let directory = create_directory();
+
+        let ann = oracle
+            .create_enum_event("test".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let att = oracle
+            .sign_enum_event("test".into(), "a".into())
+            .await
+            .unwrap();
+        let relay = RelayUrl::parse("wss://oracle.example.com").unwrap();
+
+        // someone else republishing the announcement first, even with the
+        // oracle's binding, does not become its source
+        let squatter = Keys::generate();
+        let squatter_relay = RelayUrl::parse("wss://squatter.example.com").unwrap();
+        let republished =
+            create_announcement_event(&squatter, &ann, &[squatter_relay], Some(&binding)).unwrap();
+        assert_eq!(
+            directory.insert(&republished).unwrap_err(),
+            ParseError::WrongAuthor
+        );
+
+        let ann_event =
+            create_announcement_event(&keys, &ann, std::slice::from_ref(&relay), Some(&binding))
+                .unwrap();
+        assert!(directory.insert(&ann_event).unwrap());
+        let entry = directory.get(oracle.public_key(), "test").unwrap();
+        assert_eq!(entry.nostr_public_key, keys.public_key);
+        assert_eq!(entry.announcement_event_id, ann_event.id);
+        assert_eq!(entry.relays, vec![relay]);
+
+        let deletion = create_deletion_event(&squatter, "test", ann_event.id, "spam").unwrap();
+        assert!(!directory.insert(&deletion).unwrap());
+        assert_eq!(directory.entries().len(), 1);
+
+        // the oracle also publishes it with its signing key, which takes over
+        // when the first announcement is deleted
+        let oracle_keys = Keys::new(
+            nostr::SecretKey::from_slice(&oracle.key_pair.secret_key().secret_bytes()).unwrap(),
+        );
+        let oracle_event = create_announcement_event(&oracle_keys, &ann, &[], None).unwrap();
+        assert!(directory.insert(&oracle_event).unwrap());
+        let att_event =
+            create_attestation_event(&keys, &att, ann_event.id, Some(&binding)).unwrap();
+        assert!(directory.insert(&att_event).unwrap());
+
+        let deletion = create_deletion_event(&keys, "test", ann_event.id, "moved").unwrap();
+        assert!(directory.insert(&deletion).unwrap());
+        let entry = directory.get(oracle.public_key(), "test").unwrap();
+        assert_eq!(entry.nostr_public_key, oracle_keys.public_key);
+        assert_eq!(entry.announcement_event_id, oracle_event.id);
+        assert_eq!(entry.attestation, Some(att));
+        assert!(entry.relays.is_empty());
+
+        let deletion =
+            create_deletion_event(&oracle_keys, "test", oracle_event.id, "withdrawn").unwrap();
+        assert!(directory.insert(&deletion).unwrap());
+        assert!(directory.entries().is_empty());
+    }
+
+    #[test]
+    fn test_fifo_map_evicts_oldest() {
+        let mut map = FifoMap::new(2);
+        assert_eq!(map.get_or_insert_with(1, || "a").1, None);
+        assert_eq!(map.get_or_insert_with(2, || "b").1, None);
+        // existing keys keep their value and their place
+        assert_eq!(*map.get_or_insert_with(1, || "c").0, "a");
+        assert_eq!(map.get_or_insert_with(3, || "c").1, Some("a"));
+        assert_eq!(map.get(&1), None);
+
+        assert_eq!(map.remove(&2), Some("b"));
+        assert_eq!(map.get_or_insert_with(4, || "d").1, None);
+        assert_eq!(map.get(&3), Some(&"c"));
+        assert_eq!(map.get(&4), Some(&"d"));
+    }
+
+    #[tokio::test]
+    async fn test_directory_orphans_are_bounded() {
+        let oracle = create_oracle();
+        let directory = create_directory();
+        oracle
+            .create_enum_event("test".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let att = oracle
+            .sign_enum_event("test".into(), "a".into())
+            .await
+            .unwrap();
+        let orphan = |announcement_event_id: EventId| {
+            create_attestation_event(&Keys::generate(), &att, announcement_event_id, None).unwrap()
+        };
+        let orphans = |directory: &OracleDirectory| {
+            let index = directory.index.read().unwrap();
+            let count = index.orphans.map.values().map(Vec::len).sum::<usize>();
+            assert_eq!(count, index.orphan_ids.len());
+            count
+        };
+
+        // the same attestation is only kept once
+        let announcement_event_id = EventId::from_byte_array([0xff; 32]);
+        let event = orphan(announcement_event_id);
+        assert!(!directory.insert(&event).unwrap());
+        assert!(!directory.insert(&event).unwrap());
+        assert_eq!(orphans(&directory), 1);
+
+        for _ in 0..MAX_ORPHANS_PER_ANNOUNCEMENT {
+            directory.insert(&orphan(announcement_event_id)).unwrap();
+        }
+        assert_eq!(orphans(&directory), MAX_ORPHANS_PER_ANNOUNCEMENT);
+
+        // the attestations of the oldest announcements are dropped first
+        for i in 0..MAX_ORPHANED_ANNOUNCEMENTS {
+            let mut id = [0; 32];
+            id[..8].copy_from_slice(&(i as u64).to_be_bytes());
+            directory
+                .insert(&orphan(EventId::from_byte_array(id)))
+                .unwrap();
+        }
+        assert_eq!(orphans(&directory), MAX_ORPHANED_ANNOUNCEMENTS);
+        assert!(directory
+            .index
+            .read()
+            .unwrap()
+            .orphans
+            .get(&announcement_event_id)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_directory_deletion_before_announcement() {
+        let oracle = create_oracle();
+        let keys = oracle.nostr_keys();
+        let directory = create_directory();
+
+        let ann = oracle
+            .create_enum_event("test".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let ann_event = create_announcement_event(&keys, &ann, &[], None).unwrap();
+
+        // a deletion request from someone else does not hide the announcement
+        let deletion =
+            create_deletion_event(&Keys::generate(), "test", ann_event.id, "spam").unwrap();
+        assert!(!directory.insert(&deletion).unwrap());
+        let deletion = create_deletion_event(&keys, "test", ann_event.id, "withdrawn").unwrap();
+        assert!(!directory.insert(&deletion).unwrap());
+
+        // the oracle's one does
+        assert!(!directory.insert(&ann_event).unwrap());
+        assert!(directory.entries().is_empty());
+    }
+}
@@ -1,15 +1,320 @@
-use dlc_messages::oracle_msgs::{OracleAnnouncement, OracleAttestation};
-use lightning::util::ser::Writeable;
+//! Nostr events for DLC oracles, following NIP-88.
+//!
+//! Announcements are published as kind 88 events and attestations as kind 89
+//! events. The content of both is the base64 encoded DLC message, and the tags
+//! carry enough information to find and filter the events without decoding
+//! their content:
+//!
+//! | tag        | kind   | value                                            |
+//! |------------|--------|--------------------------------------------------|
+//! | `d`        | 88, 89 | the oracle event id                              |
+//! | `maturity` | 88     | the event maturity as a unix timestamp           |
+//! | `t`        | 88     | the descriptor type, `enum` or `digit_decomposition` |
+//! | `relays`   | 88     | the relays the oracle publishes attestations to  |
+//! | `e`        | 89     | the id of the nostr announcement event           |
+//!
+//! NIP-88 only specifies the content, the `relays` tag listing where the
+//! oracle will publish the attestation and the `e` tag of attestations
+//! referencing the announcement event. The other tags are
+//! kormir's own and clients following only the NIP ignore them:
+//!
+//! - `d` is NIP-01's identifier tag. Kinds 88 and 89 are regular events, not
+//!   addressable ones, so relays keep every event with the same `d` instead
+//!   of replacing them, and a republished announcement does not hide the
+//!   first one. The tag is there because relays only index single letter
+//!   tags, letting clients query an oracle event by its id with a `#d`
+//!   filter, and because [`crate::nostr_directory`] matches announcements
+//!   and attestations by it.
+//! - `t` is NIP-24's hashtag, so the descriptor type can be queried with a
+//!   `#t` filter.
+//! - `maturity` is not indexed by relays, clients filter on it once the
+//!   events are fetched.
+//!
+//! Withdrawn announcements are retracted with a NIP-09 deletion request that
+//! references the announcement and repeats its `d` tag.
+//!
+//! Oracles also describe themselves with a kind 0 profile and advertise where
+//! they publish with a NIP-65 relay list.
+//!
+//! When the oracle publishes with a nostr key other than its signing key, the
+//! announcement, attestation and profile events carry a
+//! `["oracle", <oracle pubkey>, <signature>]` tag. The signature is made by the
+//! oracle key over the nostr pubkey, see [`KeyBinding`].
+
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use dlc_messages::oracle_msgs::{EventDescriptor, OracleAnnouncement, OracleAttestation};
+use lightning::io::Cursor;
+use lightning::util::ser::{Readable, Writeable};
 use nostr::event::Error;
-use nostr::{Event, EventBuilder, EventId, Keys, Kind, Tag};
+use nostr::nips::nip09::EventDeletionRequest;
+use nostr::{
+    Event, EventBuilder, EventId, Keys, Kind, Metadata, PublicKey, RelayUrl, Tag, TagKind,
+    TagStandard, Url,
+};
+use secp256k1_zkp::schnorr::Signature;
+use secp256k1_zkp::{Message, Secp256k1, Verification, XOnlyPublicKey};
+use std::fmt::{Display, Formatter};
 
-/// Creates an Oracle Attestation event for nostr.
+/// Kind of oracle announcement events
+pub const ANNOUNCEMENT_KIND: Kind = Kind::Custom(88);
+/// Kind of oracle attestation events
+pub const ATTESTATION_KIND: Kind = Kind::Custom(89);
+
+const EVENT_ID_TAG: &str = "d";
+const MATURITY_TAG: &str = "maturity";
+const DESCRIPTOR_TAG: &str = "t";
+const RELAYS_TAG: &str = "relays";
+const ANNOUNCEMENT_TAG: &str = "e";
+const BINDING_TAG: &str = "oracle";
+
+/// BIP-340 tag of the message signed by a [`KeyBinding`]
+const BINDING_HASH_TAG: &[u8] = b"kormir/nostr-key-binding";
+
+const ENUM_DESCRIPTOR: &str = "enum";
+const DIGIT_DECOMPOSITION_DESCRIPTOR: &str = "digit_decomposition";
+
+/// Error returned when an oracle nostr event is malformed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The event is not of the expected kind
+    WrongKind,
+    /// A required tag is missing
+    MissingTag(&'static str),
+    /// A tag has an invalid value
+    InvalidTag(&'static str),
+    /// The nostr event id or signature is invalid
+    InvalidSignature,
+    /// The content is not a base64 encoded DLC message
+    InvalidContent,
+    /// The nostr author is not the oracle that signed the message
+    WrongAuthor,
+    /// The announcement signature or nonces are invalid
+    InvalidAnnouncement,
+    /// The attestation signatures do not match the announcement
+    InvalidAttestation,
+    /// The attestation does not reference the given announcement
+    AnnouncementMismatch,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::WrongKind => write!(f, "Wrong event kind"),
+            ParseError::MissingTag(tag) => write!(f, "Missing \"{tag}\" tag"),
+            ParseError::InvalidTag(tag) => write!(f, "Invalid \"{tag}\" tag"),
+            ParseError::InvalidSignature => write!(f, "Invalid nostr event signature"),
+            ParseError::InvalidContent => write!(f, "Invalid event content"),
+            ParseError::WrongAuthor => write!(f, "Event author is not the oracle"),
+            ParseError::InvalidAnnouncement => write!(f, "Invalid oracle announcement"),
+            ParseError::InvalidAttestation => write!(f, "Invalid oracle attestation"),
+            ParseError::AnnouncementMismatch => {
+                write!(f, "Attestation does not match the announcement")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The descriptor type of an oracle event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DescriptorType {
+    Enum,
+    DigitDecomposition,
+}
+
+impl DescriptorType {
+    pub fn from_descriptor(descriptor: &EventDescriptor) -> Self {
+        match descriptor {
+            EventDescriptor::EnumEvent(_) => DescriptorType::Enum,
+            EventDescriptor::DigitDecompositionEvent(_) => DescriptorType::DigitDecomposition,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DescriptorType::Enum => ENUM_DESCRIPTOR,
+            DescriptorType::DigitDecomposition => DIGIT_DECOMPOSITION_DESCRIPTOR,
+        }
+    }
+
+    fn from_tag(value: &str) -> Option<Self> {
+        match value {
+            ENUM_DESCRIPTOR => Some(DescriptorType::Enum),
+            DIGIT_DECOMPOSITION_DESCRIPTOR => Some(DescriptorType::DigitDecomposition),
+            _ => None,
+        }
+    }
+}
+
+/// The tags of an oracle announcement event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnnouncementTags {
+    pub event_id: String,
+    pub event_maturity_epoch: u32,
+    pub descriptor: DescriptorType,
+    pub relays: Vec<RelayUrl>,
+}
+
+/// The tags of an oracle attestation event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttestationTags {
+    pub event_id: String,
+    pub announcement_event_id: EventId,
+}
+
+/// Proof that a nostr key publishes on behalf of an oracle.
+///
+/// The signature is a BIP-340 signature by the oracle key over the tagged hash
+/// of the nostr public key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub oracle_public_key: XOnlyPublicKey,
+    pub signature: Signature,
+}
+
+impl KeyBinding {
+    /// The message the oracle signs to bind the given nostr key.
+    pub fn message(nostr_public_key: &PublicKey) -> Message {
+        let tag = sha256::Hash::hash(BINDING_HASH_TAG);
+        let mut engine = sha256::Hash::engine();
+        engine.input(tag.as_ref());
+        engine.input(tag.as_ref());
+        engine.input(&nostr_public_key.to_bytes());
+        Message::from_digest(sha256::Hash::from_engine(engine).to_byte_array())
+    }
+
+    /// Checks the binding was signed by the oracle for the given nostr key.
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        nostr_public_key: &PublicKey,
+    ) -> bool {
+        secp.verify_schnorr(
+            &self.signature,
+            &Self::message(nostr_public_key),
+            &self.oracle_public_key,
+        )
+        .is_ok()
+    }
+
+    fn to_tag(self) -> Tag {
+        Tag::custom(
+            TagKind::custom(BINDING_TAG),
+            [
+                hex::encode(self.oracle_public_key.serialize()),
+                hex::encode(self.signature.serialize()),
+            ],
+        )
+    }
+}
+
+/// Reads the key binding of an event, if it has one.
+///
+/// The binding is only parsed, use [`KeyBinding::verify`] to check it was made
+/// for the event author.
+pub fn parse_key_binding(event: &Event) -> Result<Option<KeyBinding>, ParseError> {
+    let Some(values) = find_tag(event, BINDING_TAG) else {
+        return Ok(None);
+    };
+    let [pubkey, signature, ..] = values else {
+        return Err(ParseError::InvalidTag(BINDING_TAG));
+    };
+    let oracle_public_key = hex::decode(pubkey)
+        .ok()
+        .and_then(|b| XOnlyPublicKey::from_slice(&b).ok())
+        .ok_or(ParseError::InvalidTag(BINDING_TAG))?;
+    let signature = hex::decode(signature)
+        .ok()
+        .and_then(|b| Signature::from_slice(&b).ok())
+        .ok_or(ParseError::InvalidTag(BINDING_TAG))?;
+
+    Ok(Some(KeyBinding {
+        oracle_public_key,
+        signature,
+    }))
+}
+
+/// The public profile of an oracle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OracleProfile {
+    pub name: String,
+    pub description: Option<String>,
+    /// Where the oracle's HTTP API can be reached
+    pub http_endpoint: Option<Url>,
+    /// The kinds of events the oracle announces
+    pub descriptor_types: Vec<DescriptorType>,
+}
+
+/// Creates the kind 0 metadata event describing the oracle.
+///
+/// The HTTP endpoint is published as the profile website and repeated, with
+/// the descriptor types, in custom fields so clients can tell oracles apart
+/// from regular profiles.
+pub fn create_profile_event(
+    keys: &Keys,
+    profile: &OracleProfile,
+    binding: Option<&KeyBinding>,
+) -> Result<Event, Error> {
+    let descriptor_types = profile
+        .descriptor_types
+        .iter()
+        .map(|d| d.as_str())
+        .collect::<Vec<_>>();
+    let mut metadata = Metadata::new()
+        .name(&profile.name)
+        .custom_field("descriptor_types", descriptor_types);
+    if let Some(description) = &profile.description {
+        metadata = metadata.about(description);
+    }
+    if let Some(endpoint) = &profile.http_endpoint {
+        metadata = metadata
+            .website(endpoint.clone())
+            .custom_field("http_endpoint", endpoint.to_string());
+    }
+
+    let event = EventBuilder::metadata(&metadata)
+        .tags(binding.map(|b| b.to_tag()))
+        .build(keys.public_key)
+        .sign_with_keys(keys)?;
+    Ok(event)
+}
+
+/// Creates the NIP-65 relay list of the relays the oracle publishes to.
+pub fn create_relay_list_event(keys: &Keys, relays: &[RelayUrl]) -> Result<Event, Error> {
+    let event = EventBuilder::relay_list(relays.iter().map(|url| (url.clone(), None)))
+        .build(keys.public_key)
+        .sign_with_keys(keys)?;
+    Ok(event)
+}
+
+/// Creates an Oracle Announcement event for nostr.
+///
+/// `relays` are the relays the oracle will publish the attestation to. The
+/// `binding` is required by clients when `keys` are not the oracle's key.
 pub fn create_announcement_event(
     keys: &Keys,
     announcement: &OracleAnnouncement,
+    relays: &[RelayUrl],
+    binding: Option<&KeyBinding>,
 ) -> Result<Event, Error> {
     let content = announcement.encode();
-    let event = EventBuilder::new(Kind::Custom(88), base64::encode(content))
+    let oracle_event = &announcement.oracle_event;
+    let descriptor = DescriptorType::from_descriptor(&oracle_event.event_descriptor);
+    let event = EventBuilder::new(ANNOUNCEMENT_KIND, base64::encode(content))
+        .tags([
+            Tag::identifier(&oracle_event.event_id),
+            Tag::custom(
+                TagKind::custom(MATURITY_TAG),
+                [oracle_event.event_maturity_epoch.to_string()],
+            ),
+            Tag::hashtag(descriptor.as_str()),
+            Tag::custom(
+                TagKind::custom(RELAYS_TAG),
+                relays.iter().map(|r| r.to_string()),
+            ),
+        ])
+        .tags(binding.map(|b| b.to_tag()))
         .build(keys.public_key)
         .sign_with_keys(keys)?;
     Ok(event)
@@ -20,11 +325,406 @@ pub fn create_attestation_event(
     keys: &Keys,
     attestation: &OracleAttestation,
     event_id: EventId,
+    binding: Option<&KeyBinding>,
 ) -> Result<Event, Error> {
     let content = attestation.encode();
-    let event = EventBuilder::new(Kind::Custom(89), base64::encode(content))
-        .tag(Tag::event(event_id))
+    let event = EventBuilder::new(ATTESTATION_KIND, base64::encode(content))
+        .tags([Tag::identifier(&attestation.event_id), Tag::event(event_id)])
+        .tags(binding.map(|b| b.to_tag()))
+        .build(keys.public_key)
+        .sign_with_keys(keys)?;
+    Ok(event)
+}
+
+/// Creates a NIP-09 deletion request for a withdrawn Oracle Announcement event.
+pub fn create_deletion_event(
+    keys: &Keys,
+    event_id: &str,
+    announcement_event_id: EventId,
+    reason: &str,
+) -> Result<Event, Error> {
+    let request = EventDeletionRequest::new()
+        .id(announcement_event_id)
+        .reason(reason);
+    let event = EventBuilder::delete(request)
+        .tags([
+            Tag::from_standardized(TagStandard::Kind {
+                kind: ANNOUNCEMENT_KIND,
+                uppercase: false,
+            }),
+            Tag::identifier(event_id),
+        ])
         .build(keys.public_key)
         .sign_with_keys(keys)?;
     Ok(event)
 }
+
+/// Reads and validates the tags of an Oracle Announcement event.
+pub fn parse_announcement_tags(event: &Event) -> Result<AnnouncementTags, ParseError> {
+    if event.kind != ANNOUNCEMENT_KIND {
+        return Err(ParseError::WrongKind);
+    }
+
+    let event_id = single_value(event, EVENT_ID_TAG)?.to_string();
+    let event_maturity_epoch = single_value(event, MATURITY_TAG)?
+        .parse()
+        .map_err(|_| ParseError::InvalidTag(MATURITY_TAG))?;
+    let descriptor = DescriptorType::from_tag(single_value(event, DESCRIPTOR_TAG)?)
+        .ok_or(ParseError::InvalidTag(DESCRIPTOR_TAG))?;
+    let relays = find_tag(event, RELAYS_TAG)
+        .ok_or(ParseError::MissingTag(RELAYS_TAG))?
+        .iter()
+        .map(|url| RelayUrl::parse(url).map_err(|_| ParseError::InvalidTag(RELAYS_TAG)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(AnnouncementTags {
+        event_id,
+        event_maturity_epoch,
+        descriptor,
+        relays,
+    })
+}
+
+/// Reads and validates the tags of an Oracle Attestation event.
+pub fn parse_attestation_tags(event: &Event) -> Result<AttestationTags, ParseError> {
+    if event.kind != ATTESTATION_KIND {
+        return Err(ParseError::WrongKind);
+    }
+
+    let event_id = single_value(event, EVENT_ID_TAG)?.to_string();
+    let announcement_event_id = EventId::from_hex(single_value(event, ANNOUNCEMENT_TAG)?)
+        .map_err(|_| ParseError::InvalidTag(ANNOUNCEMENT_TAG))?;
+
+    Ok(AttestationTags {
+        event_id,
+        announcement_event_id,
+    })
+}
+
+/// Verifies an Oracle Announcement event and decodes its content.
+///
+/// Checks the nostr signature, the tags, the announcement signature and that
+/// the nostr author is the oracle or carries a valid [`KeyBinding`] for it.
+pub fn parse_announcement_event(event: &Event) -> Result<OracleAnnouncement, ParseError> {
+    event.verify().map_err(|_| ParseError::InvalidSignature)?;
+    let tags = parse_announcement_tags(event)?;
+
+    let announcement: OracleAnnouncement = decode_content(event)?;
+    check_author(event, &announcement.oracle_public_key)?;
+    announcement
+        .validate(&Secp256k1::verification_only())
+        .map_err(|_| ParseError::InvalidAnnouncement)?;
+
+    let oracle_event = &announcement.oracle_event;
+    if tags.event_id != oracle_event.event_id {
+        return Err(ParseError::InvalidTag(EVENT_ID_TAG));
+    }
+    if tags.event_maturity_epoch != oracle_event.event_maturity_epoch {
+        return Err(ParseError::InvalidTag(MATURITY_TAG));
+    }
+    if tags.descriptor != DescriptorType::from_descriptor(&oracle_event.event_descriptor) {
+        return Err(ParseError::InvalidTag(DESCRIPTOR_TAG));
+    }
+
+    Ok(announcement)
+}
+
+/// Verifies an Oracle Attestation event against the announcement event it
+/// references and decodes its content.
+///
+/// The announcement event is verified with [`parse_announcement_event`], the
+/// attestation's "e" tag must point to it and the attestation signatures must
+/// use the announced nonces.
+pub fn parse_attestation_event(
+    event: &Event,
+    announcement_event: &Event,
+) -> Result<OracleAttestation, ParseError> {
+    event.verify().map_err(|_| ParseError::InvalidSignature)?;
+    let tags = parse_attestation_tags(event)?;
+    if tags.announcement_event_id != announcement_event.id {
+        return Err(ParseError::AnnouncementMismatch);
+    }
+    let announcement = parse_announcement_event(announcement_event)?;
+
+    let attestation: OracleAttestation = decode_content(event)?;
+    check_author(event, &attestation.oracle_public_key)?;
+    if tags.event_id != attestation.event_id {
+        return Err(ParseError::InvalidTag(EVENT_ID_TAG));
+    }
+    if attestation.event_id != announcement.oracle_event.event_id {
+        return Err(ParseError::AnnouncementMismatch);
+    }
+    attestation
+        .validate(&Secp256k1::verification_only(), &announcement)
+        .map_err(|_| ParseError::InvalidAttestation)?;
+
+    Ok(attestation)
+}
+
+fn decode_content<T: Readable>(event: &Event) -> Result<T, ParseError> {
+    let bytes = base64::decode(&event.content).map_err(|_| ParseError::InvalidContent)?;
+    let mut cursor = Cursor::new(&bytes);
+    let msg = T::read(&mut cursor).map_err(|_| ParseError::InvalidContent)?;
+    if cursor.position() as usize != bytes.len() {
+        return Err(ParseError::InvalidContent);
+    }
+    Ok(msg)
+}
+
+fn check_author(event: &Event, oracle_public_key: &XOnlyPublicKey) -> Result<(), ParseError> {
+    if event.pubkey.to_bytes() == oracle_public_key.serialize() {
+        return Ok(());
+    }
+
+    match parse_key_binding(event)? {
+        Some(binding)
+            if binding.oracle_public_key == *oracle_public_key
+                && binding.verify(&Secp256k1::verification_only(), &event.pubkey) =>
+        {
+            Ok(())
+        }
+        _ => Err(ParseError::WrongAuthor),
+    }
+}
+
+/// Returns the values of the first tag with the given name.
+fn find_tag<'a>(event: &'a Event, name: &str) -> Option<&'a [String]> {
+    event
+        .tags
+        .iter()
+        .map(|tag| tag.as_slice())
+        .find(|tag| tag.first().is_some_and(|n| n == name))
+        .map(|tag| &tag[1..])
+}
+
+fn single_value<'a>(event: &'a Event, name: &'static str) -> Result<&'a str, ParseError> {
+    match find_tag(event, name) {
+        None => Err(ParseError::MissingTag(name)),
+        Some([value, ..]) => Ok(value),
+        Some([]) => Err(ParseError::InvalidTag(name)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::Oracle;
+    use bitcoin::bip32::Xpriv;
+    use bitcoin::secp256k1::rand::{thread_rng, Rng};
+    use bitcoin::Network;
+    use nostr::JsonUtil;
+
+    fn create_oracle() -> Oracle<MemoryStorage> {
+        let mut seed: [u8; 64] = [0; 64];
+        thread_rng().fill(&mut seed);
+        let xpriv = Xpriv::new_master(Network::Regtest, &seed).unwrap();
+        Oracle::from_xpriv(MemoryStorage::default(), xpriv).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_announcement_tags() {
+        let oracle = create_oracle();
+        let keys = oracle.nostr_keys();
+        let ann = oracle
+            .create_numeric_event("test".into(), 8, false, 0, "m/s".into(), 100)
+            .await
+            .unwrap();
+        let relays = vec![RelayUrl::parse("wss://relay.damus.io").unwrap()];
+
+        let event = create_announcement_event(&keys, &ann, &relays, None).unwrap();
+        let tags = parse_announcement_tags(&event).unwrap();
+        assert_eq!(
+            tags,
+            AnnouncementTags {
+                event_id: "test".into(),
+                event_maturity_epoch: 100,
+                descriptor: DescriptorType::DigitDecomposition,
+                relays,
+            }
+        );
+        assert_eq!(
+            parse_attestation_tags(&event).unwrap_err(),
+            ParseError::WrongKind
+        );
+
+        let event = EventBuilder::new(ANNOUNCEMENT_KIND, "")
+            .tags([Tag::identifier("test")])
+            .sign_with_keys(&keys)
+            .unwrap();
+        assert_eq!(
+            parse_announcement_tags(&event).unwrap_err(),
+            ParseError::MissingTag(MATURITY_TAG)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_attestation_tags() {
+        let oracle = create_oracle();
+        let keys = oracle.nostr_keys();
+        oracle
+            .create_enum_event("test".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let att = oracle
+            .sign_enum_event("test".into(), "a".into())
+            .await
+            .unwrap();
+
+        let event = create_attestation_event(&keys, &att, EventId::all_zeros(), None).unwrap();
+        let tags = parse_attestation_tags(&event).unwrap();
+        assert_eq!(
+            tags,
+            AttestationTags {
+                event_id: "test".into(),
+                announcement_event_id: EventId::all_zeros(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parse_events() {
+        let oracle = create_oracle();
+        let keys = oracle.nostr_keys();
+        let ann = oracle
+            .create_enum_event("test".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let att = oracle
+            .sign_enum_event("test".into(), "a".into())
+            .await
+            .unwrap();
+
+        let ann_event = create_announcement_event(&keys, &ann, &[], None).unwrap();
+        let att_event = create_attestation_event(&keys, &att, ann_event.id, None).unwrap();
+        assert_eq!(parse_announcement_event(&ann_event).unwrap(), ann);
+        assert_eq!(
+            parse_attestation_event(&att_event, &ann_event).unwrap(),
+            att
+        );
+
+        // attestation pointing to another announcement
+        let other = create_announcement_event(&keys, &ann, &[], None).unwrap();
+        let event = create_attestation_event(&keys, &att, EventId::all_zeros(), None).unwrap();
+        assert_eq!(
+            parse_attestation_event(&event, &other).unwrap_err(),
+            ParseError::AnnouncementMismatch
+        );
+
+        // event published by someone other than the oracle
+        let event = create_announcement_event(&Keys::generate(), &ann, &[], None).unwrap();
+        assert_eq!(
+            parse_announcement_event(&event).unwrap_err(),
+            ParseError::WrongAuthor
+        );
+
+        // attestation for a different oracle event
+        let other_ann = oracle
+            .create_enum_event("other".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let other = create_announcement_event(&keys, &other_ann, &[], None).unwrap();
+        let event = create_attestation_event(&keys, &att, other.id, None).unwrap();
+        assert_eq!(
+            parse_attestation_event(&event, &other).unwrap_err(),
+            ParseError::AnnouncementMismatch
+        );
+    }
+
+    #[test]
+    fn test_profile_events() {
+        let keys = Keys::generate();
+        let profile = OracleProfile {
+            name: "Kormir".into(),
+            description: Some("test oracle".into()),
+            http_endpoint: Some(Url::parse("https://oracle.example.com").unwrap()),
+            descriptor_types: vec![DescriptorType::Enum, DescriptorType::DigitDecomposition],
+        };
+
+        let event = create_profile_event(&keys, &profile, None).unwrap();
+        assert_eq!(event.kind, Kind::Metadata);
+        let metadata = Metadata::from_json(&event.content).unwrap();
+        assert_eq!(metadata.name.as_deref(), Some("Kormir"));
+        assert_eq!(metadata.about.as_deref(), Some("test oracle"));
+        assert_eq!(
+            metadata.custom["descriptor_types"],
+            nostr::serde_json::json!(["enum", "digit_decomposition"])
+        );
+
+        let relays = vec![RelayUrl::parse("wss://relay.damus.io").unwrap()];
+        let event = create_relay_list_event(&keys, &relays).unwrap();
+        assert_eq!(event.kind, Kind::RelayList);
+        assert_eq!(
+            event.tags.first().unwrap().as_slice(),
+            ["r", "wss://relay.damus.io"]
+        );
+    }
+
+    #[test]
+    fn test_deletion_event() {
+        let keys = Keys::generate();
+        let id = EventId::all_zeros();
+        let event = create_deletion_event(&keys, "test", id, "cancelled").unwrap();
+
+        assert_eq!(event.kind, Kind::EventDeletion);
+        assert_eq!(event.content, "cancelled");
+        assert_eq!(event.tags.event_ids().collect::<Vec<_>>(), vec![&id]);
+        assert_eq!(find_tag(&event, "k"), Some(&["88".to_string()][..]));
+        assert_eq!(
+            find_tag(&event, EVENT_ID_TAG),
+            Some(&["test".to_string()][..])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_separate_nostr_key() {
+        let mut seed: [u8; 64] = [0; 64];
+        thread_rng().fill(&mut seed);
+        let xpriv = Xpriv::new_master(Network::Regtest, &seed).unwrap();
+        let nostr_key = crate::derive_nostr_key(&Secp256k1::new(), xpriv).unwrap();
+        let oracle = Oracle::from_xpriv(MemoryStorage::default(), xpriv)
+            .unwrap()
+            .with_nostr_key(nostr_key);
+        assert!(create_oracle().nostr_key_binding().is_none());
+
+        let keys = oracle.nostr_keys();
+        assert_ne!(keys.public_key.to_bytes(), oracle.public_key().serialize());
+        let binding = oracle.nostr_key_binding().unwrap();
+        assert!(binding.verify(&Secp256k1::new(), &keys.public_key));
+        assert!(!binding.verify(&Secp256k1::new(), &Keys::generate().public_key));
+
+        let ann = oracle
+            .create_enum_event("test".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let att = oracle
+            .sign_enum_event("test".into(), "a".into())
+            .await
+            .unwrap();
+
+        let ann_event = create_announcement_event(&keys, &ann, &[], Some(&binding)).unwrap();
+        let att_event =
+            create_attestation_event(&keys, &att, ann_event.id, Some(&binding)).unwrap();
+        assert_eq!(parse_key_binding(&ann_event).unwrap(), Some(binding));
+        assert_eq!(parse_announcement_event(&ann_event).unwrap(), ann);
+        assert_eq!(
+            parse_attestation_event(&att_event, &ann_event).unwrap(),
+            att
+        );
+
+        // without the binding the nostr key is not trusted
+        let event = create_announcement_event(&keys, &ann, &[], None).unwrap();
+        assert_eq!(
+            parse_announcement_event(&event).unwrap_err(),
+            ParseError::WrongAuthor
+        );
+
+        // a binding can not be reused by another nostr key
+        let event =
+            create_announcement_event(&Keys::generate(), &ann, &[], Some(&binding)).unwrap();
+        assert_eq!(
+            parse_announcement_event(&event).unwrap_err(),
+            ParseError::WrongAuthor
+        );
+    }
+}
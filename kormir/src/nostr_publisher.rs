@@ -0,0 +1,707 @@
+use crate::error::Error;
+use crate::nostr_events::{
+    create_announcement_event, create_attestation_event, create_deletion_event,
+    create_profile_event, create_relay_list_event, KeyBinding, OracleProfile,
+};
+use crate::observer::OracleObserver;
+use crate::retry::retry_delay;
+use crate::storage::{OracleEventData, OutboxEvent, Storage};
+use dlc_messages::oracle_msgs::{OracleAnnouncement, OracleAttestation};
+use nostr::{Event, EventId, Filter, JsonUtil, Keys, RelayUrl, Timestamp};
+use nostr_sdk::Client;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Observer that publishes the oracle's announcements and attestations to nostr.
+///
+/// Every event is written to the storage outbox before it is sent and only
+/// removed once a relay has accepted it, so events that could not be published
+/// are retried by [`NostrPublisher::flush`] instead of being lost.
+#[derive(Debug, Clone)]
+pub struct NostrPublisher<S: Storage> {
+    storage: S,
+    keys: Keys,
+    binding: Option<KeyBinding>,
+    client: Client,
+    queue_only: bool,
+    stats: Arc<RwLock<HashMap<RelayUrl, RelayPublishStats>>>,
+}
+
+/// Number of nostr events a relay accepted and rejected since the publisher
+/// was created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RelayPublishStats {
+    pub published: u64,
+    pub failed: u64,
+}
+
+/// Outcome of flushing the outbox
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlushResult {
+    /// Nostr events accepted by at least one relay
+    pub published: Vec<EventId>,
+    /// Nostr events that failed again and were rescheduled
+    pub failed: Vec<EventId>,
+}
+
+/// What happened to a single nostr event during a rebroadcast
+#[derive(Debug, Clone, Serialize)]
+pub struct RebroadcastReport {
+    /// The oracle event the nostr event belongs to
+    pub event_id: String,
+    pub nostr_event_id: EventId,
+    pub kind: u16,
+    /// Whether the original nostr event could not be recovered and a new one was signed
+    pub resigned: bool,
+    /// Relays that accepted the event
+    pub published: Vec<String>,
+    /// Relays that rejected the event, with the reason
+    pub failed: HashMap<String, String>,
+}
+
+impl<S: Storage> NostrPublisher<S> {
+    pub fn new(storage: S, keys: Keys, client: Client) -> Self {
+        Self {
+            storage,
+            keys,
+            binding: None,
+            client,
+            queue_only: false,
+            stats: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Sets the binding added to published events when `keys` are not the
+    /// oracle's signing key, see [`crate::Oracle::nostr_key_binding`].
+    pub fn with_key_binding(mut self, binding: Option<KeyBinding>) -> Self {
+        self.binding = binding;
+        self
+    }
+
+    /// Only writes new events to the outbox without sending them, for oracles
+    /// running without relays. They are published by [`NostrPublisher::flush`].
+    pub fn with_queue_only(mut self, queue_only: bool) -> Self {
+        self.queue_only = queue_only;
+        self
+    }
+
+    /// The nostr client used to publish events.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// The publication results of each relay the publisher sent events to.
+    pub fn publish_stats(&self) -> HashMap<RelayUrl, RelayPublishStats> {
+        self.stats.read().unwrap().clone()
+    }
+
+    fn record_publish(&self, url: &RelayUrl, published: bool) {
+        let mut stats = self.stats.write().unwrap();
+        let stats = stats.entry(url.clone()).or_default();
+        if published {
+            stats.published += 1;
+        } else {
+            stats.failed += 1;
+        }
+    }
+
+    /// Returns the nostr events that have not been published yet.
+    pub async fn pending(&self) -> Result<Vec<OutboxEvent>, Error> {
+        self.storage.list_outbox_events().await
+    }
+
+    /// Retries publishing the events in the outbox.
+    ///
+    /// Events whose backoff has not elapsed yet are skipped unless `force` is set.
+    pub async fn flush(&self, force: bool) -> Result<FlushResult, Error> {
+        let now = Timestamp::now();
+        let mut result = FlushResult::default();
+        for entry in self.storage.list_outbox_events().await? {
+            if !force && entry.next_attempt > now {
+                continue;
+            }
+
+            let id = entry.nostr_event.id;
+            match self.publish(entry).await {
+                Ok(()) => result.published.push(id),
+                Err(_) => result.failed.push(id),
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Republishes the nostr events of every stored announcement and attestation
+    /// to all configured relays.
+    ///
+    /// The original nostr events are reused when they are still in the outbox or
+    /// can be fetched from a relay, so their ids stay the same. Events that were
+    /// never published, or that no relay has anymore, are signed again and their
+    /// new ids are saved.
+    ///
+    /// Events that no relay accepts are left in the outbox, counting the
+    /// rebroadcast as a failed attempt. As with [`NostrPublisher::flush`], an
+    /// event is published once a relay accepts it and leaves the outbox, the
+    /// relays that failed are listed in the report and are not retried.
+    pub async fn rebroadcast(&self, timeout: Duration) -> Result<Vec<RebroadcastReport>, Error> {
+        let pending: HashMap<EventId, OutboxEvent> = self
+            .storage
+            .list_outbox_events()
+            .await?
+            .into_iter()
+            .map(|e| (e.nostr_event.id, e))
+            .collect();
+
+        let mut reports = Vec::new();
+        for data in self.storage.list_events().await? {
+            // the deletion request of a withdrawn event is retried from the outbox
+            if data.withdrawn {
+                continue;
+            }
+            let stored = parse_event_id(&data.announcement_event_id);
+            let (announcement, resigned) = match self.recover(stored, &pending, timeout).await {
+                Some(event) => (event, false),
+                None => (self.rebuild_announcement(&data).await?, true),
+            };
+            reports.push(
+                self.broadcast(&data.event_id, &announcement, resigned, &pending)
+                    .await?,
+            );
+
+            let Some(attestation) = data.attestation() else {
+                continue;
+            };
+            // a new announcement invalidates the "e" tag of the old attestation
+            let mut stored = parse_event_id(&data.attestation_event_id);
+            if resigned {
+                if let Some(old) = stored.take().filter(|id| pending.contains_key(id)) {
+                    self.storage.remove_outbox_event(old).await?;
+                }
+            }
+            let (event, resigned) = match self.recover(stored, &pending, timeout).await {
+                Some(event) => (event, false),
+                None => {
+                    let event = create_attestation_event(
+                        &self.keys,
+                        &attestation,
+                        announcement.id,
+                        self.binding.as_ref(),
+                    )
+                    .map_err(|_| Error::Nostr)?;
+                    self.storage
+                        .add_attestation_event_id(data.event_id.clone(), event.id)
+                        .await?;
+                    (event, true)
+                }
+            };
+            reports.push(
+                self.broadcast(&data.event_id, &event, resigned, &pending)
+                    .await?,
+            );
+        }
+
+        Ok(reports)
+    }
+
+    /// Looks for the original nostr event in the outbox, then on the relays.
+    async fn recover(
+        &self,
+        id: Option<EventId>,
+        pending: &HashMap<EventId, OutboxEvent>,
+        timeout: Duration,
+    ) -> Option<Event> {
+        let id = id?;
+        if let Some(entry) = pending.get(&id) {
+            return Some(entry.nostr_event.clone());
+        }
+
+        let filter = Filter::new().id(id).author(self.keys.public_key);
+        match self.client.fetch_events(filter, timeout).await {
+            Ok(events) => events
+                .into_iter()
+                .find(|e| e.id == id && e.verify().is_ok()),
+            Err(e) => {
+                log::warn!("Failed to fetch nostr event {id}: {e}");
+                None
+            }
+        }
+    }
+
+    async fn rebuild_announcement(&self, data: &OracleEventData) -> Result<Event, Error> {
+        let relays = self.relay_urls().await;
+        let event = create_announcement_event(
+            &self.keys,
+            &data.announcement,
+            &relays,
+            self.binding.as_ref(),
+        )
+        .map_err(|_| Error::Nostr)?;
+        self.storage
+            .add_announcement_event_id(data.event_id.clone(), event.id)
+            .await?;
+        Ok(event)
+    }
+
+    /// Publishes the oracle's kind 0 profile and its NIP-65 relay list.
+    ///
+    /// Both are replaceable events, so publishing them again updates what
+    /// relays serve. They are not tied to an oracle event and are sent directly
+    /// instead of going through the outbox.
+    pub async fn publish_profile(&self, profile: &OracleProfile) -> Result<(), Error> {
+        let relays = self.relay_urls().await;
+        let events = [
+            create_profile_event(&self.keys, profile, self.binding.as_ref()),
+            create_relay_list_event(&self.keys, &relays),
+        ];
+        for event in events {
+            let event = event.map_err(|e| {
+                log::error!("Failed to create profile nostr event: {e}");
+                Error::Nostr
+            })?;
+            self.client.send_event(&event).await.map_err(|e| {
+                log::warn!("Failed to publish nostr event {}: {e}", event.id);
+                Error::Nostr
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// The relays the client publishes to, advertised in announcements.
+    async fn relay_urls(&self) -> Vec<RelayUrl> {
+        self.client.relays().await.into_keys().collect()
+    }
+
+    /// Sends the event to each relay individually to report on every one of them.
+    /// When none accepts it, the attempt is added to its outbox entry, if any.
+    async fn broadcast(
+        &self,
+        event_id: &str,
+        event: &Event,
+        resigned: bool,
+        pending: &HashMap<EventId, OutboxEvent>,
+    ) -> Result<RebroadcastReport, Error> {
+        let mut published = Vec::new();
+        let mut failed = HashMap::new();
+        for url in self.client.relays().await.into_keys() {
+            let result = self.client.send_event_to([url.clone()], event).await;
+            self.record_publish(&url, matches!(&result, Ok(o) if !o.success.is_empty()));
+            match result {
+                Ok(output) if !output.success.is_empty() => published.push(url.to_string()),
+                Ok(output) => {
+                    let reason = output.failed.into_values().next();
+                    failed.insert(url.to_string(), reason.unwrap_or_default());
+                }
+                Err(e) => {
+                    failed.insert(url.to_string(), e.to_string());
+                }
+            }
+        }
+
+        if published.is_empty() {
+            let mut entry = pending.get(&event.id).cloned().unwrap_or(OutboxEvent {
+                event_id: event_id.to_string(),
+                nostr_event: event.clone(),
+                attempts: 0,
+                next_attempt: Timestamp::now(),
+                last_error: None,
+            });
+            entry.attempts += 1;
+            entry.next_attempt = Timestamp::now() + retry_delay(entry.attempts);
+            entry.last_error = Some("no relay accepted the event".to_string());
+            self.storage.save_outbox_event(entry).await?;
+        } else {
+            self.storage.remove_outbox_event(event.id).await?;
+        }
+
+        Ok(RebroadcastReport {
+            event_id: event_id.to_string(),
+            nostr_event_id: event.id,
+            kind: event.kind.as_u16(),
+            resigned,
+            published,
+            failed,
+        })
+    }
+
+    /// Queues the event in the outbox and tries to publish it right away,
+    /// unless the publisher is queue only.
+    async fn enqueue(&self, event_id: String, event: Event) -> Result<(), Error> {
+        let entry = OutboxEvent {
+            event_id,
+            nostr_event: event,
+            attempts: 0,
+            next_attempt: Timestamp::now(),
+            last_error: None,
+        };
+        self.storage.save_outbox_event(entry.clone()).await?;
+        if self.queue_only {
+            return Ok(());
+        }
+
+        self.publish(entry).await
+    }
+
+    async fn publish(&self, mut entry: OutboxEvent) -> Result<(), Error> {
+        log::debug!("Broadcasting nostr event: {}", entry.nostr_event.as_json());
+
+        let result = self.client.send_event(&entry.nostr_event).await;
+        match &result {
+            Ok(output) => {
+                output
+                    .success
+                    .iter()
+                    .for_each(|url| self.record_publish(url, true));
+                output
+                    .failed
+                    .keys()
+                    .for_each(|url| self.record_publish(url, false));
+            }
+            // every relay failed
+            Err(_) => {
+                for url in self.relay_urls().await {
+                    self.record_publish(&url, false);
+                }
+            }
+        }
+        match result {
+            Ok(_) => {
+                self.storage
+                    .remove_outbox_event(entry.nostr_event.id)
+                    .await?;
+                Ok(())
+            }
+            Err(e) => {
+                log::warn!(
+                    "Failed to publish nostr event {} (attempt {}): {e}",
+                    entry.nostr_event.id,
+                    entry.attempts + 1,
+                );
+                entry.attempts += 1;
+                entry.next_attempt = Timestamp::now() + retry_delay(entry.attempts);
+                entry.last_error = Some(e.to_string());
+                self.storage.save_outbox_event(entry).await?;
+                Err(Error::Nostr)
+            }
+        }
+    }
+}
+
+fn parse_event_id(id: &Option<String>) -> Option<EventId> {
+    id.as_ref().and_then(|id| EventId::from_hex(id).ok())
+}
+
+impl<S: Storage> OracleObserver for NostrPublisher<S> {
+    async fn on_announcement(&self, announcement: &OracleAnnouncement) -> Result<(), Error> {
+        let event_id = announcement.oracle_event.event_id.clone();
+        let relays = self.relay_urls().await;
+        let event =
+            create_announcement_event(&self.keys, announcement, &relays, self.binding.as_ref())
+                .map_err(|e| {
+                    log::error!("Failed to create announcement nostr event: {e}");
+                    Error::Nostr
+                })?;
+
+        self.storage
+            .add_announcement_event_id(event_id.clone(), event.id)
+            .await?;
+
+        log::debug!(
+            "Added announcement event id to storage: {}",
+            event.id.to_hex()
+        );
+
+        self.enqueue(event_id, event).await
+    }
+
+    async fn on_attestation(&self, attestation: &OracleAttestation) -> Result<(), Error> {
+        let data = self
+            .storage
+            .get_event(attestation.event_id.clone())
+            .await?
+            .ok_or_else(|| Error::NotFound {
+                event_id: attestation.event_id.clone(),
+            })?;
+        let announcement_event_id =
+            parse_event_id(&data.announcement_event_id).ok_or_else(|| Error::NotFound {
+                event_id: attestation.event_id.clone(),
+            })?;
+
+        let event = create_attestation_event(
+            &self.keys,
+            attestation,
+            announcement_event_id,
+            self.binding.as_ref(),
+        )
+        .map_err(|e| {
+            log::error!("Failed to create attestation nostr event: {e}");
+            Error::Nostr
+        })?;
+
+        self.storage
+            .add_attestation_event_id(attestation.event_id.clone(), event.id)
+            .await?;
+
+        log::debug!(
+            "Added attestation event id to storage: {}",
+            event.id.to_hex()
+        );
+
+        self.enqueue(attestation.event_id.clone(), event).await
+    }
+
+    /// Retracts the announcement with a NIP-09 deletion request. Announcements
+    /// that no relay has accepted yet are simply dropped from the outbox.
+    async fn on_cancellation(&self, data: &OracleEventData) -> Result<(), Error> {
+        let Some(announcement_event_id) = parse_event_id(&data.announcement_event_id) else {
+            return Ok(());
+        };
+
+        let pending = self.storage.list_outbox_events().await?;
+        if pending
+            .iter()
+            .any(|e| e.nostr_event.id == announcement_event_id)
+        {
+            log::debug!("Dropping unpublished announcement for {}", data.event_id);
+            return self
+                .storage
+                .remove_outbox_event(announcement_event_id)
+                .await;
+        }
+
+        let event = create_deletion_event(
+            &self.keys,
+            &data.event_id,
+            announcement_event_id,
+            "Event withdrawn by the oracle",
+        )
+        .map_err(|e| {
+            log::error!("Failed to create deletion nostr event: {e}");
+            Error::Nostr
+        })?;
+
+        self.enqueue(data.event_id.clone(), event).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::Oracle;
+    use bitcoin::bip32::Xpriv;
+    use bitcoin::secp256k1::rand::{thread_rng, Rng};
+    use bitcoin::Network;
+
+    fn create_oracle() -> Oracle<MemoryStorage, NostrPublisher<MemoryStorage>> {
+        let mut seed: [u8; 64] = [0; 64];
+        thread_rng().fill(&mut seed);
+        let xpriv = Xpriv::new_master(Network::Regtest, &seed).unwrap();
+        let storage = MemoryStorage::default();
+        let oracle = Oracle::from_xpriv(storage.clone(), xpriv).unwrap();
+        // no relays, so every publication fails
+        let client = Client::new(oracle.nostr_keys());
+        let publisher = NostrPublisher::new(storage, oracle.nostr_keys(), client);
+        oracle.with_observer(publisher)
+    }
+
+    #[tokio::test]
+    async fn test_failed_publication_stays_in_outbox() {
+        let oracle = create_oracle();
+        let publisher = oracle.observer();
+
+        let event_id = "test_outbox".to_string();
+        oracle
+            .create_enum_event(event_id.clone(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        oracle
+            .sign_enum_event(event_id.clone(), "a".into())
+            .await
+            .unwrap();
+
+        let pending = publisher.pending().await.unwrap();
+        assert_eq!(pending.len(), 2);
+        assert!(pending.iter().all(|e| e.event_id == event_id));
+        assert!(pending.iter().all(|e| e.attempts == 1));
+        assert!(pending.iter().all(|e| e.last_error.is_some()));
+
+        // backoff has not elapsed, nothing is retried
+        let result = publisher.flush(false).await.unwrap();
+        assert_eq!(result, FlushResult::default());
+
+        let result = publisher.flush(true).await.unwrap();
+        assert!(result.published.is_empty());
+        assert_eq!(result.failed.len(), 2);
+        let pending = publisher.pending().await.unwrap();
+        assert!(pending.iter().all(|e| e.attempts == 2));
+    }
+
+    #[tokio::test]
+    async fn test_publish_stats_per_relay() {
+        let oracle = create_oracle();
+        let publisher = oracle.observer();
+        // never connected, so it rejects every event
+        publisher
+            .client()
+            .add_relay("ws://127.0.0.1:1")
+            .await
+            .unwrap();
+        let url = RelayUrl::parse("ws://127.0.0.1:1").unwrap();
+
+        oracle
+            .create_enum_event("test_stats".into(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        publisher.flush(true).await.unwrap();
+
+        let stats = publisher.publish_stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(
+            stats[&url],
+            RelayPublishStats {
+                published: 0,
+                failed: 2
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rebroadcast_rebuilds_missing_events() {
+        let oracle = create_oracle();
+        let publisher = oracle.observer();
+
+        let event_id = "test_rebroadcast".to_string();
+        oracle
+            .create_enum_event(event_id.clone(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        oracle
+            .sign_enum_event(event_id.clone(), "a".into())
+            .await
+            .unwrap();
+        let data = oracle.storage.get_event(event_id.clone()).await.unwrap();
+        let data = data.unwrap();
+
+        // events still in the outbox are reused as is, the failed rebroadcast
+        // adds to their attempts
+        let reports = publisher.rebroadcast(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(reports.len(), 2);
+        assert!(reports
+            .iter()
+            .all(|r| !r.resigned && r.published.is_empty()));
+        let pending = publisher.pending().await.unwrap();
+        assert_eq!(pending.len(), 2);
+        for entry in pending {
+            assert_eq!(entry.attempts, 2);
+            assert!(entry.next_attempt >= Timestamp::now() + retry_delay(2) - 5);
+        }
+        assert_eq!(
+            Some(reports[0].nostr_event_id.to_hex()),
+            data.announcement_event_id
+        );
+        assert_eq!(
+            Some(reports[1].nostr_event_id.to_hex()),
+            data.attestation_event_id
+        );
+
+        // once lost, both are signed again and the new ids are saved
+        for entry in publisher.pending().await.unwrap() {
+            oracle
+                .storage
+                .remove_outbox_event(entry.nostr_event.id)
+                .await
+                .unwrap();
+        }
+        let reports = publisher.rebroadcast(Duration::from_secs(1)).await.unwrap();
+        assert_eq!(reports.len(), 2);
+        assert!(reports.iter().all(|r| r.resigned));
+        let data = oracle.storage.get_event(event_id).await.unwrap().unwrap();
+        assert_eq!(
+            Some(reports[0].nostr_event_id.to_hex()),
+            data.announcement_event_id
+        );
+        assert_eq!(
+            Some(reports[1].nostr_event_id.to_hex()),
+            data.attestation_event_id
+        );
+        assert_eq!(publisher.pending().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_withdraw_publishes_deletion() {
+        let oracle = create_oracle();
+        let publisher = oracle.observer();
+
+        // never published, the announcement is dropped from the outbox
+        let event_id = "test_withdraw_pending".to_string();
+        oracle
+            .create_enum_event(event_id.clone(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        oracle.withdraw_event(event_id).await.unwrap();
+        assert!(publisher.pending().await.unwrap().is_empty());
+
+        // published, a deletion request is queued
+        let event_id = "test_withdraw_published".to_string();
+        oracle
+            .create_enum_event(event_id.clone(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        let announcement = publisher.pending().await.unwrap().remove(0).nostr_event;
+        oracle
+            .storage
+            .remove_outbox_event(announcement.id)
+            .await
+            .unwrap();
+        oracle.withdraw_event(event_id.clone()).await.unwrap();
+
+        let pending = publisher.pending().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].event_id, event_id);
+        assert_eq!(pending[0].nostr_event.kind, nostr::Kind::EventDeletion);
+        assert_eq!(
+            pending[0].nostr_event.tags.event_ids().collect::<Vec<_>>(),
+            vec![&announcement.id]
+        );
+
+        // withdrawn events are not rebroadcast
+        let reports = publisher.rebroadcast(Duration::from_secs(1)).await.unwrap();
+        assert!(reports.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_queue_only() {
+        let oracle = create_oracle();
+        let publisher = oracle.observer().clone().with_queue_only(true);
+        let oracle = oracle.with_observer(publisher.clone());
+        publisher
+            .client()
+            .add_relay("ws://127.0.0.1:1")
+            .await
+            .unwrap();
+
+        let event_id = "test_queue_only".to_string();
+        oracle
+            .create_enum_event(event_id.clone(), vec!["a".into(), "b".into()], 100)
+            .await
+            .unwrap();
+        oracle
+            .sign_enum_event(event_id.clone(), "a".into())
+            .await
+            .unwrap();
+
+        // nothing was sent, so no attempt failed
+        let pending = publisher.pending().await.unwrap();
+        assert_eq!(pending.len(), 2);
+        assert!(pending
+            .iter()
+            .all(|e| e.attempts == 0 && e.last_error.is_none()));
+        // and the relay was never connected to
+        for relay in publisher.client().relays().await.values() {
+            assert_eq!(relay.stats().attempts(), 0);
+        }
+    }
+}
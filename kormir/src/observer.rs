@@ -0,0 +1,73 @@
+use crate::error::Error;
+use crate::storage::OracleEventData;
+use dlc_messages::oracle_msgs::{OracleAnnouncement, OracleAttestation};
+
+/// Hooks that are notified after the oracle has committed an action.
+///
+/// By the time an observer is called the action has already been saved to
+/// storage, so an observer error is logged by the [`crate::Oracle`] and does
+/// not fail the call that triggered it.
+pub trait OracleObserver {
+    /// Called after an announcement has been created and saved
+    async fn on_announcement(&self, _announcement: &OracleAnnouncement) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called after an event has been attested and its signatures saved
+    async fn on_attestation(&self, _attestation: &OracleAttestation) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called after an event has been cancelled
+    async fn on_cancellation(&self, _event: &OracleEventData) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Observer that ignores every notification
+impl OracleObserver for () {}
+
+/// Notifies the observer when there is one, so it can be turned off at runtime
+impl<O: OracleObserver> OracleObserver for Option<O> {
+    async fn on_announcement(&self, announcement: &OracleAnnouncement) -> Result<(), Error> {
+        match self {
+            Some(observer) => observer.on_announcement(announcement).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn on_attestation(&self, attestation: &OracleAttestation) -> Result<(), Error> {
+        match self {
+            Some(observer) => observer.on_attestation(attestation).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn on_cancellation(&self, event: &OracleEventData) -> Result<(), Error> {
+        match self {
+            Some(observer) => observer.on_cancellation(event).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Notifies both observers in order, returning the first error.
+impl<A: OracleObserver, B: OracleObserver> OracleObserver for (A, B) {
+    async fn on_announcement(&self, announcement: &OracleAnnouncement) -> Result<(), Error> {
+        let a = self.0.on_announcement(announcement).await;
+        let b = self.1.on_announcement(announcement).await;
+        a.and(b)
+    }
+
+    async fn on_attestation(&self, attestation: &OracleAttestation) -> Result<(), Error> {
+        let a = self.0.on_attestation(attestation).await;
+        let b = self.1.on_attestation(attestation).await;
+        a.and(b)
+    }
+
+    async fn on_cancellation(&self, event: &OracleEventData) -> Result<(), Error> {
+        let a = self.0.on_cancellation(event).await;
+        let b = self.1.on_cancellation(event).await;
+        a.and(b)
+    }
+}
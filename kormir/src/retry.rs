@@ -0,0 +1,32 @@
+//! Exponential backoff shared by the deliveries that are retried, the nostr
+//! outbox and the webhooks of the server.
+
+/// Delay before the first retry, in seconds
+pub const BASE_RETRY_DELAY: u64 = 30;
+/// Upper bound on the delay between two attempts, in seconds
+pub const MAX_RETRY_DELAY: u64 = 60 * 60;
+
+/// Delay before the next attempt after the given number of failed attempts,
+/// in seconds. It doubles with each attempt up to [`MAX_RETRY_DELAY`].
+pub fn retry_delay(attempts: u32) -> u64 {
+    let exp = attempts.saturating_sub(1).min(16);
+    (BASE_RETRY_DELAY << exp).min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(0), BASE_RETRY_DELAY);
+        assert_eq!(retry_delay(1), BASE_RETRY_DELAY);
+        assert_eq!(retry_delay(2), BASE_RETRY_DELAY * 2);
+        assert_eq!(retry_delay(3), BASE_RETRY_DELAY * 4);
+        assert_eq!(retry_delay(7), BASE_RETRY_DELAY * 64);
+        // capped from the 8th attempt on
+        assert_eq!(retry_delay(8), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(17), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+}
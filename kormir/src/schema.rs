@@ -0,0 +1,57 @@
+//! OpenAPI schemas of the DLC messages, as serialized by `dlc-messages` with
+//! its `use-serde` feature. The keys and signatures are hex encoded.
+//!
+//! The messages are defined in `dlc-messages`, so they can not derive
+//! [`ToSchema`] themselves, these types only describe them.
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OracleAnnouncement {
+    pub announcement_signature: String,
+    pub oracle_public_key: String,
+    pub oracle_event: OracleEvent,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OracleEvent {
+    pub oracle_nonces: Vec<String>,
+    pub event_maturity_epoch: u32,
+    pub event_descriptor: EventDescriptor,
+    pub event_id: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum EventDescriptor {
+    EnumEvent(EnumEventDescriptor),
+    DigitDecompositionEvent(DigitDecompositionEventDescriptor),
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EnumEventDescriptor {
+    pub outcomes: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DigitDecompositionEventDescriptor {
+    pub base: u16,
+    pub is_signed: bool,
+    pub unit: String,
+    pub precision: i32,
+    pub nb_digits: u16,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OracleAttestation {
+    pub event_id: String,
+    pub oracle_public_key: String,
+    pub signatures: Vec<String>,
+    pub outcomes: Vec<String>,
+}
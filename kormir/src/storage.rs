@@ -1,11 +1,19 @@
+use crate::audit::{AuditEntry, AuditRecord};
 use crate::error::Error;
 use bitcoin::secp256k1::schnorr::Signature;
-use dlc_messages::oracle_msgs::{OracleAnnouncement, OracleAttestation};
+use dlc_messages::oracle_msgs::{EventDescriptor, OracleAnnouncement, OracleAttestation};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, RwLock};
 
+/// Where the oracle keeps its events.
+///
+/// Only the methods to create, sign and read an event are required. The
+/// others have defaults so a storage can leave out what it does not support:
+/// listing, withdrawing and the audit log return [`Error::Unsupported`], and
+/// without the nostr bookkeeping the events are published once and never
+/// retried, rebroadcast nor deleted from the relays.
 pub trait Storage {
     /// Get the next `num` nonce indexes
     async fn get_next_nonce_indexes(&self, num: usize) -> Result<Vec<u32>, Error>;
@@ -27,6 +35,68 @@ pub trait Storage {
 
     /// Get the announcement data for the given id
     async fn get_event(&self, event_id: String) -> Result<Option<OracleEventData>, Error>;
+
+    /// Get the data of every event the oracle has announced
+    async fn list_events(&self) -> Result<Vec<OracleEventData>, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// Mark an event as withdrawn so it is never signed, returning its data
+    async fn withdraw_event(&self, _event_id: String) -> Result<OracleEventData, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// Append a record to the audit log, chained after the last entry
+    async fn append_audit_record(&self, _record: AuditRecord) -> Result<AuditEntry, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// List at most `limit` audit log entries, starting at the given sequence number
+    async fn list_audit_entries(
+        &self,
+        _from: u64,
+        _limit: usize,
+    ) -> Result<Vec<AuditEntry>, Error> {
+        Err(Error::Unsupported)
+    }
+
+    /// Save the id of the nostr event the announcement was published in
+    #[cfg(feature = "nostr")]
+    async fn add_announcement_event_id(
+        &self,
+        _event_id: String,
+        _nostr_event_id: nostr::EventId,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Save the id of the nostr event the attestation was published in
+    #[cfg(feature = "nostr")]
+    async fn add_attestation_event_id(
+        &self,
+        _event_id: String,
+        _nostr_event_id: nostr::EventId,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Add a nostr event to the outbox, replacing any entry for the same nostr event
+    #[cfg(feature = "nostr")]
+    async fn save_outbox_event(&self, _event: OutboxEvent) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// List all nostr events waiting in the outbox
+    #[cfg(feature = "nostr")]
+    async fn list_outbox_events(&self) -> Result<Vec<OutboxEvent>, Error> {
+        Ok(Vec::new())
+    }
+
+    /// Remove a nostr event from the outbox once it has been published
+    #[cfg(feature = "nostr")]
+    async fn remove_outbox_event(&self, _nostr_event_id: nostr::EventId) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 /// Data saved for an oracle announcement
@@ -36,6 +106,9 @@ pub struct OracleEventData {
     pub announcement: OracleAnnouncement,
     pub indexes: Vec<u32>,
     pub signatures: Vec<(String, Signature)>,
+    /// Whether the announcement was withdrawn by the oracle
+    #[serde(default)]
+    pub withdrawn: bool,
     #[cfg(feature = "nostr")]
     pub announcement_event_id: Option<String>,
     #[cfg(feature = "nostr")]
@@ -55,12 +128,85 @@ impl OracleEventData {
             })
         }
     }
+
+    /// Checks the event can be attested with the enum outcome.
+    pub fn check_enum_outcome(&self, outcome: &str) -> Result<(), Error> {
+        self.check_unsigned()?;
+        let descriptor = match &self.announcement.oracle_event.event_descriptor {
+            EventDescriptor::EnumEvent(desc) => desc,
+            _ => return Err(Error::invalid_outcome(&self.event_id, "not an enum event")),
+        };
+        if !descriptor.outcomes.iter().any(|o| o == outcome) {
+            return Err(Error::invalid_outcome(
+                &self.event_id,
+                format!("{outcome} is not one of the event outcomes"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks the event can be attested with the numeric outcome.
+    pub fn check_numeric_outcome(&self, outcome: i64) -> Result<(), Error> {
+        self.check_unsigned()?;
+        let descriptor = match &self.announcement.oracle_event.event_descriptor {
+            EventDescriptor::DigitDecompositionEvent(desc) => desc,
+            _ => {
+                return Err(Error::invalid_outcome(
+                    &self.event_id,
+                    "not a numeric event",
+                ))
+            }
+        };
+        if descriptor.base != 2 {
+            return Err(Error::Internal);
+        }
+        let max_value = (descriptor.base as i64).pow(descriptor.nb_digits as u32) - 1;
+        let min_value = if descriptor.is_signed { -max_value } else { 0 };
+        if outcome < min_value || outcome > max_value {
+            return Err(Error::invalid_outcome(
+                &self.event_id,
+                format!("{outcome} is outside of [{min_value}, {max_value}]"),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_unsigned(&self) -> Result<(), Error> {
+        if self.withdrawn {
+            return Err(Error::EventWithdrawn {
+                event_id: self.event_id.clone(),
+            });
+        }
+        if !self.signatures.is_empty() {
+            return Err(Error::EventAlreadySigned {
+                event_id: self.event_id.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A nostr event that has not been published to any relay yet
+#[cfg(feature = "nostr")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutboxEvent {
+    /// The oracle event the nostr event belongs to
+    pub event_id: String,
+    pub nostr_event: nostr::Event,
+    /// Number of failed publication attempts
+    pub attempts: u32,
+    /// Earliest time the publisher will retry the event
+    pub next_attempt: nostr::Timestamp,
+    pub last_error: Option<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct MemoryStorage {
     current_index: Arc<AtomicU32>,
     data: Arc<RwLock<HashMap<String, OracleEventData>>>,
+    audit_log: Arc<RwLock<Vec<AuditEntry>>>,
+    #[cfg(feature = "nostr")]
+    outbox: Arc<RwLock<HashMap<nostr::EventId, OutboxEvent>>>,
 }
 
 impl MemoryStorage {
@@ -68,16 +214,11 @@ impl MemoryStorage {
         Self {
             current_index: Arc::new(AtomicU32::new(0)),
             data: Arc::new(RwLock::new(HashMap::new())),
+            audit_log: Arc::new(RwLock::new(Vec::new())),
+            #[cfg(feature = "nostr")]
+            outbox: Arc::new(RwLock::new(HashMap::new())),
         }
     }
-
-    pub fn list_events(&self) -> Result<Vec<OracleEventData>, Error> {
-        let Ok(guard) = self.data.try_read() else {
-            return Err(Error::Internal);
-        };
-
-        Ok(guard.values().cloned().collect())
-    }
 }
 
 impl Default for MemoryStorage {
@@ -88,13 +229,8 @@ impl Default for MemoryStorage {
 
 impl Storage for MemoryStorage {
     async fn get_next_nonce_indexes(&self, num: usize) -> Result<Vec<u32>, Error> {
-        let mut current_index = self.current_index.fetch_add(num as u32, Ordering::Relaxed);
-        let mut indexes = Vec::with_capacity(num);
-        for _ in 0..num {
-            indexes.push(current_index);
-            current_index += 1;
-        }
-        Ok(indexes)
+        let current_index = self.current_index.fetch_add(num as u32, Ordering::Relaxed);
+        Ok((current_index..current_index + num as u32).collect())
     }
 
     async fn save_announcement(
@@ -108,6 +244,7 @@ impl Storage for MemoryStorage {
             announcement,
             indexes,
             signatures: Default::default(),
+            withdrawn: false,
             #[cfg(feature = "nostr")]
             announcement_event_id: None,
             #[cfg(feature = "nostr")]
@@ -127,11 +264,14 @@ impl Storage for MemoryStorage {
     ) -> Result<OracleEventData, Error> {
         let mut data = self.data.try_write().unwrap();
         let Some(mut event) = data.get(&id).cloned() else {
-            return Err(Error::NotFound);
+            return Err(Error::NotFound { event_id: id });
         };
 
+        if event.withdrawn {
+            return Err(Error::EventWithdrawn { event_id: id });
+        }
         if !event.signatures.is_empty() {
-            return Err(Error::EventAlreadySigned);
+            return Err(Error::EventAlreadySigned { event_id: id });
         }
 
         event.signatures = sigs;
@@ -144,4 +284,90 @@ impl Storage for MemoryStorage {
         let data = self.data.try_read().unwrap();
         Ok(data.get(&event_id).cloned())
     }
+
+    async fn list_events(&self) -> Result<Vec<OracleEventData>, Error> {
+        let Ok(guard) = self.data.try_read() else {
+            return Err(Error::Internal);
+        };
+
+        Ok(guard.values().cloned().collect())
+    }
+
+    async fn withdraw_event(&self, event_id: String) -> Result<OracleEventData, Error> {
+        let mut data = self.data.try_write().unwrap();
+        let Some(event) = data.get_mut(&event_id) else {
+            return Err(Error::NotFound { event_id });
+        };
+        if !event.signatures.is_empty() {
+            return Err(Error::EventAlreadySigned { event_id });
+        }
+        event.withdrawn = true;
+
+        Ok(event.clone())
+    }
+
+    async fn append_audit_record(&self, record: AuditRecord) -> Result<AuditEntry, Error> {
+        let mut audit_log = self.audit_log.try_write().unwrap();
+        let entry = AuditEntry::new(audit_log.last(), record);
+        audit_log.push(entry.clone());
+
+        Ok(entry)
+    }
+
+    async fn list_audit_entries(&self, from: u64, limit: usize) -> Result<Vec<AuditEntry>, Error> {
+        let audit_log = self.audit_log.try_read().unwrap();
+        let from = usize::try_from(from).unwrap_or(usize::MAX);
+
+        Ok(audit_log.iter().skip(from).take(limit).cloned().collect())
+    }
+
+    #[cfg(feature = "nostr")]
+    async fn add_announcement_event_id(
+        &self,
+        event_id: String,
+        nostr_event_id: nostr::EventId,
+    ) -> Result<(), Error> {
+        let mut data = self.data.try_write().unwrap();
+        let Some(event) = data.get_mut(&event_id) else {
+            return Err(Error::NotFound { event_id });
+        };
+        event.announcement_event_id = Some(nostr_event_id.to_hex());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "nostr")]
+    async fn add_attestation_event_id(
+        &self,
+        event_id: String,
+        nostr_event_id: nostr::EventId,
+    ) -> Result<(), Error> {
+        let mut data = self.data.try_write().unwrap();
+        let Some(event) = data.get_mut(&event_id) else {
+            return Err(Error::NotFound { event_id });
+        };
+        event.attestation_event_id = Some(nostr_event_id.to_hex());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "nostr")]
+    async fn save_outbox_event(&self, event: OutboxEvent) -> Result<(), Error> {
+        let mut outbox = self.outbox.try_write().unwrap();
+        outbox.insert(event.nostr_event.id, event);
+        Ok(())
+    }
+
+    #[cfg(feature = "nostr")]
+    async fn list_outbox_events(&self) -> Result<Vec<OutboxEvent>, Error> {
+        let outbox = self.outbox.try_read().unwrap();
+        Ok(outbox.values().cloned().collect())
+    }
+
+    #[cfg(feature = "nostr")]
+    async fn remove_outbox_event(&self, nostr_event_id: nostr::EventId) -> Result<(), Error> {
+        let mut outbox = self.outbox.try_write().unwrap();
+        outbox.remove(&nostr_event_id);
+        Ok(())
+    }
 }